@@ -1,6 +1,14 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+use chrono::NaiveTime;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use serde::Serialize;
 
+use crate::history::Aggregate;
+use crate::schedule::parse_time;
+use crate::scrobble::Api;
+use crate::sleep::{parse_seconds, Target};
+
 /// Music Player Daemon client written in Rust
 #[derive(Debug, Parser)]
 pub(crate) struct Cli {
@@ -9,19 +17,33 @@ pub(crate) struct Cli {
     /// Set output format
     #[clap(long, value_enum, default_value_t=OutputFormat::Json)]
     pub(crate) format: OutputFormat,
-    /// Set the ip address the mpd server is listening on
+    /// Set the ip address the mpd server is listening on (or the path to
+    /// its unix socket)
     #[clap(long, default_value = "127.0.0.1")]
     pub(crate) bind_to_address: Option<String>,
     /// Set the port the mpd server is listening on
     #[clap(long, default_value = "6600")]
     pub(crate) port: Option<String>,
+    /// Run the command against this server (address[:port] or socket
+    /// path) instead, repeat to run it against several at once
+    #[clap(long)]
+    pub(crate) host: Vec<String>,
+    /// Run the command against every server of a group from the config
+    #[clap(long)]
+    pub(crate) group: Option<String>,
 }
 
-#[derive(Debug, Subcommand)]
+#[derive(Clone, Debug, Subcommand)]
 pub(crate) enum Commands {
-    /// Add a song to the queue at the given path (or dir '/')
-    #[command()]
-    Add { path: Option<String> },
+    /// Add songs or playlist files (m3u, pls, xspf, cue) at the given path
+    /// (or dir '/'), or a stream URL, to the queue
+    #[command()]
+    Add {
+        path: Option<String>,
+        /// Walk the path on this machine instead of asking the server
+        #[clap(long)]
+        local: bool,
+    },
     /// Remove all but the current song from the queue
     #[command()]
     Crop,
@@ -85,6 +107,40 @@ pub(crate) enum Commands {
     /// Shuffle the queue
     #[command()]
     Shuffle,
+    /// Restore the queue from before a clear, crop, shuffle, del or load
+    #[command()]
+    Undo {
+        /// How many changes to go back, 1 being the latest
+        #[clap(default_value_t = 1)]
+        number: usize,
+        /// List the saved queues instead
+        #[clap(long, conflicts_with = "number")]
+        list: bool,
+    },
+    /// Save or restore the queue, playback, modes, volume and outputs
+    #[command()]
+    Session {
+        #[clap(subcommand)]
+        command: SessionCommand,
+    },
+    /// Remove repeated songs from the queue
+    #[command()]
+    Dedupe {
+        /// What makes two songs the same
+        #[clap(long, value_enum, default_value_t = DedupeBy::File)]
+        by: DedupeBy,
+        /// Which of the repeated songs stays
+        #[clap(long, value_enum, default_value_t = Keep::First)]
+        keep: Keep,
+    },
+    /// Order the queue by tags (eg. albumartist,date,album,disc,track)
+    #[command()]
+    SortQueue {
+        #[clap(value_delimiter = ',', required = true)]
+        tags: Vec<String>,
+        #[clap(long)]
+        reverse: bool,
+    },
     /// List all of the playlists
     #[command()]
     Lsplaylists,
@@ -103,6 +159,21 @@ pub(crate) enum Commands {
     /// List songs in a playlist
     #[command()]
     Playlist { name: Option<String> },
+    /// Write a playlist (or the queue) out as a playlist file
+    #[command()]
+    Export {
+        name: Option<String>,
+        #[clap(long, value_enum, default_value_t = ExportFormat::M3u8)]
+        format: ExportFormat,
+    },
+    /// Create a playlist from a m3u, m3u8, pls, xspf or json file
+    #[command()]
+    Import {
+        file: String,
+        /// Name of the playlist (defaults to the file name)
+        #[clap(long = "as")]
+        name: Option<String>,
+    },
     /// List all songs in the music directory
     #[command()]
     Listall { file: Option<String> },
@@ -127,6 +198,25 @@ pub(crate) enum Commands {
     /// Search for song(s) by type/query and add to queue
     #[command()]
     SearchAdd { tag: Tag, query: String },
+    /// Search the library forgiving typos and accents (or by regex)
+    #[command()]
+    Fuzzy {
+        query: String,
+        #[clap(long, value_enum, default_value_t = FuzzyTag::Any)]
+        tag: FuzzyTag,
+        /// Number of hits to list
+        #[clap(long, default_value_t = 20)]
+        limit: usize,
+        /// Treat the query as a regular expression
+        #[clap(long)]
+        regex: bool,
+        /// Add the hits to the queue
+        #[clap(long, conflicts_with = "insert")]
+        add: bool,
+        /// Insert the hits after the current song
+        #[clap(long)]
+        insert: bool,
+    },
     /// Find (exact match) song(s) by type/query
     #[command()]
     Find { tag: Tag, query: String },
@@ -146,9 +236,24 @@ pub(crate) enum Commands {
     /// Remove a playlist
     #[command()]
     Rm { name: String },
-    /// Set the volume to specified value <num> or increase/decrease it [+-]<num>
+    /// Print the volume, set it to <num> or increase/decrease it [+-]<num>
+    #[command()]
+    Volume {
+        #[clap(allow_hyphen_values = true)]
+        volume: Option<String>,
+        /// Move to the new volume gradually over this long (eg. 5s)
+        #[clap(long, requires = "volume", value_parser = parse_seconds)]
+        over: Option<u64>,
+    },
+    /// Silence the output, remembering the volume
     #[command()]
-    Volume { volume: String },
+    Mute,
+    /// Restore the volume from before `mute`
+    #[command()]
+    Unmute,
+    /// Mute or unmute
+    #[command()]
+    Togglemute,
     /// Provide mpd statistics
     #[command()]
     Stats,
@@ -158,6 +263,280 @@ pub(crate) enum Commands {
     /// Get the current status of the player
     #[command()]
     Status,
+    /// Browse the queue, library and playlists in a full-screen interface
+    #[command()]
+    Tui,
+    /// Expose the player over MPRIS2 on the D-Bus session bus
+    #[command()]
+    Mpris,
+    /// Submit listens to ListenBrainz or a Last.fm compatible service
+    #[command()]
+    Scrobble {
+        /// API spoken by the service (overrides the config file)
+        #[clap(long, value_enum)]
+        api: Option<Api>,
+        /// Base URL of the service (overrides the config file)
+        #[clap(long)]
+        endpoint: Option<String>,
+        /// ListenBrainz user token (overrides the config file)
+        #[clap(long)]
+        token: Option<String>,
+    },
+    /// Pause after a while or stop at the end of the song, album or queue
+    #[command()]
+    Sleep {
+        /// How long to wait (eg. 30m, 1h30m) or end-of-song, end-of-album
+        /// or end-of-queue
+        target: Target,
+        /// Fade the volume out over this long beforehand (eg. 2m)
+        #[clap(long, value_parser = parse_seconds)]
+        fade: Option<u64>,
+        /// Run in the background
+        #[clap(long)]
+        detach: bool,
+    },
+    /// Run the `[[schedule]]` jobs of the config file at their times
+    #[command()]
+    Scheduler,
+    /// Start playback at the given time, replacing the queue with a playlist
+    #[command()]
+    Alarm {
+        /// Time of day to start playing (eg. 06:45)
+        #[clap(value_parser = parse_time)]
+        time: NaiveTime,
+        /// Stored playlist to replace the queue with
+        playlist: Option<String>,
+        /// Volume to play at
+        #[clap(long)]
+        volume: Option<u8>,
+        /// Fade the volume in from silence over this long (eg. 5m)
+        #[clap(long, value_parser = parse_seconds)]
+        ramp: Option<u64>,
+    },
+    /// Show, or record with `history record`, the local listening history
+    #[command()]
+    History(HistoryArgs),
+    /// Queue a random album in track order
+    #[command()]
+    RandomAlbum {
+        /// Only pick among albums with songs matching this MPD filter
+        filter: Option<String>,
+        /// Clear the queue first
+        #[clap(long)]
+        replace: bool,
+        /// Start playing the picked songs
+        #[clap(long)]
+        play: bool,
+    },
+    /// Queue the songs of a random album artist
+    #[command()]
+    RandomArtist {
+        /// Only pick among artists with songs matching this MPD filter
+        filter: Option<String>,
+        /// Clear the queue first
+        #[clap(long)]
+        replace: bool,
+        /// Start playing the picked songs
+        #[clap(long)]
+        play: bool,
+    },
+    /// Queue random songs
+    #[command()]
+    RandomSongs {
+        count: usize,
+        /// Only pick songs matching this MPD filter
+        filter: Option<String>,
+        /// Clear the queue first
+        #[clap(long)]
+        replace: bool,
+        /// Start playing the picked songs
+        #[clap(long)]
+        play: bool,
+    },
+    /// Keep the queue topped up with random songs or albums
+    #[command()]
+    Autodj {
+        /// Songs to keep queued after the current one
+        #[clap(long, default_value_t = 5)]
+        min: usize,
+        /// Only pick songs matching this MPD filter (eg. '(genre == "Jazz")')
+        #[clap(long)]
+        filter: Option<String>,
+        /// Queue whole random albums instead of single songs
+        #[clap(long)]
+        albums: bool,
+        /// Don't repeat a song within this many songs
+        #[clap(long, default_value_t = 100)]
+        track_window: usize,
+        /// Don't repeat an artist within this many songs
+        #[clap(long, default_value_t = 10)]
+        artist_window: usize,
+        /// Remove played songs, keeping this many above the current one
+        #[clap(long)]
+        keep: Option<usize>,
+    },
+    /// List, show, queue or store the smart playlists of the config file
+    #[command()]
+    Smart {
+        #[clap(subcommand)]
+        command: SmartCommand,
+    },
+    /// Move playback to another server: the queue, position, modes and
+    /// volume
+    #[command()]
+    Handoff {
+        /// Server to play on (address[:port] or socket path), or a group
+        /// from the config to play on all of its servers
+        #[clap(long)]
+        to: String,
+        /// Stop the player here once the other server plays
+        #[clap(long)]
+        stop_source: bool,
+    },
+    /// Keep another server playing what a server plays: the queue, the
+    /// current song and position, and play/pause/stop
+    #[command()]
+    Follow {
+        /// Server to follow (address[:port] or socket path)
+        source: String,
+        /// Server to keep in line with it
+        #[clap(long)]
+        to: String,
+        /// Seconds the elapsed times may be apart before seeking
+        #[clap(long, default_value_t = 2.0)]
+        drift: f64,
+    },
+    /// Print the lyrics of a song (defaults to the current one)
+    #[command()]
+    Lyrics {
+        uri: Option<String>,
+        /// Only synced (LRC) lyrics
+        #[clap(long)]
+        synced: bool,
+        /// Print the line being sung of the current song whenever it
+        /// changes
+        #[clap(long, requires = "synced")]
+        follow: bool,
+        /// Directory of `Artist - Title.lrc` (or `.txt`) files to look in
+        #[clap(long)]
+        directory: Option<PathBuf>,
+    },
+    /// Run the commands of the config's `[hooks]` section on song, state,
+    /// volume, output and database changes and at the end of the queue
+    #[command()]
+    Hooks,
+    /// Manage the on-disk copy of the library used by library-wide commands
+    #[command()]
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+impl Commands {
+    /// Whether the command keeps running, takes over the terminal, starts
+    /// itself again in the background or moves playback between servers,
+    /// and so can't be run against several servers at once
+    pub(crate) fn runs_alone(&self) -> bool {
+        matches!(
+            self,
+            Commands::Tui
+                | Commands::Mpris
+                | Commands::Scrobble { .. }
+                | Commands::Scheduler
+                | Commands::Autodj { .. }
+                | Commands::Handoff { .. }
+                | Commands::Follow { .. }
+                | Commands::Hooks
+                | Commands::Sleep { detach: true, .. }
+                | Commands::Lyrics { follow: true, .. }
+                | Commands::History(HistoryArgs {
+                    command: Some(HistoryCommand::Record),
+                    ..
+                })
+        )
+    }
+}
+
+#[derive(Args, Clone, Debug)]
+pub(crate) struct HistoryArgs {
+    #[clap(subcommand)]
+    pub(crate) command: Option<HistoryCommand>,
+    /// Only plays since this time (eg. today, 7d, 4w, 2024-01-01)
+    #[clap(long)]
+    pub(crate) since: Option<String>,
+    /// Only plays before this time
+    #[clap(long)]
+    pub(crate) until: Option<String>,
+    /// Only plays by this artist
+    #[clap(long)]
+    pub(crate) artist: Option<String>,
+    /// Only skipped plays
+    #[clap(long)]
+    pub(crate) skipped: bool,
+    /// Most played artists, albums or tracks instead of single plays
+    #[clap(long, value_enum)]
+    pub(crate) top: Option<Aggregate>,
+    /// Maximum number of rows (defaults to 10 with --top)
+    #[clap(long)]
+    pub(crate) limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub(crate) enum HistoryCommand {
+    /// Record every play to the history while running
+    #[command()]
+    Record,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub(crate) enum SessionCommand {
+    /// Save the state of the player under a name
+    #[command()]
+    Save { name: String },
+    /// Bring back a saved state (`undo` brings back the queue it replaced)
+    #[command()]
+    Restore { name: String },
+    /// List the saved sessions
+    #[command()]
+    List,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub(crate) enum SmartCommand {
+    /// List the smart playlists and their rules
+    #[command()]
+    List,
+    /// List the songs of a smart playlist
+    #[command()]
+    Show { name: String },
+    /// Add the songs of a smart playlist to the queue
+    #[command()]
+    Load { name: String },
+    /// Store the songs of a smart playlist as a playlist
+    #[command()]
+    SaveAs {
+        name: String,
+        /// Name of the stored playlist (defaults to the smart playlist's)
+        playlist: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub(crate) enum CacheCommand {
+    /// Bring the cache up to date with the server's database
+    #[command()]
+    Refresh {
+        /// Download the whole library instead of only what changed
+        #[clap(long)]
+        full: bool,
+    },
+    /// Delete the cache
+    #[command()]
+    Clear,
+    /// Show where the cache is and whether it is up to date
+    #[command()]
+    Status,
 }
 
 #[derive(Clone, Debug, ValueEnum)]
@@ -177,6 +556,82 @@ impl OutputFormat {
     }
 }
 
+#[derive(Clone, Debug, ValueEnum)]
+pub enum DedupeBy {
+    File,
+    #[value(name = "artist,title")]
+    ArtistTitle,
+    #[value(name = "musicbrainz_trackid")]
+    MusicbrainzTrackid,
+}
+
+impl DedupeBy {
+    pub fn to(&self) -> mpd_easy::DedupeBy {
+        match self {
+            DedupeBy::File => mpd_easy::DedupeBy::File,
+            DedupeBy::ArtistTitle => mpd_easy::DedupeBy::ArtistTitle,
+            DedupeBy::MusicbrainzTrackid => {
+                mpd_easy::DedupeBy::MusicbrainzTrackid
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum Keep {
+    First,
+    Last,
+}
+
+impl Keep {
+    pub fn to(&self) -> mpd_easy::Keep {
+        match self {
+            Keep::First => mpd_easy::Keep::First,
+            Keep::Last => mpd_easy::Keep::Last,
+        }
+    }
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum FuzzyTag {
+    Artist,
+    Album,
+    Title,
+    Any,
+}
+
+impl FuzzyTag {
+    pub fn to(&self) -> mpd_easy::Field {
+        match self {
+            FuzzyTag::Artist => mpd_easy::Field::Artist,
+            FuzzyTag::Album => mpd_easy::Field::Album,
+            FuzzyTag::Title => mpd_easy::Field::Title,
+            FuzzyTag::Any => mpd_easy::Field::Any,
+        }
+    }
+}
+
+#[derive(Clone, Debug, ValueEnum)]
+pub enum ExportFormat {
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn to(&self) -> mpd_easy::ExportFormat {
+        match self {
+            ExportFormat::M3u => mpd_easy::ExportFormat::M3u,
+            ExportFormat::M3u8 => mpd_easy::ExportFormat::M3u8,
+            ExportFormat::Pls => mpd_easy::ExportFormat::Pls,
+            ExportFormat::Xspf => mpd_easy::ExportFormat::Xspf,
+            ExportFormat::Json => mpd_easy::ExportFormat::Json,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, ValueEnum)]
 pub enum OnOff {
     On,
@@ -184,6 +639,7 @@ pub enum OnOff {
 }
 
 impl OnOff {
+    #[allow(clippy::ref_option)]
     pub fn to(value: &Option<OnOff>) -> Option<mpd_easy::OnOff> {
         match value {
             Some(OnOff::On) => Some(mpd_easy::OnOff::On),
@@ -0,0 +1,315 @@
+//! Keeps the queue topped up with random songs or whole albums, like
+//! ashuffle, without repeating recently played songs and artists.
+use std::collections::{BTreeMap, VecDeque};
+
+use eyre::eyre;
+use mpd_easy::{random, track_order, Client, Event, Song};
+
+use crate::history::{Filter, Store};
+
+pub(crate) struct Options {
+    /// Songs to keep queued after the current one
+    pub(crate) min: usize,
+    /// MPD filter expression the picked songs have to match
+    pub(crate) filter: Option<String>,
+    pub(crate) albums: bool,
+    pub(crate) track_window: usize,
+    pub(crate) artist_window: usize,
+    /// Played songs to leave above the current one, all when `None`
+    pub(crate) keep: Option<usize>,
+}
+
+/// Files and artists of the latest songs, oldest first
+struct Recent {
+    songs: VecDeque<(String, String)>,
+    track_window: usize,
+    artist_window: usize,
+}
+
+impl Recent {
+    fn new(track_window: usize, artist_window: usize) -> Self {
+        Self {
+            songs: VecDeque::new(),
+            track_window,
+            artist_window,
+        }
+    }
+
+    fn remember(&mut self, file: &str, artist: &str) {
+        self.songs
+            .push_back((file.to_string(), artist.to_lowercase()));
+
+        while self.songs.len() > self.track_window.max(self.artist_window) {
+            self.songs.pop_front();
+        }
+    }
+
+    fn latest(&self, window: usize) -> impl Iterator<Item = &(String, String)> {
+        self.songs.iter().rev().take(window)
+    }
+
+    fn played(&self, song: &Song) -> bool {
+        self.latest(self.track_window)
+            .any(|(file, _)| *file == song.file)
+    }
+
+    fn artist_played(&self, song: &Song) -> bool {
+        let artist = artist(song).to_lowercase();
+
+        !artist.is_empty()
+            && self
+                .latest(self.artist_window)
+                .any(|(_, played)| *played == artist)
+    }
+}
+
+fn tag<'a>(song: &'a Song, name: &str) -> Option<&'a str> {
+    song.tags
+        .iter()
+        .find(|(tag, _)| tag.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn artist(song: &Song) -> &str {
+    tag(song, "AlbumArtist")
+        .or(song.artist.as_deref())
+        .unwrap_or_default()
+}
+
+/// A random song, preferably neither it nor its artist played recently
+fn pick_song(candidates: &[Song], recent: &Recent) -> Vec<Song> {
+    let fresh: Vec<&Song> = candidates
+        .iter()
+        .filter(|song| !recent.played(song) && !recent.artist_played(song))
+        .collect();
+    let unplayed: Vec<&Song> = candidates
+        .iter()
+        .filter(|song| !recent.played(song))
+        .collect();
+    let pool: Vec<&Song> = if !fresh.is_empty() {
+        fresh
+    } else if !unplayed.is_empty() {
+        unplayed
+    } else {
+        candidates.iter().collect()
+    };
+
+    vec![pool[random::below(pool.len())].clone()]
+}
+
+/// The songs of a random album in track order, preferably an album whose
+/// artist and songs weren't played recently
+fn pick_album(candidates: &[Song], recent: &Recent) -> Vec<Song> {
+    let mut albums: BTreeMap<(&str, &str), Vec<&Song>> = BTreeMap::new();
+    for song in candidates {
+        if let Some(album) = tag(song, "Album") {
+            albums.entry((artist(song), album)).or_default().push(song);
+        }
+    }
+
+    let albums: Vec<Vec<&Song>> = albums.into_values().collect();
+    if albums.is_empty() {
+        return pick_song(candidates, recent);
+    }
+    let fresh: Vec<&Vec<&Song>> = albums
+        .iter()
+        .filter(|songs| {
+            songs
+                .iter()
+                .all(|song| !recent.played(song) && !recent.artist_played(song))
+        })
+        .collect();
+    let pool: Vec<&Vec<&Song>> = if fresh.is_empty() {
+        albums.iter().collect()
+    } else {
+        fresh
+    };
+
+    let mut songs: Vec<Song> = pool[random::below(pool.len())]
+        .iter()
+        .map(|song| (*song).clone())
+        .collect();
+    songs.sort_by(track_order);
+
+    songs
+}
+
+/// Removes played songs above the current one beyond the `keep` latest
+fn trim(mpd: &mut Client, keep: usize) -> eyre::Result<()> {
+    let status = mpd.status()?;
+
+    if status.file_path.is_some() && status.position as usize > keep {
+        let keep = u32::try_from(keep)?;
+        mpd.delete(0..status.position - keep)?;
+    }
+
+    Ok(())
+}
+
+/// Appends picks until `options.min` songs follow the current one and
+/// returns them
+fn top_up(
+    mpd: &mut Client,
+    options: &Options,
+    recent: &mut Recent,
+) -> eyre::Result<Vec<Song>> {
+    let status = mpd.status()?;
+    let after = match status.file_path {
+        Some(_) => status.queue_count.saturating_sub(status.position + 1),
+        None => status.queue_count,
+    } as usize;
+    if after >= options.min {
+        return Ok(vec![]);
+    }
+
+    let candidates = mpd.filter(options.filter.as_deref())?;
+    if candidates.is_empty() {
+        return Err(eyre!("no songs to pick from"));
+    }
+
+    let mut added = Vec::new();
+    while after + added.len() < options.min {
+        let picked = if options.albums {
+            pick_album(&candidates, recent)
+        } else {
+            pick_song(&candidates, recent)
+        };
+        for song in &picked {
+            recent.remember(&song.file, artist(song));
+        }
+
+        mpd.enqueue(&picked)?;
+        added.extend(picked);
+    }
+
+    Ok(added)
+}
+
+/// Tops the queue up whenever it or the player changes, until interrupted
+pub(crate) fn run(
+    mpd: &mut Client,
+    options: &Options,
+) -> eyre::Result<Option<String>> {
+    let mut recent = Recent::new(options.track_window, options.artist_window);
+    for entry in Store::default().entries(&Filter::default())? {
+        recent.remember(&entry.file, &entry.artist);
+    }
+    for song in mpd.songs(None)? {
+        recent.remember(&song.file, artist(&song));
+    }
+
+    loop {
+        if let Some(keep) = options.keep {
+            trim(mpd, keep)?;
+        }
+        for song in top_up(mpd, options, &mut recent)? {
+            println!("queued: {}", song.file);
+        }
+
+        mpd.wait(&[Event::Playlist, Event::Player])?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+
+    const LIBRARY: &str = concat!(
+        "file: a.flac\nArtist: X\nAlbum: One\nTrack: 1\n",
+        "file: b2.flac\nArtist: Y\nAlbum: Two\nTrack: 2/2\n",
+        "file: b1.flac\nArtist: Y\nAlbum: Two\nTrack: 1/2\n",
+        "file: c.flac\nArtist: Z\nAlbum: Three\nTrack: 1\n",
+    );
+
+    fn server() -> FakeServer {
+        FakeServer::start(|line| {
+            let body = match line {
+                "status" => {
+                    "state: play\nsong: 3\nsongid: 7\nplaylistlength: 4\n"
+                }
+                "currentsong" => "file: a.flac\nArtist: X\nPos: 3\nId: 7\n",
+                "listallinfo" => LIBRARY,
+                _ if line.starts_with("addid") => "Id: 9\n",
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        })
+    }
+
+    fn options(albums: bool) -> Options {
+        Options {
+            min: 2,
+            filter: None,
+            albums,
+            track_window: 10,
+            artist_window: 10,
+            keep: None,
+        }
+    }
+
+    #[test]
+    fn test_recent_windows() {
+        let song = |file: &str, artist: &str| Song {
+            file: file.to_string(),
+            artist: Some(artist.to_string()),
+            ..Default::default()
+        };
+        let mut recent = Recent::new(2, 1);
+        recent.remember("a.flac", "X");
+        recent.remember("b.flac", "Y");
+
+        assert!(recent.played(&song("a.flac", "")));
+        assert!(!recent.artist_played(&song("c.flac", "x")));
+        assert!(recent.artist_played(&song("c.flac", "y")));
+
+        recent.remember("c.flac", "Z");
+        assert!(!recent.played(&song("a.flac", "")));
+    }
+
+    #[test]
+    fn test_top_up_avoids_recent_artists() {
+        let server = server();
+        let mut mpd = server.client();
+        let mut recent = Recent::new(10, 10);
+        recent.remember("a.flac", "X");
+        recent.remember("b1.flac", "Y");
+
+        let mut added: Vec<String> =
+            top_up(&mut mpd, &options(false), &mut recent)
+                .unwrap()
+                .into_iter()
+                .map(|song| song.file)
+                .collect();
+        added.sort();
+
+        // only c is fresh, then b2 is the only song not played
+        assert_eq!(added, ["b2.flac", "c.flac"]);
+        assert_eq!(
+            server
+                .commands()
+                .iter()
+                .filter(|line| line.starts_with("addid"))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_top_up_queues_albums_in_track_order() {
+        let server = server();
+        let mut mpd = server.client();
+        let mut recent = Recent::new(10, 10);
+        recent.remember("a.flac", "X");
+        recent.remember("c.flac", "Z");
+
+        let added: Vec<String> = top_up(&mut mpd, &options(true), &mut recent)
+            .unwrap()
+            .into_iter()
+            .map(|song| song.file)
+            .collect();
+
+        assert_eq!(added, ["b1.flac", "b2.flac"]);
+    }
+}
@@ -0,0 +1,96 @@
+//! Settings read from `$XDG_CONFIG_HOME/mp-cli/config.toml`.
+//!
+//! Every section is optional; command line flags take precedence over the
+//! values found here.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use eyre::WrapErr;
+use mpd_easy::{dirs, FinderOptions};
+use serde::Deserialize;
+
+use crate::{hooks, lyrics, schedule, scrobble, smart};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    pub(crate) add: FinderOptions,
+    pub(crate) scrobble: scrobble::Settings,
+    pub(crate) schedule: Vec<schedule::Job>,
+    pub(crate) smart: BTreeMap<String, smart::Definition>,
+    pub(crate) lyrics: lyrics::Settings,
+    pub(crate) hooks: hooks::Hooks,
+    /// Servers `--group` runs commands against, by group name
+    pub(crate) groups: BTreeMap<String, Vec<String>>,
+}
+
+impl Config {
+    pub(crate) fn load() -> eyre::Result<Self> {
+        Self::from_path(&dirs::config_dir().join("config.toml"))
+    }
+
+    pub(crate) fn from_path(path: &Path) -> eyre::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(path)
+            .wrap_err(format!("unable to read config: {}", path.display()))?;
+
+        Self::parse(&contents)
+            .wrap_err(format!("invalid config: {}", path.display()))
+    }
+
+    pub(crate) fn parse(contents: &str) -> eyre::Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrobble::Api;
+
+    #[test]
+    fn test_parse_scrobble_section() {
+        let config = Config::parse(
+            r#"
+            [scrobble]
+            api = "lastfm"
+            endpoint = "http://localhost:8080/2.0/"
+            api_key = "key"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.scrobble.api, Api::Lastfm);
+        assert_eq!(
+            config.scrobble.endpoint.as_deref(),
+            Some("http://localhost:8080/2.0/")
+        );
+        assert_eq!(config.scrobble.api_key.as_deref(), Some("key"));
+    }
+
+    #[test]
+    fn test_parse_add_section() {
+        let config = Config::parse(
+            "[add]\nextensions = [\"flac\", \"wv\"]\nhidden = true",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.add.extensions.unwrap(),
+            vec!["flac".to_string(), "wv".to_string()]
+        );
+        assert!(config.add.hidden);
+        assert!(Config::parse("[add]\nextension = []").is_err());
+    }
+
+    #[test]
+    fn test_missing_config_is_default() {
+        let config = Config::from_path(Path::new("/nonexistent/config.toml"));
+
+        assert!(config.unwrap().scrobble.token.is_none());
+    }
+}
@@ -0,0 +1,182 @@
+//! Running one command against several servers at once, eg. pausing every
+//! room before a phone call.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::thread;
+
+use eyre::eyre;
+use serde_json::{json, Map, Value};
+
+/// The servers given with `--host` and `--group`, in order and without
+/// repeats
+pub(crate) fn hosts(
+    hosts: &[String],
+    group: Option<&str>,
+    groups: &BTreeMap<String, Vec<String>>,
+) -> eyre::Result<Vec<String>> {
+    let mut all = hosts.to_vec();
+
+    if let Some(group) = group {
+        let members = groups
+            .get(group)
+            .ok_or_else(|| eyre!("no group named {group} in the config"))?;
+        all.extend(members.iter().cloned());
+    }
+
+    let mut unique = Vec::new();
+    for host in all {
+        if !unique.contains(&host) {
+            unique.push(host);
+        }
+    }
+
+    Ok(unique)
+}
+
+/// The address and port of `host`, `port` being used when it names none
+pub(crate) fn address(host: &str, port: &str) -> (String, String) {
+    if host.starts_with('/') {
+        return (host.to_string(), port.to_string());
+    }
+
+    match host.rsplit_once(':') {
+        Some((address, port))
+            if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            (address.to_string(), port.to_string())
+        }
+        _ => (host.to_string(), port.to_string()),
+    }
+}
+
+/// Runs `command` against every host at once, the results being in the
+/// order of the hosts
+pub(crate) fn run<F>(
+    hosts: &[String],
+    command: F,
+) -> Vec<(String, eyre::Result<Option<String>>)>
+where
+    F: Fn(&str) -> eyre::Result<Option<String>> + Sync,
+{
+    thread::scope(|scope| {
+        let handles: Vec<_> = hosts
+            .iter()
+            .map(|host| (host, scope.spawn(|| command(host))))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|(host, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err(eyre!("{host} panicked")));
+                (host.clone(), result)
+            })
+            .collect()
+    })
+}
+
+/// One JSON object keyed by host, or a block of text per host
+pub(crate) fn render(
+    results: &[(String, eyre::Result<Option<String>>)],
+    json: bool,
+) -> String {
+    if json {
+        let mut object = Map::new();
+        for (host, result) in results {
+            let value = match result {
+                Ok(Some(output)) => serde_json::from_str(output)
+                    .unwrap_or_else(|_| Value::String(output.clone())),
+                Ok(None) => Value::Null,
+                Err(e) => json!({ "error": e.to_string() }),
+            };
+            object.insert(host.clone(), value);
+        }
+
+        return serde_json::to_string_pretty(&object).unwrap_or_default();
+    }
+
+    let mut text = String::new();
+    for (host, result) in results {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        let _ = writeln!(text, "{host}:");
+        match result {
+            Ok(Some(output)) => {
+                let _ = writeln!(text, "{output}");
+            }
+            Ok(None) => (),
+            Err(e) => {
+                let _ = writeln!(text, "error: {e}");
+            }
+        }
+    }
+
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+    use mpd_easy::Client;
+
+    #[test]
+    fn test_hosts_adds_the_group() {
+        let groups = BTreeMap::from([(
+            "rooms".to_string(),
+            vec!["kitchen".to_string(), "living:6601".to_string()],
+        )]);
+        let given = ["kitchen".to_string()];
+
+        assert_eq!(
+            hosts(&given, Some("rooms"), &groups).unwrap(),
+            ["kitchen", "living:6601"]
+        );
+        assert!(hosts(&given, Some("garden"), &groups).is_err());
+    }
+
+    #[test]
+    fn test_address() {
+        let address = |host| address(host, "6600");
+
+        assert_eq!(address("kitchen"), ("kitchen".into(), "6600".into()));
+        assert_eq!(address("kitchen:6601"), ("kitchen".into(), "6601".into()));
+        assert_eq!(
+            address("/run/mpd/socket"),
+            ("/run/mpd/socket".into(), "6600".into())
+        );
+    }
+
+    #[test]
+    fn test_errors_do_not_stop_the_other_hosts() {
+        let handler = |line: &str| match line {
+            "status" => Ok("state: pause\n".to_string()),
+            _ => Ok(String::new()),
+        };
+        let first = FakeServer::start(handler);
+        let second = FakeServer::start(handler);
+        let hosts = vec![
+            format!("{}:{}", first.address, first.port),
+            "127.0.0.1:1".to_string(),
+            format!("{}:{}", second.address, second.port),
+        ];
+
+        let results = run(&hosts, |host| {
+            let (address, port) = address(host, "6600");
+            Client::new(&address, &port, None)?.pause()
+        });
+
+        assert!(results[0].1.is_ok());
+        assert!(results[1].1.is_err());
+        assert!(results[2].1.is_ok());
+        assert!(first.commands().contains(&r#"pause "1""#.to_string()));
+        assert!(second.commands().contains(&r#"pause "1""#.to_string()));
+
+        let rendered: Value =
+            serde_json::from_str(&render(&results, true)).unwrap();
+        assert!(rendered["127.0.0.1:1"]["error"].is_string());
+        assert!(rendered[&hosts[2]].is_object());
+    }
+}
@@ -0,0 +1,114 @@
+//! Keeps one server playing whatever another plays, eg. a backyard speaker
+//! following the living room.
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use eyre::eyre;
+use mpd_easy::{Client, Event};
+
+use crate::fanout;
+
+/// How often the servers are compared when nothing happens on the source,
+/// so that drift gets corrected
+const RECHECK: Duration = Duration::from_secs(5);
+
+/// Brings `target` in line with `source`, printing the commands sent
+fn sync(
+    source: &mut Client,
+    target: &mut Client,
+    drift: f64,
+) -> eyre::Result<()> {
+    for line in target.mirror(&source.session()?, drift)? {
+        println!("{line}");
+    }
+
+    Ok(())
+}
+
+/// Mirrors `source` on `to` whenever the queue or the player changes
+/// there, until interrupted
+pub(crate) fn run(
+    source: &str,
+    to: &str,
+    port: &str,
+    drift: f64,
+) -> eyre::Result<Option<String>> {
+    let connect = |host: &str| {
+        let (address, port) = fanout::address(host, port);
+        Client::new(&address, &port, None)
+    };
+    let mut watched = connect(source)?;
+    let mut followed = connect(source)?;
+    let mut target = connect(to)?;
+
+    let (sender, events) = mpsc::channel();
+    thread::spawn(move || loop {
+        let result = watched.wait(&[Event::Playlist, Event::Player]);
+        let failed = result.is_err();
+        if sender.send(result).is_err() || failed {
+            break;
+        }
+    });
+
+    loop {
+        sync(&mut followed, &mut target, drift)?;
+
+        match events.recv_timeout(RECHECK) {
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => (),
+            Ok(Err(e)) => return Err(e),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(eyre!("stopped watching {source}"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+
+    #[test]
+    fn test_sync_copies_the_queue_and_position() {
+        let source = FakeServer::start(|line| {
+            let body = match line {
+                "status" => concat!(
+                    "state: play\nsong: 1\nsongid: 2\nplaylistlength: 2\n",
+                    "elapsed: 10.000\n",
+                ),
+                "playlistinfo" => concat!(
+                    "file: a.flac\nPos: 0\nId: 1\n",
+                    "file: b.flac\nPos: 1\nId: 2\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let target = FakeServer::start(|line| {
+            let body = match line {
+                "status" => "state: stop\nplaylistlength: 1\n",
+                "playlistinfo" => "file: b.flac\nPos: 0\nId: 1\n",
+                "listall" => "file: a.flac\n",
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+
+        sync(&mut source.client(), &mut target.client(), 2.0).unwrap();
+
+        let commands = target.commands();
+        let clear = commands.iter().position(|line| line == "clear").unwrap();
+        assert_eq!(
+            commands[clear..clear + 4],
+            ["clear", r#"add "a.flac""#, r#"add "b.flac""#, "seek 1 10"]
+        );
+        // a single listing tells what the target's library holds
+        assert_eq!(
+            commands.iter().filter(|line| *line == "listall").count(),
+            1
+        );
+    }
+}
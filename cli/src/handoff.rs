@@ -0,0 +1,130 @@
+//! Moving playback to another server, eg. following along from the office
+//! to the kitchen.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use mpd_easy::{Client, Handoff};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::fanout;
+
+#[derive(Debug, Serialize)]
+struct Handoffs(Vec<Handoff>);
+
+impl fmt::Display for Handoffs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let handoffs: Vec<String> =
+            self.0.iter().map(ToString::to_string).collect();
+
+        write!(f, "{}", handoffs.join("\n"))
+    }
+}
+
+/// The servers `to` names: those of the config group, or `to` itself
+fn targets(to: &str, groups: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    groups
+        .get(to)
+        .cloned()
+        .unwrap_or_else(|| vec![to.to_string()])
+}
+
+/// Has every target play what `mpd` plays, stopping `mpd` afterwards if
+/// `stop_source`
+fn hand_off(
+    mpd: &mut Client,
+    targets: Vec<Client>,
+    stop_source: bool,
+) -> eyre::Result<Option<String>> {
+    let session = mpd.session()?;
+
+    let mut handoffs = Vec::new();
+    for mut target in targets {
+        handoffs.push(target.take_over(&session)?);
+    }
+    if stop_source {
+        mpd.stop()?;
+    }
+
+    mpd.render(&Handoffs(handoffs))
+}
+
+pub(crate) fn run(
+    mpd: &mut Client,
+    to: &str,
+    port: &str,
+    stop_source: bool,
+) -> eyre::Result<Option<String>> {
+    let config = Config::load()?;
+    let targets = targets(to, &config.groups)
+        .iter()
+        .map(|host| {
+            let (address, port) = fanout::address(host, port);
+            Client::new(&address, &port, None)
+        })
+        .collect::<eyre::Result<_>>()?;
+
+    hand_off(mpd, targets, stop_source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+
+    #[test]
+    fn test_targets() {
+        let groups = BTreeMap::from([(
+            "downstairs".to_string(),
+            vec!["kitchen".to_string(), "living".to_string()],
+        )]);
+
+        assert_eq!(targets("downstairs", &groups), ["kitchen", "living"]);
+        assert_eq!(targets("office:6601", &groups), ["office:6601"]);
+    }
+
+    #[test]
+    fn test_hand_off_leaves_out_missing_songs() {
+        let source = FakeServer::start(|line| {
+            let body = match line {
+                "status" => concat!(
+                    "state: play\nsong: 1\nsongid: 2\nplaylistlength: 3\n",
+                    "elapsed: 42.000\nvolume: 30\nrandom: 1\n",
+                ),
+                "playlistinfo" => concat!(
+                    "file: a.flac\nPos: 0\nId: 1\n",
+                    "file: b.flac\nPos: 1\nId: 2\n",
+                    "file: c.flac\nPos: 2\nId: 3\nPrio: 3\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let target = FakeServer::start(|line| {
+            let body = match line {
+                "listall" => "file: a.flac\nfile: b.flac\n",
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut mpd = source.client();
+
+        let output = hand_off(&mut mpd, vec![target.client()], true)
+            .unwrap()
+            .unwrap();
+
+        let handoffs: serde_json::Value =
+            serde_json::from_str(&output).unwrap();
+        assert_eq!(handoffs[0]["queued"], 2);
+        assert_eq!(handoffs[0]["missing"][0], "c.flac");
+        let commands = target.commands();
+        for line in [r#"add "a.flac""#, r#"random "1""#, "setvol 30"] {
+            assert!(commands.contains(&line.to_string()), "{line}");
+        }
+        assert!(commands.contains(&"seek 1 42".to_string()));
+        assert!(!commands.contains(&r#"add "c.flac""#.to_string()));
+        assert!(source.commands().contains(&"stop".to_string()));
+    }
+}
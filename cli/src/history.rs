@@ -0,0 +1,502 @@
+//! A local record of every play, kept as JSON lines in
+//! `$XDG_DATA_HOME/mp-cli/history.jsonl`, and reports built from it.
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use chrono::{
+    DateTime, Duration, Local, LocalResult, NaiveDate, NaiveTime, TimeZone,
+};
+use clap::ValueEnum;
+use eyre::{eyre, WrapErr};
+use mpd_easy::{dirs, Client, Event};
+use serde::{Deserialize, Serialize};
+
+use crate::args::{HistoryArgs, HistoryCommand};
+use crate::tracker::{self, Change, Play, Tracker};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Entry {
+    /// Unix timestamp of when the song started playing
+    pub(crate) played_at: i64,
+    pub(crate) file: String,
+    pub(crate) artist: String,
+    pub(crate) album: String,
+    pub(crate) title: String,
+    /// Length of the song in seconds, 0 when unknown
+    pub(crate) duration: u64,
+    /// Seconds actually spent playing the song
+    pub(crate) elapsed: u64,
+    pub(crate) skipped: bool,
+}
+
+impl From<Play> for Entry {
+    fn from(play: Play) -> Self {
+        let skipped = play.skipped();
+
+        Self {
+            played_at: play.started_at,
+            file: play.track.file,
+            artist: play.track.artist,
+            album: play.track.album,
+            title: play.track.title,
+            duration: play.track.duration,
+            elapsed: play.played,
+            skipped,
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let played_at = Local
+            .timestamp_opt(self.played_at, 0)
+            .single()
+            .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+
+        write!(
+            f,
+            "{played_at} {} - {} ({}/{}){}",
+            self.artist,
+            self.title,
+            clock(self.elapsed),
+            clock(self.duration),
+            if self.skipped { " skipped" } else { "" },
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Entries(Vec<Entry>);
+
+impl fmt::Display for Entries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> =
+            self.0.iter().map(ToString::to_string).collect();
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum Aggregate {
+    Artists,
+    Albums,
+    Tracks,
+}
+
+impl Aggregate {
+    fn key(self, entry: &Entry) -> String {
+        match self {
+            Aggregate::Artists => entry.artist.clone(),
+            Aggregate::Albums => format!("{} - {}", entry.artist, entry.album),
+            Aggregate::Tracks => format!("{} - {}", entry.artist, entry.title),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Count {
+    pub(crate) name: String,
+    pub(crate) plays: usize,
+    /// Total seconds listened
+    pub(crate) elapsed: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Counts(Vec<Count>);
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .0
+            .iter()
+            .map(|count| {
+                format!(
+                    "{:>5} {:>9} {}",
+                    count.plays,
+                    clock(count.elapsed),
+                    count.name
+                )
+            })
+            .collect();
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Most played entries first, by number of plays then time listened
+pub(crate) fn top(entries: &[Entry], by: Aggregate) -> Vec<Count> {
+    let mut counts: HashMap<String, Count> = HashMap::new();
+
+    for entry in entries {
+        let name = by.key(entry);
+        let count = counts.entry(name.clone()).or_insert(Count {
+            name,
+            plays: 0,
+            elapsed: 0,
+        });
+        count.plays += 1;
+        count.elapsed += entry.elapsed;
+    }
+
+    let mut counts: Vec<Count> = counts.into_values().collect();
+    counts.sort_by(|a, b| {
+        (Reverse(a.plays), Reverse(a.elapsed), &a.name).cmp(&(
+            Reverse(b.plays),
+            Reverse(b.elapsed),
+            &b.name,
+        ))
+    });
+
+    counts
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Filter {
+    pub(crate) since: Option<i64>,
+    pub(crate) until: Option<i64>,
+    pub(crate) artist: Option<String>,
+    pub(crate) skipped: bool,
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        self.since.is_none_or(|since| entry.played_at >= since)
+            && self.until.is_none_or(|until| entry.played_at < until)
+            && self
+                .artist
+                .as_ref()
+                .is_none_or(|artist| entry.artist.eq_ignore_ascii_case(artist))
+            && (!self.skipped || entry.skipped)
+    }
+}
+
+/// Parses `today`, a relative age (`12h`, `7d`, `4w`, `1y`), a date
+/// (`2024-01-01`) or a unix timestamp into a unix timestamp.
+pub(crate) fn parse_time(
+    input: &str,
+    now: DateTime<Local>,
+) -> eyre::Result<i64> {
+    let invalid =
+        || eyre!("invalid time: {input} (expected eg. today, 7d, 2024-01-01)");
+    let midnight = |date: NaiveDate| match Local
+        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+    {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => {
+            Ok(time.timestamp())
+        }
+        LocalResult::None => Err(invalid()),
+    };
+
+    if input == "today" {
+        return midnight(now.date_naive());
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return midnight(date);
+    }
+
+    if let Ok(timestamp) = input.parse::<i64>() {
+        return Ok(timestamp);
+    }
+
+    let split = input.len().saturating_sub(1);
+    let (amount, unit) = (input.get(..split), input.get(split..));
+    let amount: i64 = amount
+        .filter(|amount| !amount.starts_with(['-', '+']))
+        .and_then(|amount| amount.parse().ok())
+        .ok_or_else(invalid)?;
+    let too_far = || eyre!("invalid time: {input} (too far back)");
+    let age = match unit {
+        Some("h") => Duration::try_hours(amount),
+        Some("d") => Duration::try_days(amount),
+        Some("w") => Duration::try_weeks(amount),
+        Some("y") => amount.checked_mul(365).and_then(Duration::try_days),
+        _ => return Err(invalid()),
+    }
+    .ok_or_else(too_far)?;
+
+    now.checked_sub_signed(age)
+        .map(|time| time.timestamp())
+        .ok_or_else(too_far)
+}
+
+pub(crate) struct Store {
+    path: PathBuf,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new(dirs::data_dir().join("history.jsonl"))
+    }
+}
+
+impl Store {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub(crate) fn append(&self, entry: &Entry) -> eyre::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn entries(&self, filter: &Filter) -> eyre::Result<Vec<Entry>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let entries: Vec<Entry> = fs::read_to_string(&self.path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect::<eyre::Result<_>>()
+            .wrap_err(format!("corrupt history: {}", self.path.display()))?;
+
+        Ok(entries
+            .into_iter()
+            .filter(|entry| filter.matches(entry))
+            .collect())
+    }
+}
+
+fn clock(seconds: u64) -> String {
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Records `play`, logging a failure so that recording carries on
+fn save(store: &Store, play: Play) {
+    let entry = Entry::from(play);
+
+    match store.append(&entry) {
+        Ok(()) => println!("recorded: {entry}"),
+        Err(e) => println!("unable to record {entry}: {e}"),
+    }
+}
+
+/// Records every play until interrupted
+pub(crate) fn record(
+    mpd: &mut Client,
+    store: &Store,
+) -> eyre::Result<Option<String>> {
+    let mut tracker = Tracker::default();
+
+    loop {
+        let status = mpd.status()?;
+        for change in tracker.update(&status, tracker::now()) {
+            if let Change::Finished(play) = change {
+                save(store, play);
+            }
+        }
+
+        mpd.wait(&[Event::Player])?;
+    }
+}
+
+/// Plays matching `filter`, most recent last, or the most played artists,
+/// albums or tracks among them
+pub(crate) fn report(
+    mpd: &Client,
+    store: &Store,
+    filter: &Filter,
+    top_by: Option<Aggregate>,
+    limit: Option<usize>,
+) -> eyre::Result<Option<String>> {
+    let entries = store.entries(filter)?;
+
+    if let Some(by) = top_by {
+        let mut counts = top(&entries, by);
+        counts.truncate(limit.unwrap_or(10));
+        return mpd.render(&Counts(counts));
+    }
+
+    let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+    mpd.render(&Entries(entries.into_iter().skip(skip).collect()))
+}
+
+pub(crate) fn run(
+    mpd: &mut Client,
+    args: HistoryArgs,
+) -> eyre::Result<Option<String>> {
+    let store = Store::default();
+
+    if let Some(HistoryCommand::Record) = args.command {
+        return record(mpd, &store);
+    }
+
+    let now = Local::now();
+    let parse = |time: Option<String>| {
+        time.map(|time| parse_time(&time, now)).transpose()
+    };
+    let filter = Filter {
+        since: parse(args.since)?,
+        until: parse(args.until)?,
+        artist: args.artist,
+        skipped: args.skipped,
+    };
+
+    report(mpd, &store, &filter, args.top, args.limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(
+        played_at: i64,
+        artist: &str,
+        title: &str,
+        skipped: bool,
+    ) -> Entry {
+        Entry {
+            played_at,
+            file: format!("{artist}/{title}.flac"),
+            artist: artist.to_string(),
+            album: "Album".to_string(),
+            title: title.to_string(),
+            duration: 200,
+            elapsed: if skipped { 20 } else { 200 },
+            skipped,
+        }
+    }
+
+    #[test]
+    fn test_store_filters() {
+        let dir = tempdir::TempDir::new("history").unwrap();
+        let store = Store::new(dir.path().join("history.jsonl"));
+
+        store.append(&entry(100, "Phish", "Fee", false)).unwrap();
+        store.append(&entry(200, "Phish", "Llama", true)).unwrap();
+        store
+            .append(&entry(300, "Wilco", "Jesus, Etc.", false))
+            .unwrap();
+
+        let titles = |filter: Filter| -> Vec<String> {
+            store
+                .entries(&filter)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.title)
+                .collect()
+        };
+
+        assert_eq!(titles(Filter::default()).len(), 3);
+        assert_eq!(
+            titles(Filter {
+                since: Some(200),
+                ..Default::default()
+            }),
+            vec!["Llama", "Jesus, Etc."]
+        );
+        assert_eq!(
+            titles(Filter {
+                artist: Some("phish".to_string()),
+                skipped: true,
+                ..Default::default()
+            }),
+            vec!["Llama"]
+        );
+    }
+
+    #[test]
+    fn test_failed_writes_do_not_stop_recording() {
+        let dir = tempdir::TempDir::new("history").unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let play = || Play {
+            track: tracker::Track {
+                file: "a.flac".to_string(),
+                artist: "Phish".to_string(),
+                album: "Junta".to_string(),
+                title: "Fee".to_string(),
+                duration: 300,
+            },
+            started_at: 100,
+            played: 300,
+            position: 300,
+        };
+
+        // the history can't be written inside a file
+        save(&Store::new(file.join("history.jsonl")), play());
+        let store = Store::new(dir.path().join("history.jsonl"));
+        save(&store, play());
+
+        assert_eq!(store.entries(&Filter::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_top_artists() {
+        let entries = vec![
+            entry(1, "Wilco", "Jesus, Etc.", false),
+            entry(2, "Phish", "Fee", false),
+            entry(3, "Phish", "Llama", true),
+        ];
+
+        assert_eq!(
+            top(&entries, Aggregate::Artists),
+            vec![
+                Count {
+                    name: "Phish".to_string(),
+                    plays: 2,
+                    elapsed: 220,
+                },
+                Count {
+                    name: "Wilco".to_string(),
+                    plays: 1,
+                    elapsed: 200,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+
+        assert_eq!(parse_time("7d", now).unwrap(), 1_700_000_000 - 7 * 86400);
+        assert_eq!(parse_time("12h", now).unwrap(), 1_700_000_000 - 12 * 3600);
+        assert_eq!(parse_time("1699999999", now).unwrap(), 1_699_999_999);
+        assert!(parse_time("today", now).unwrap() <= 1_700_000_000);
+        assert!(parse_time("2023-11-14", now).is_ok());
+        assert!(parse_time("soon", now).is_err());
+        assert!(parse_time("d", now).is_err());
+    }
+
+    #[test]
+    fn test_parse_time_rejects_negative_and_huge_ages() {
+        let now = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+
+        assert!(parse_time("-7d", now).is_err());
+        assert!(parse_time("+7d", now).is_err());
+        for huge in ["9223372036854775807y", "99999999999999w", "400000000y"] {
+            assert_eq!(
+                parse_time(huge, now).unwrap_err().to_string(),
+                format!("invalid time: {huge} (too far back)")
+            );
+        }
+    }
+}
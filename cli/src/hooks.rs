@@ -0,0 +1,228 @@
+//! Runs the commands of the `[hooks]` section of the config when the player
+//! changes, so that notifications, overlays or lights are small scripts.
+//!
+//! Each command runs with `sh -c`, gets the status as `MPC_*` environment
+//! variables (eg. `MPC_ARTIST`, `MPC_STATE`, `MPC_ELAPSED_SECS`) and
+//! `{"event": ..., "status": ...}` as JSON on stdin.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+use mpd_easy::{Client, Event, State, Status};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// What a hook runs on
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Trigger {
+    /// Another song became the current one
+    Song,
+    /// Playing, paused or stopped
+    State,
+    Volume,
+    /// Playback stopped after the last song of the queue
+    QueueEnd,
+    /// The database changed after an update
+    Database,
+    /// An output was enabled or disabled
+    Output,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let trigger = match self {
+            Trigger::Song => "song",
+            Trigger::State => "state",
+            Trigger::Volume => "volume",
+            Trigger::QueueEnd => "queue_end",
+            Trigger::Database => "database",
+            Trigger::Output => "output",
+        };
+        write!(f, "{trigger}")
+    }
+}
+
+/// The command run on each trigger
+pub(crate) type Hooks = BTreeMap<Trigger, String>;
+
+/// What changed between two statuses, given the subsystems MPD reported
+fn triggers(
+    previous: &Status,
+    current: &Status,
+    events: &[Event],
+) -> Vec<Trigger> {
+    let mut triggers = Vec::new();
+
+    if current.file_path.is_some() && current.file_path != previous.file_path {
+        triggers.push(Trigger::Song);
+    }
+    if current.state != previous.state {
+        triggers.push(Trigger::State);
+    }
+    if current.volume != previous.volume {
+        triggers.push(Trigger::Volume);
+    }
+    // MPD forgets the current song once it played the last one
+    if previous.state == State::Play
+        && current.state == State::Stop
+        && current.file_path.is_none()
+    {
+        triggers.push(Trigger::QueueEnd);
+    }
+    if events.contains(&Event::Database) {
+        triggers.push(Trigger::Database);
+    }
+    if events.contains(&Event::Output) {
+        triggers.push(Trigger::Output);
+    }
+
+    triggers
+}
+
+/// `MPC_*` variables for the fields of `value`, nested fields joined with
+/// `_`
+fn variables(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                let prefix = format!("{prefix}_{}", name.to_uppercase());
+                variables(&prefix, field, out);
+            }
+        }
+        Value::String(text) => out.push((prefix.to_string(), text.clone())),
+        Value::Null => out.push((prefix.to_string(), String::new())),
+        other => out.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+/// Starts `command` for `trigger`, writing the status to its stdin
+fn spawn(
+    command: &str,
+    trigger: Trigger,
+    status: &Status,
+) -> eyre::Result<Child> {
+    let status = serde_json::to_value(status)?;
+    let mut environment = vec![("MPC_EVENT".to_string(), trigger.to_string())];
+    variables("MPC", &status, &mut environment);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(environment)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = json!({ "event": trigger, "status": status });
+        // the hook may not read its stdin
+        let _ = writeln!(stdin, "{input}");
+    }
+
+    Ok(child)
+}
+
+/// Runs the hooks of `triggers` without waiting for them to finish
+fn fire(hooks: &Hooks, triggers: &[Trigger], status: &Status) {
+    for trigger in triggers {
+        let Some(command) = hooks.get(trigger) else {
+            continue;
+        };
+
+        match spawn(command, *trigger, status) {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+            }
+            Err(e) => println!("unable to run the {trigger} hook: {e}"),
+        }
+    }
+}
+
+/// Runs the hooks whenever their trigger happens, until interrupted
+pub(crate) fn run(
+    mpd: &mut Client,
+    hooks: &Hooks,
+) -> eyre::Result<Option<String>> {
+    let mut previous = mpd.status()?;
+
+    loop {
+        let events = mpd.wait(&[
+            Event::Player,
+            Event::Mixer,
+            Event::Database,
+            Event::Output,
+        ])?;
+        let status = mpd.status()?;
+
+        fire(hooks, &triggers(&previous, &status, &events), &status);
+        previous = status;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::tests::status;
+
+    #[test]
+    fn test_triggers() {
+        let playing = status(State::Play, "a.flac", 10, 100);
+        let mut next = status(State::Play, "b.flac", 0, 100);
+        next.volume = "80".to_string();
+        let mut ended = status(State::Stop, "", 0, 0);
+        ended.file_path = None;
+
+        assert!(triggers(&playing, &playing, &[]).is_empty());
+        assert_eq!(
+            triggers(&playing, &next, &[Event::Output]),
+            [Trigger::Song, Trigger::Volume, Trigger::Output]
+        );
+        assert_eq!(
+            triggers(&playing, &ended, &[]),
+            [Trigger::State, Trigger::QueueEnd]
+        );
+    }
+
+    #[test]
+    fn test_hooks_config() {
+        let config = crate::config::Config::parse(
+            "[hooks]\nsong = \"notify-send song\"\nqueue_end = \"true\"",
+        )
+        .unwrap();
+
+        assert_eq!(config.hooks[&Trigger::Song], "notify-send song");
+        assert!(config.hooks.contains_key(&Trigger::QueueEnd));
+        assert!(crate::config::Config::parse("[hooks]\nsongs = \"\"").is_err());
+    }
+
+    #[test]
+    fn test_spawn_passes_the_status() {
+        let dir = tempdir::TempDir::new("hooks").unwrap();
+        let output = dir.path().join("output");
+        let command = format!(
+            "echo \"$MPC_EVENT $MPC_ARTIST $MPC_ELAPSED $MPC_ELAPSED_SECS\" > {0}; \
+             cat >> {0}",
+            output.display()
+        );
+
+        spawn(
+            &command,
+            Trigger::Song,
+            &status(State::Play, "a.flac", 5, 100),
+        )
+        .unwrap()
+        .wait()
+        .unwrap();
+
+        let written = std::fs::read_to_string(&output).unwrap();
+        let (variables, input) = written.split_once('\n').unwrap();
+        assert_eq!(variables, "song Phish 00:00:05 5.0");
+        let input: Value = serde_json::from_str(input).unwrap();
+        assert_eq!(input["event"], "song");
+        assert_eq!(input["status"]["title"], "a.flac");
+    }
+}
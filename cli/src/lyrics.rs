@@ -0,0 +1,151 @@
+//! Lyrics of a song, or with `--follow` the line being sung, printed anew
+//! as the song plays (eg. for a status bar).
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use mpd_easy::{Client, Event, Line, Lyrics, State, Time};
+use serde::Deserialize;
+
+use crate::config::Config;
+
+/// Longest wait between looks at the elapsed time, so that seeks show
+const POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    /// Where `Artist - Title.lrc` (or `.txt`) files are looked for
+    pub(crate) directory: Option<PathBuf>,
+}
+
+/// The synced lyrics of the current song and the line last shown
+#[derive(Default)]
+struct Follower {
+    directory: Option<PathBuf>,
+    /// The song and its lyrics, `None` when it has none
+    song: Option<(String, Option<Lyrics>)>,
+    shown: Option<Line>,
+}
+
+impl Follower {
+    /// The line to show when it changed, an empty one between lines, and
+    /// how long until the next one
+    fn update(
+        &mut self,
+        mpd: &mut Client,
+        file: &str,
+        elapsed: Time,
+    ) -> (Option<Line>, Option<Duration>) {
+        if self.song.as_ref().is_none_or(|(song, _)| song != file) {
+            let lyrics =
+                mpd.lyrics(Some(file), self.directory.as_deref(), true).ok();
+            self.song = Some((file.to_string(), lyrics));
+        }
+        let lyrics = self.song.as_ref().and_then(|(_, lyrics)| lyrics.as_ref());
+
+        let line = lyrics
+            .and_then(|lyrics| lyrics.line_at(elapsed))
+            .cloned()
+            .unwrap_or(Line {
+                time: None,
+                text: String::new(),
+            });
+        let until_next = lyrics
+            .and_then(|lyrics| lyrics.next_time(elapsed))
+            .map(|next| (next - elapsed).to_duration());
+
+        if self.shown.as_ref() == Some(&line) {
+            return (None, until_next);
+        }
+        self.shown = Some(line.clone());
+
+        (Some(line), until_next)
+    }
+}
+
+/// Prints the line being sung whenever it changes, until interrupted
+fn follow(
+    mpd: &mut Client,
+    directory: Option<PathBuf>,
+) -> eyre::Result<Option<String>> {
+    let mut follower = Follower {
+        directory,
+        ..Follower::default()
+    };
+
+    loop {
+        let status = mpd.status()?;
+        let file = status.file_path.unwrap_or_default();
+
+        let (line, until_next) = follower.update(mpd, &file, status.elapsed);
+        if let Some(output) = line.map(|line| mpd.render(&line)).transpose()? {
+            println!("{}", output.unwrap_or_default());
+        }
+
+        if status.state == State::Play {
+            thread::sleep(until_next.map_or(POLL, |until| until.min(POLL)));
+        } else {
+            mpd.wait(&[Event::Player])?;
+        }
+    }
+}
+
+pub(crate) fn run(
+    mpd: &mut Client,
+    uri: Option<&str>,
+    directory: Option<PathBuf>,
+    synced: bool,
+    follow_along: bool,
+) -> eyre::Result<Option<String>> {
+    let directory = directory.or(Config::load()?.lyrics.directory);
+
+    if follow_along {
+        return follow(mpd, directory);
+    }
+
+    let lyrics = mpd.lyrics(uri, directory.as_deref(), synced)?;
+    mpd.render(&lyrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+
+    #[test]
+    fn test_follower_shows_each_line_once() {
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                r#"find "file" "song.flac""# => "file: song.flac\n",
+                r#"readcomments "song.flac""# => {
+                    "LYRICS: [00:01.00]One\n[00:03.00]Two\n"
+                }
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut mpd = server.client();
+        let mut follower = Follower::default();
+        let mut update = |millis| {
+            let (line, until_next) = follower.update(
+                &mut mpd,
+                "song.flac",
+                Time::from_millis(millis),
+            );
+            (line.map(|line| line.text), until_next)
+        };
+
+        assert_eq!(
+            update(500),
+            (Some(String::new()), Some(Duration::from_millis(500)))
+        );
+        assert_eq!(
+            update(1500),
+            (Some("One".to_string()), Some(Duration::from_millis(1500)))
+        );
+        assert_eq!(update(2000).0, None);
+        assert_eq!(update(3000), (Some("Two".to_string()), None));
+    }
+}
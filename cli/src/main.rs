@@ -1,31 +1,132 @@
 #![deny(clippy::pedantic)]
 #![allow(clippy::missing_errors_doc)]
 #![allow(clippy::missing_panics_doc)]
+use std::collections::BTreeMap;
 use std::io::BufRead;
+use std::time::Duration;
 
 use clap::Parser;
 
 mod args;
+mod autodj;
+mod config;
+mod fanout;
+mod follow;
+mod handoff;
+mod history;
+mod hooks;
+mod lyrics;
+mod mpris;
+mod schedule;
+mod scrobble;
+mod session;
+mod sleep;
+mod smart;
+mod tracker;
+mod tui;
 
-use args::{Cli, Commands, OnOff};
-use mpd_easy::Client;
+use args::{CacheCommand, Cli, Commands, OnOff, OutputFormat};
+use mpd_easy::{Client, Enqueue};
 
 fn main() {
     let args = Cli::parse();
 
     // safe to unwrap because we have default values
-    let mut mpd = match Client::new(
-        &args.bind_to_address.unwrap(),
-        &args.port.unwrap(),
-        Some(args.format.to()),
-    ) {
-        Ok(client) => client,
+    let bind_to_address = args.bind_to_address.unwrap();
+    let port = args.port.unwrap();
+
+    let groups = match args.group {
+        Some(_) => match config::Config::load() {
+            Ok(config) => config.groups,
+            Err(e) => handle_error(e),
+        },
+        None => BTreeMap::new(),
+    };
+    let hosts = match fanout::hosts(&args.host, args.group.as_deref(), &groups)
+    {
+        Ok(hosts) => hosts,
         Err(e) => handle_error(e),
     };
+    let command = args.command.map(with_stdin);
+
+    if let [host] = hosts.as_slice() {
+        let (address, port) = fanout::address(host, &port);
+        return run_once(&address, &port, &args.format, command);
+    }
+    if hosts.is_empty() {
+        return run_once(&bind_to_address, &port, &args.format, command);
+    }
+
+    if command.as_ref().is_some_and(Commands::runs_alone) {
+        handle_error("this command runs against a single server");
+    }
+    let results = fanout::run(&hosts, |host| {
+        let (address, port) = fanout::address(host, &port);
+        let mut mpd = Client::new(&address, &port, Some(args.format.to()))?;
+        dispatch(&mut mpd, command.clone(), &address, &port)
+    });
+
+    println!(
+        "{}",
+        fanout::render(&results, matches!(args.format, OutputFormat::Json))
+    );
+    if results.iter().any(|(_, result)| result.is_err()) {
+        std::process::exit(1);
+    }
+}
+
+fn run_once(
+    address: &str,
+    port: &str,
+    format: &OutputFormat,
+    command: Option<Commands>,
+) {
+    let result = Client::new(address, port, Some(format.to()))
+        .and_then(|mut mpd| dispatch(&mut mpd, command, address, port));
+
+    match result {
+        Ok(Some(output)) => println!("{output}"),
+        Ok(None) => (),
+        Err(e) => handle_error(e),
+    }
+}
 
-    let result = match args.command {
-        Some(Commands::Add { path }) => {
-            mpd.add(&input_or_stdin(path, std::io::stdin().lock()))
+/// Reads the paths left out of `add` and `insert` from stdin, once even
+/// when running against several servers
+fn with_stdin(command: Commands) -> Commands {
+    match command {
+        Commands::Add { path, local } => Commands::Add {
+            path: Some(input_or_stdin(path, std::io::stdin().lock())),
+            local,
+        },
+        Commands::Insert { uri } => Commands::Insert {
+            uri: Some(input_or_stdin(uri, std::io::stdin().lock())),
+        },
+        command => command,
+    }
+}
+
+// one arm per command
+#[allow(clippy::too_many_lines)]
+fn dispatch(
+    mpd: &mut Client,
+    command: Option<Commands>,
+    bind_to_address: &str,
+    port: &str,
+) -> eyre::Result<Option<String>> {
+    match command {
+        Some(Commands::Add { path, local }) => {
+            let path = path.unwrap_or_default();
+            // a broken config only loses the walk's options
+            let options = config::Config::load()
+                .map(|config| config.add)
+                .unwrap_or_default();
+            mpd.set_finder_options(options);
+            if local {
+                mpd.add_local(&path)
+            } else {
+                mpd.add(&path)
+            }
         }
         Some(Commands::Crop) => mpd.crop(),
         Some(Commands::Del { position }) => mpd.del(position),
@@ -48,16 +149,32 @@ fn main() {
         Some(Commands::Toggleoutput { args }) => mpd.toggle_output(args),
         Some(Commands::Queued) => mpd.queued(),
         Some(Commands::Shuffle) => mpd.shuffle(),
+        Some(Commands::Undo { number, list }) => {
+            if list {
+                mpd.undo_list()
+            } else {
+                mpd.undo(number)
+            }
+        }
+        Some(Commands::Session { command }) => session::run(mpd, command),
+        Some(Commands::Dedupe { by, keep }) => mpd.dedupe(by.to(), keep.to()),
+        Some(Commands::SortQueue { tags, reverse }) => {
+            mpd.sort_queue(&tags, reverse)
+        }
         Some(Commands::Lsplaylists) => mpd.lsplaylists(),
         Some(Commands::Load { name, range }) => mpd.load(&name, range),
-        Some(Commands::Insert { uri }) => {
-            mpd.insert(&input_or_stdin(uri, std::io::stdin().lock()))
-        }
+        Some(Commands::Insert { uri }) => mpd.insert(&uri.unwrap_or_default()),
         Some(Commands::Prio {
             priority,
             position_or_range,
         }) => mpd.prio(&priority, &position_or_range),
-        Some(Commands::Playlist { name }) => mpd.playlist(name),
+        Some(Commands::Playlist { name }) => mpd.playlist(name.as_deref()),
+        Some(Commands::Export { name, format }) => {
+            mpd.export(name.as_deref(), format.to())
+        }
+        Some(Commands::Import { file, name }) => {
+            mpd.import(&file, name.as_deref())
+        }
         Some(Commands::Listall { file }) => mpd.listall(file.as_deref()),
         Some(Commands::Ls { directory }) => mpd.ls(directory.as_deref()),
         Some(Commands::Repeat { state }) => mpd.repeat(OnOff::to(&state)),
@@ -69,6 +186,23 @@ fn main() {
         Some(Commands::SearchAdd { tag, query }) => {
             mpd.search_add(tag.to_str(), &query)
         }
+        Some(Commands::Fuzzy {
+            query,
+            tag,
+            limit,
+            regex,
+            add,
+            insert,
+        }) => {
+            let enqueue = if add {
+                Some(Enqueue::Add)
+            } else if insert {
+                Some(Enqueue::Insert)
+            } else {
+                None
+            };
+            mpd.fuzzy(&query, tag.to(), regex, limit, enqueue)
+        }
         Some(Commands::Find { tag, query }) => mpd.find(tag.to_str(), &query),
         Some(Commands::FindAdd { tag, query }) => {
             mpd.find_add(tag.to_str(), &query)
@@ -79,17 +213,105 @@ fn main() {
 
         Some(Commands::Save { name }) => mpd.save(&name),
         Some(Commands::Rm { name }) => mpd.rm(&name),
-        Some(Commands::Volume { volume }) => mpd.set_volume(&volume),
+        Some(Commands::Volume { volume: None, .. }) => mpd.volume(),
+        Some(Commands::Volume {
+            volume: Some(volume),
+            over,
+        }) => match over {
+            Some(over) => mpd.ramp_volume(&volume, Duration::from_secs(over)),
+            None => mpd.set_volume(&volume),
+        },
+        Some(Commands::Mute) => mpd.mute(),
+        Some(Commands::Unmute) => mpd.unmute(),
+        Some(Commands::Togglemute) => mpd.toggle_mute(),
         Some(Commands::Stats) => mpd.stats(),
         Some(Commands::Version) => mpd.version(),
+        Some(Commands::Tui) => tui::run(mpd, bind_to_address, port),
+        Some(Commands::Mpris) => mpris::run(bind_to_address, port),
+        Some(Commands::Scrobble {
+            api,
+            endpoint,
+            token,
+        }) => config::Config::load().and_then(|config| {
+            let mut settings = config.scrobble;
+            settings.api = api.unwrap_or(settings.api);
+            settings.endpoint = endpoint.or(settings.endpoint);
+            settings.token = token.or(settings.token);
+            scrobble::run(mpd, settings)
+        }),
+        Some(Commands::Sleep {
+            target,
+            fade,
+            detach,
+        }) => sleep::run(mpd, target, fade, detach),
+        Some(Commands::Scheduler) => {
+            config::Config::load().and_then(|config| {
+                schedule::run(&config.schedule, bind_to_address, port)
+            })
+        }
+        Some(Commands::Alarm {
+            time,
+            playlist,
+            volume,
+            ramp,
+        }) => schedule::alarm(mpd, time, playlist, volume, ramp),
+        Some(Commands::History(args)) => history::run(mpd, args),
+        Some(Commands::RandomAlbum {
+            filter,
+            replace,
+            play,
+        }) => mpd.random_album(filter.as_deref(), replace, play),
+        Some(Commands::RandomArtist {
+            filter,
+            replace,
+            play,
+        }) => mpd.random_artist(filter.as_deref(), replace, play),
+        Some(Commands::RandomSongs {
+            count,
+            filter,
+            replace,
+            play,
+        }) => mpd.random_songs(count, filter.as_deref(), replace, play),
+        Some(Commands::Autodj {
+            min,
+            filter,
+            albums,
+            track_window,
+            artist_window,
+            keep,
+        }) => autodj::run(
+            mpd,
+            &autodj::Options {
+                min,
+                filter,
+                albums,
+                track_window,
+                artist_window,
+                keep,
+            },
+        ),
+        Some(Commands::Smart { command }) => smart::run(mpd, command),
+        Some(Commands::Handoff { to, stop_source }) => {
+            handoff::run(mpd, &to, port, stop_source)
+        }
+        Some(Commands::Follow { source, to, drift }) => {
+            follow::run(&source, &to, port, drift)
+        }
+        Some(Commands::Lyrics {
+            uri,
+            synced,
+            follow,
+            directory,
+        }) => lyrics::run(mpd, uri.as_deref(), directory, synced, follow),
+        Some(Commands::Hooks) => config::Config::load()
+            .and_then(|config| hooks::run(mpd, &config.hooks)),
+        Some(Commands::Cache { command }) => match command {
+            CacheCommand::Refresh { full } => mpd.cache_refresh(full),
+            CacheCommand::Clear => mpd.cache_clear(),
+            CacheCommand::Status => mpd.cache_status(),
+        },
 
         Some(Commands::Status) | None => mpd.current_status(),
-    };
-
-    match result {
-        Ok(Some(output)) => println!("{output}"),
-        Ok(None) => (),
-        Err(e) => handle_error(e),
     }
 }
 
@@ -0,0 +1,587 @@
+//! MPRIS2 D-Bus bridge so desktop media keys and widgets can drive MPD.
+//!
+//! Method calls and property reads go through a shared command connection
+//! while a second connection waits on MPD's `idle` events and turns them into
+//! `PropertiesChanged` (and `Seeked`) signals.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use mpd_easy::{dirs, Client, Event, OnOff, OutputFormat, State, Time};
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{block_on, fdo, interface};
+
+pub(crate) const BUS_NAME: &str = "org.mpris.MediaPlayer2.mp_cli";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+type Shared = Arc<Mutex<Client>>;
+
+/// `time` in the microseconds MPRIS counts in
+fn micros(time: Time) -> i64 {
+    time.as_millis() * 1000
+}
+
+/// `micros` as a time, to the millisecond MPD seeks with
+fn from_micros(micros: i64) -> Time {
+    Time::from_millis(micros / 1000)
+}
+
+/// Blocks serving MPRIS on the session bus until the MPD connection drops.
+pub(crate) fn run(
+    bind_to_address: &str,
+    port: &str,
+) -> eyre::Result<Option<String>> {
+    let connection = serve(Builder::session()?, bind_to_address, port)?;
+    let idle = Client::new(bind_to_address, port, Some(OutputFormat::None))?;
+
+    watch(&connection, idle)?;
+
+    Ok(None)
+}
+
+fn serve(
+    builder: Builder,
+    bind_to_address: &str,
+    port: &str,
+) -> eyre::Result<Connection> {
+    let mpd = Client::new(bind_to_address, port, Some(OutputFormat::None))?;
+    let player = Player {
+        mpd: Arc::new(Mutex::new(mpd)),
+        art: Mutex::new(None),
+    };
+
+    let connection = builder
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root)?
+        .serve_at(OBJECT_PATH, player)?
+        .build()?;
+
+    Ok(connection)
+}
+
+/// Turns idle events into D-Bus signals for as long as MPD is reachable.
+fn watch(connection: &Connection, mut idle: Client) -> eyre::Result<()> {
+    let iface_ref = connection
+        .object_server()
+        .interface::<_, Player>(OBJECT_PATH)?;
+    let emitter = iface_ref.signal_emitter();
+    let mut last = iface_ref.get().snapshot()?;
+
+    loop {
+        let events =
+            idle.wait(&[Event::Player, Event::Mixer, Event::Options])?;
+        let iface = iface_ref.get();
+
+        for event in events {
+            match event {
+                Event::Player => {
+                    let current = iface.snapshot()?;
+
+                    block_on(iface.playback_status_changed(emitter))?;
+
+                    if current.file == last.file && current.state == last.state
+                    {
+                        block_on(Player::seeked(emitter, current.position))?;
+                    } else {
+                        block_on(iface.metadata_changed(emitter))?;
+                    }
+
+                    last = current;
+                }
+                Event::Mixer => block_on(iface.volume_changed(emitter))?,
+                Event::Options => {
+                    block_on(iface.loop_status_changed(emitter))?;
+                    block_on(iface.shuffle_changed(emitter))?;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn failed(error: &eyre::Report) -> fdo::Error {
+    fdo::Error::Failed(error.to_string())
+}
+
+struct Root;
+
+// D-Bus methods and properties must take `self` even when constant
+#[allow(clippy::unused_self)]
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        "mp-cli".to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// The parts of the player state used to tell seeks from track changes
+struct Snapshot {
+    state: State,
+    file: Option<String>,
+    position: i64,
+}
+
+struct Player {
+    mpd: Shared,
+    /// Cover art URL of the last song looked up, keyed by its file
+    art: Mutex<Option<(String, Option<String>)>>,
+}
+
+impl Player {
+    fn with<T>(
+        &self,
+        command: impl FnOnce(&mut Client) -> eyre::Result<T>,
+    ) -> fdo::Result<T> {
+        let mut mpd = self
+            .mpd
+            .lock()
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+
+        command(&mut mpd).map_err(|e| failed(&e))
+    }
+
+    fn snapshot(&self) -> fdo::Result<Snapshot> {
+        let status = self.with(Client::status)?;
+
+        Ok(Snapshot {
+            state: status.state,
+            file: status.file_path,
+            position: micros(status.elapsed),
+        })
+    }
+
+    /// Writes the cover art MPD serves for `file` to the cache directory.
+    fn art_url(&self, mpd: &mut Client, file: &str) -> Option<String> {
+        let mut cached = self.art.lock().ok()?;
+
+        if let Some((cached_file, url)) = cached.as_ref() {
+            if cached_file == file {
+                return url.clone();
+            }
+        }
+
+        let url = mpd.albumart(file).ok().and_then(|bytes| {
+            let mut hasher = DefaultHasher::new();
+            file.hash(&mut hasher);
+
+            let directory: PathBuf = dirs::cache_dir().join("art");
+            let path = directory.join(format!("{:016x}", hasher.finish()));
+
+            fs::create_dir_all(&directory).ok()?;
+            fs::write(&path, bytes).ok()?;
+
+            Some(format!("file://{}", path.display()))
+        });
+
+        *cached = Some((file.to_string(), url.clone()));
+        url
+    }
+}
+
+#[allow(clippy::unused_self, clippy::needless_pass_by_value)]
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) -> fdo::Result<()> {
+        self.with(Client::next).map(|_| ())
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        self.with(Client::prev).map(|_| ())
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        self.with(Client::pause).map(|_| ())
+    }
+
+    fn play_pause(&self) -> fdo::Result<()> {
+        self.with(Client::toggle).map(|_| ())
+    }
+
+    fn stop(&self) -> fdo::Result<()> {
+        self.with(Client::stop).map(|_| ())
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        self.with(|mpd| mpd.play(None)).map(|_| ())
+    }
+
+    /// Seeks forwards (or backwards) by `offset` microseconds.
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        self.with(|mpd| {
+            let status = mpd.status()?;
+            let target = status.elapsed + from_micros(offset);
+
+            if target > status.track_length {
+                mpd.next()?;
+            } else {
+                mpd.seek_to(target)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn set_position(
+        &self,
+        track_id: ObjectPath<'_>,
+        position: i64,
+    ) -> fdo::Result<()> {
+        self.with(|mpd| {
+            let status = mpd.status()?;
+            let song = mpd.current_song()?;
+            let current =
+                track_path(song.and_then(|s| s.place).map(|p| p.id.0));
+            let target = from_micros(position);
+
+            // stale requests for a previous track are ignored per the spec
+            if current.as_str() == track_id.as_str()
+                && (Time::ZERO..=status.track_length).contains(&target)
+            {
+                mpd.seek_to(target)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn open_uri(&self, uri: &str) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(format!("cannot open uri: {uri}")))
+    }
+
+    #[zbus(signal)]
+    async fn seeked(
+        emitter: &SignalEmitter<'_>,
+        position: i64,
+    ) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> fdo::Result<String> {
+        let status = self.with(Client::status)?;
+
+        let playback = match status.state {
+            State::Play => "Playing",
+            State::Pause => "Paused",
+            State::Stop => "Stopped",
+        };
+
+        Ok(playback.to_string())
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> fdo::Result<String> {
+        let status = self.with(Client::status)?;
+
+        let looping = match (status.repeat, status.single) {
+            (OnOff::On, OnOff::On) => "Track",
+            (OnOff::On, OnOff::Off) => "Playlist",
+            (OnOff::Off, _) => "None",
+        };
+
+        Ok(looping.to_string())
+    }
+
+    #[zbus(property)]
+    fn set_loop_status(&mut self, value: String) -> fdo::Result<()> {
+        let (repeat, single) = match value.as_str() {
+            "None" => (OnOff::Off, OnOff::Off),
+            "Track" => (OnOff::On, OnOff::On),
+            "Playlist" => (OnOff::On, OnOff::Off),
+            _ => {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "unknown loop status: {value}"
+                )))
+            }
+        };
+
+        self.with(|mpd| {
+            mpd.repeat(Some(repeat))?;
+            mpd.single(Some(single))?;
+
+            Ok(())
+        })
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> fdo::Result<bool> {
+        Ok(self.with(Client::status)?.random == OnOff::On)
+    }
+
+    #[zbus(property)]
+    fn set_shuffle(&mut self, value: bool) -> fdo::Result<()> {
+        self.with(|mpd| mpd.random(Some(OnOff::from(value))))
+            .map(|_| ())
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> fdo::Result<HashMap<String, OwnedValue>> {
+        self.with(|mpd| {
+            let song = mpd.current_song()?;
+            let mut metadata = HashMap::new();
+            let mut insert = |key: &str, value: Value<'_>| {
+                if let Ok(value) = value.try_to_owned() {
+                    metadata.insert(key.to_string(), value);
+                }
+            };
+
+            let track_id =
+                track_path(song.as_ref().and_then(|s| s.place).map(|p| p.id.0));
+            insert(
+                "mpris:trackid",
+                Value::from(ObjectPath::from_string_unchecked(track_id)),
+            );
+
+            let Some(song) = song else {
+                return Ok(metadata);
+            };
+
+            if let Some(duration) = song.duration {
+                let length = i64::try_from(duration.as_micros())?;
+                insert("mpris:length", Value::from(length));
+            }
+            if let Some(title) = song.title.clone() {
+                insert("xesam:title", Value::from(title));
+            }
+            if let Some(artist) = song.artist.clone() {
+                insert("xesam:artist", Value::from(vec![artist]));
+            }
+            if let Some((_, album)) =
+                song.tags.iter().find(|(key, _)| key == "Album")
+            {
+                insert("xesam:album", Value::from(album.clone()));
+            }
+            insert("xesam:url", Value::from(song.file.clone()));
+            if let Some(url) = self.art_url(mpd, &song.file) {
+                insert("mpris:artUrl", Value::from(url));
+            }
+
+            Ok(metadata)
+        })
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> fdo::Result<f64> {
+        let status = self.with(Client::status)?;
+        let volume = status.volume.parse::<i8>().unwrap_or(-1).max(0);
+
+        Ok(f64::from(volume) / 100.0)
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, value: f64) -> fdo::Result<()> {
+        #[allow(clippy::cast_possible_truncation)]
+        let volume = (value.clamp(0.0, 1.0) * 100.0).round() as i8;
+
+        self.with(|mpd| mpd.set_volume(&volume.to_string()))
+            .map(|_| ())
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> fdo::Result<i64> {
+        Ok(self.snapshot()?.position)
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// MPRIS track ids are object paths; MPD song ids make them unique per queue
+fn track_path(song_id: Option<u32>) -> String {
+    song_id.map_or(NO_TRACK.to_string(), |id| format!("/org/mpd/Tracks/{id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use zbus::blocking::Proxy;
+
+    const MICROSECONDS: i64 = 1_000_000;
+
+    /// A private session bus so tests never touch the desktop's own bus.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+
+            Some(Self {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    fn server() -> FakeServer {
+        FakeServer::start(|line| {
+            let body = match line {
+                "status" => concat!(
+                    "volume: 40\nrepeat: 0\nrandom: 1\nsingle: 0\n",
+                    "state: play\nsong: 0\nsongid: 7\ntime: 30:200\n",
+                ),
+                "currentsong" => concat!(
+                    "file: Phish/Junta/01.flac\nArtist: Phish\n",
+                    "Title: Fee\nAlbum: Junta\nTime: 200\nPos: 0\nId: 7\n",
+                ),
+                line if line.starts_with("albumart") => {
+                    return Err("No file exists".to_string())
+                }
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        })
+    }
+
+    #[test]
+    fn test_player_over_private_bus() {
+        let bus = Bus::start().expect("dbus-daemon is needed on the PATH");
+        let server = server();
+
+        let _service = serve(
+            Builder::address(bus.address.as_str()).unwrap(),
+            &server.address,
+            &server.port,
+        )
+        .unwrap();
+
+        let connection = Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+        let player = Proxy::new(
+            &connection,
+            BUS_NAME,
+            OBJECT_PATH,
+            "org.mpris.MediaPlayer2.Player",
+        )
+        .unwrap();
+
+        let status: String = player.get_property("PlaybackStatus").unwrap();
+        assert_eq!(status, "Playing");
+
+        let volume: f64 = player.get_property("Volume").unwrap();
+        assert!((volume - 0.4).abs() < f64::EPSILON);
+
+        let metadata: HashMap<String, OwnedValue> =
+            player.get_property("Metadata").unwrap();
+        let title = String::try_from(metadata["xesam:title"].clone()).unwrap();
+        let length = i64::try_from(metadata["mpris:length"].clone()).unwrap();
+        assert_eq!(title, "Fee");
+        assert_eq!(length, 200 * MICROSECONDS);
+
+        player.call_method("PlayPause", &()).unwrap();
+        player.call_method("Seek", &(10 * MICROSECONDS)).unwrap();
+        player.call_method("Seek", &2_500_000_i64).unwrap();
+        player.set_property("Volume", 0.25).unwrap();
+        player.set_property("LoopStatus", "Track").unwrap();
+
+        let commands = server.commands();
+        assert!(commands.contains(&r#"pause "1""#.to_string()));
+        assert!(commands.contains(&r#"seek "0" "40""#.to_string()));
+        assert!(commands.contains(&r#"seek "0" "32.5""#.to_string()));
+        assert!(commands.contains(&r#"setvol "25""#.to_string()));
+        assert!(commands.contains(&r#"repeat "1""#.to_string()));
+        assert!(commands.contains(&r#"single "1""#.to_string()));
+    }
+}
@@ -0,0 +1,426 @@
+//! Jobs run at a wall-clock time, either from the `[[schedule]]` sections of
+//! the config file by the `scheduler` command or once by `alarm`.
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use eyre::eyre;
+use mpd_easy::Client;
+use serde::{Deserialize, Deserializer};
+
+/// Longest the scheduler sleeps before looking at the clock again, so that
+/// suspends and clock changes are noticed
+const MAX_NAP: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+    Weekdays,
+    Weekends,
+}
+
+impl Day {
+    fn includes(self, weekday: Weekday) -> bool {
+        match self {
+            Day::Mon => weekday == Weekday::Mon,
+            Day::Tue => weekday == Weekday::Tue,
+            Day::Wed => weekday == Weekday::Wed,
+            Day::Thu => weekday == Weekday::Thu,
+            Day::Fri => weekday == Weekday::Fri,
+            Day::Sat => weekday == Weekday::Sat,
+            Day::Sun => weekday == Weekday::Sun,
+            Day::Weekdays => weekday.num_days_from_monday() < 5,
+            Day::Weekends => weekday.num_days_from_monday() >= 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum Volume {
+    Level(u8),
+    /// Fade from one level to another, `over` being a duration (eg. 5m)
+    Ramp {
+        from: u8,
+        to: u8,
+        over: String,
+    },
+}
+
+// the flags mirror the keys of a `[[schedule]]` section
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Job {
+    #[serde(deserialize_with = "deserialize_time")]
+    pub(crate) at: NaiveTime,
+    /// Days the job runs on, every day when empty
+    #[serde(default)]
+    pub(crate) days: Vec<Day>,
+    /// Empty the queue before loading
+    #[serde(default)]
+    pub(crate) clear: bool,
+    /// Stored playlist to load into the queue
+    pub(crate) load: Option<String>,
+    pub(crate) volume: Option<Volume>,
+    #[serde(default)]
+    pub(crate) play: bool,
+    #[serde(default)]
+    pub(crate) pause: bool,
+    #[serde(default)]
+    pub(crate) stop: bool,
+}
+
+fn deserialize_time<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let time = String::deserialize(deserializer)?;
+
+    parse_time(&time).map_err(serde::de::Error::custom)
+}
+
+/// Parses a wall-clock time such as `06:45` or `23:00:30`
+pub(crate) fn parse_time(input: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(input, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(input, "%H:%M:%S"))
+        .map_err(|_| format!("invalid time: {input} (expected eg. 06:45)"))
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut steps = Vec::new();
+
+        if self.clear {
+            steps.push("clear".to_string());
+        }
+        if let Some(name) = &self.load {
+            steps.push(format!("load {name}"));
+        }
+        match &self.volume {
+            Some(Volume::Level(level)) => steps.push(format!("volume {level}")),
+            Some(Volume::Ramp { from, to, over }) => {
+                steps.push(format!("volume {from}→{to} over {over}"));
+            }
+            None => {}
+        }
+        for (enabled, step) in [
+            (self.play, "play"),
+            (self.pause, "pause"),
+            (self.stop, "stop"),
+        ] {
+            if enabled {
+                steps.push(step.to_string());
+            }
+        }
+
+        write!(f, "{}: {}", self.at.format("%H:%M"), steps.join(", "))
+    }
+}
+
+impl Job {
+    fn runs_on(&self, weekday: Weekday) -> bool {
+        self.days.is_empty()
+            || self.days.iter().any(|day| day.includes(weekday))
+    }
+
+    /// The first time the job runs after `after`
+    pub(crate) fn next(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        after
+            .date()
+            .iter_days()
+            .take(8)
+            .map(|date| date.and_time(self.at))
+            .find(|at| *at > after && self.runs_on(at.weekday()))
+    }
+
+    /// Checks the job can run before its time comes
+    fn validate(&self) -> eyre::Result<()> {
+        if let Some(Volume::Ramp { over, .. }) = &self.volume {
+            crate::sleep::parse_seconds(over).map_err(|e| eyre!(e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs every step of the job, ramping the volume up after playback has
+    /// started so that it fades in
+    pub(crate) fn run(&self, mpd: &mut Client) -> eyre::Result<()> {
+        if self.clear {
+            mpd.clear()?;
+        }
+        if let Some(name) = &self.load {
+            mpd.load(name, None)?;
+        }
+
+        let ramp = match &self.volume {
+            Some(Volume::Level(level)) => {
+                mpd.set_volume(&level.to_string())?;
+                None
+            }
+            Some(Volume::Ramp { from, to, over }) => {
+                mpd.set_volume(&from.to_string())?;
+                let over =
+                    crate::sleep::parse_seconds(over).map_err(|e| eyre!(e))?;
+                Some((*to, over))
+            }
+            None => None,
+        };
+
+        if self.play {
+            mpd.play(None)?;
+        }
+
+        if let Some((to, over)) = ramp {
+            mpd.ramp_volume(&to.to_string(), Duration::from_secs(over))?;
+        }
+
+        if self.pause {
+            mpd.pause()?;
+        }
+        if self.stop {
+            mpd.stop()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+/// Sleeps until `at`, waking up regularly to follow the wall clock
+fn sleep_until(at: NaiveDateTime) {
+    loop {
+        let Ok(left) = (at - now()).to_std() else {
+            return;
+        };
+        if left.is_zero() {
+            return;
+        }
+
+        thread::sleep(left.min(MAX_NAP));
+    }
+}
+
+/// Runs the configured jobs at their times until interrupted. Jobs run on
+/// a connection of their own, so that a long volume ramp doesn't hold back
+/// the next ones.
+pub(crate) fn run(
+    jobs: &[Job],
+    bind_to_address: &str,
+    port: &str,
+) -> eyre::Result<Option<String>> {
+    if jobs.is_empty() {
+        return Err(eyre!("no [[schedule]] jobs in the config file"));
+    }
+    for job in jobs {
+        job.validate()?;
+        println!("scheduled {job}");
+    }
+
+    let mut last = now();
+    loop {
+        let Some(next) = jobs.iter().filter_map(|job| job.next(last)).min()
+        else {
+            return Err(eyre!("no job will ever run"));
+        };
+        sleep_until(next);
+
+        let current = now();
+        // every job whose time came while sleeping, in the order of the
+        // config
+        let due: Vec<Job> = jobs
+            .iter()
+            .filter(|job| job.next(last).is_some_and(|at| at <= current))
+            .cloned()
+            .collect();
+        let (address, port) = (bind_to_address.to_string(), port.to_string());
+        thread::spawn(move || {
+            let mut mpd = match Client::new(&address, &port, None) {
+                Ok(mpd) => mpd,
+                Err(e) => {
+                    println!("{current}: {e}");
+                    return;
+                }
+            };
+            for job in due {
+                match job.run(&mut mpd) {
+                    Ok(()) => println!("{current} {job}: ok"),
+                    Err(e) => println!("{current} {job}: {e}"),
+                }
+            }
+        });
+        last = current;
+    }
+}
+
+/// Waits for the next `at` and starts playback, optionally loading a
+/// playlist and fading the volume in
+pub(crate) fn alarm(
+    mpd: &mut Client,
+    at: NaiveTime,
+    playlist: Option<String>,
+    volume: Option<u8>,
+    ramp: Option<u64>,
+) -> eyre::Result<Option<String>> {
+    let volume = match (volume, ramp) {
+        (Some(level), None) => Some(Volume::Level(level)),
+        (level, Some(over)) => Some(Volume::Ramp {
+            from: 0,
+            to: match level {
+                Some(level) => level,
+                None => mpd.status()?.volume.parse().unwrap_or(100),
+            },
+            over: over.to_string(),
+        }),
+        (None, None) => None,
+    };
+    let job = Job {
+        at,
+        days: vec![],
+        clear: playlist.is_some(),
+        load: playlist,
+        volume,
+        play: true,
+        pause: false,
+        stop: false,
+    };
+    job.validate()?;
+
+    let next = job.next(now()).ok_or_else(|| eyre!("invalid alarm time"))?;
+    println!("alarm set for {next}: {job}");
+    sleep_until(next);
+    job.run(mpd)?;
+
+    mpd.current_status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use chrono::NaiveDate;
+    use mpd_easy::testing::{parse_line, FakeServer};
+    use std::sync::Mutex;
+
+    fn jobs() -> Vec<Job> {
+        Config::parse(
+            r#"
+            [[schedule]]
+            at = "06:45"
+            days = ["weekdays"]
+            load = "Morning"
+            volume = { from = 10, to = 40, over = "5m" }
+            play = true
+
+            [[schedule]]
+            at = "23:00"
+            pause = true
+            "#,
+        )
+        .unwrap()
+        .schedule
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-01-05 is a Friday
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_jobs() {
+        let jobs = jobs();
+
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(
+            jobs[0].to_string(),
+            "06:45: load Morning, volume 10→40 over 5m, play"
+        );
+        assert_eq!(jobs[1].to_string(), "23:00: pause");
+        assert!(Config::parse("[[schedule]]\nat = \"25:00\"").is_err());
+        assert!(Config::parse("[[schedule]]\nat = \"7:00\"\nday = []").is_err());
+    }
+
+    #[test]
+    fn test_next_skips_weekends() {
+        let jobs = jobs();
+
+        assert_eq!(jobs[0].next(at(5, 6, 0)), Some(at(5, 6, 45)));
+        assert_eq!(jobs[0].next(at(5, 6, 45)), Some(at(8, 6, 45)));
+        assert_eq!(jobs[1].next(at(6, 23, 30)), Some(at(7, 23, 0)));
+    }
+
+    #[test]
+    fn test_run_job() {
+        let server = FakeServer::start(|line| match line {
+            "status" => Ok("volume: 20\nstate: stop\n".to_string()),
+            _ => Ok(String::new()),
+        });
+        let mut client = server.client();
+        let job = Job {
+            volume: Some(Volume::Level(30)),
+            ..jobs().remove(0)
+        };
+
+        job.run(&mut client).unwrap();
+
+        let commands = server.commands();
+        let position = |command: &str| {
+            commands.iter().position(|line| line == command).unwrap()
+        };
+        assert!(
+            position(r#"load "Morning" "0:""#) < position(r#"setvol "30""#)
+        );
+        assert!(position(r#"setvol "30""#) < position("play"));
+    }
+
+    #[test]
+    fn test_ramp_after_play() {
+        let level = Mutex::new("20".to_string());
+        let server = FakeServer::start(move |line| {
+            let mut level = level.lock().unwrap();
+            match parse_line(line) {
+                (command, args) if command == "setvol" => {
+                    level.clone_from(&args[0]);
+                    Ok(String::new())
+                }
+                (command, _) if command == "status" || command == "getvol" => {
+                    Ok(format!("volume: {level}\nstate: play\n"))
+                }
+                _ => Ok(String::new()),
+            }
+        });
+        let job = Job {
+            volume: Some(Volume::Ramp {
+                from: 10,
+                to: 12,
+                over: "1s".to_string(),
+            }),
+            ..jobs().remove(0)
+        };
+
+        job.run(&mut server.client()).unwrap();
+
+        let commands = server.commands();
+        let play = commands.iter().position(|line| line == "play").unwrap();
+        assert!(commands[..play].contains(&r#"setvol "10""#.to_string()));
+        let ramp: Vec<_> = commands[play..]
+            .iter()
+            .filter(|line| line.starts_with("setvol"))
+            .collect();
+        assert_eq!(ramp, [r#"setvol "11""#, r#"setvol "12""#]);
+    }
+}
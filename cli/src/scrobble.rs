@@ -0,0 +1,581 @@
+//! Submits listens to a ListenBrainz or Last.fm compatible service.
+//!
+//! Listens that cannot be delivered are appended to a spool file and sent
+//! again once the service can be reached.
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use eyre::{eyre, WrapErr};
+use md5::{Digest, Md5};
+use mpd_easy::{dirs, Client, Event};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::tracker::{self, Change, Track, Tracker};
+
+const LISTENBRAINZ: &str = "https://api.listenbrainz.org";
+const LASTFM: &str = "https://ws.audioscrobbler.com/2.0/";
+const CLIENT: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Api {
+    #[default]
+    Listenbrainz,
+    Lastfm,
+}
+
+/// The `[scrobble]` section of the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub(crate) api: Api,
+    /// Base URL of the service, defaults to the public one for `api`
+    pub(crate) endpoint: Option<String>,
+    /// ListenBrainz user token
+    pub(crate) token: Option<String>,
+    /// Last.fm API key, secret and session key
+    pub(crate) api_key: Option<String>,
+    pub(crate) api_secret: Option<String>,
+    pub(crate) session_key: Option<String>,
+    /// Where undelivered listens are kept
+    pub(crate) spool: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Listen {
+    pub(crate) track: Track,
+    pub(crate) listened_at: i64,
+}
+
+enum Credentials {
+    ListenBrainz {
+        token: String,
+    },
+    LastFm {
+        api_key: String,
+        api_secret: String,
+        session_key: String,
+    },
+}
+
+/// `listens` as the lines of a spool file
+fn lines(listens: &[Listen]) -> eyre::Result<String> {
+    let mut lines = String::new();
+    for listen in listens {
+        writeln!(lines, "{}", serde_json::to_string(listen)?)?;
+    }
+
+    Ok(lines)
+}
+
+pub(crate) struct Scrobbler {
+    agent: ureq::Agent,
+    endpoint: String,
+    credentials: Credentials,
+    spool: PathBuf,
+}
+
+impl Scrobbler {
+    pub(crate) fn new(settings: Settings) -> eyre::Result<Self> {
+        let missing = |name: &str| eyre!("scrobble: {name} is not configured");
+
+        let credentials = match settings.api {
+            Api::Listenbrainz => Credentials::ListenBrainz {
+                token: settings.token.ok_or_else(|| missing("token"))?,
+            },
+            Api::Lastfm => Credentials::LastFm {
+                api_key: settings.api_key.ok_or_else(|| missing("api_key"))?,
+                api_secret: settings
+                    .api_secret
+                    .ok_or_else(|| missing("api_secret"))?,
+                session_key: settings
+                    .session_key
+                    .ok_or_else(|| missing("session_key"))?,
+            },
+        };
+
+        let endpoint = settings.endpoint.unwrap_or_else(|| {
+            match settings.api {
+                Api::Listenbrainz => LISTENBRAINZ,
+                Api::Lastfm => LASTFM,
+            }
+            .to_string()
+        });
+
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(10)))
+            .build()
+            .into();
+
+        Ok(Self {
+            agent,
+            endpoint,
+            credentials,
+            spool: settings.spool.unwrap_or_else(|| {
+                dirs::state_dir().join("scrobble-spool.jsonl")
+            }),
+        })
+    }
+
+    pub(crate) fn now_playing(&self, track: &Track) -> eyre::Result<()> {
+        match &self.credentials {
+            Credentials::ListenBrainz { token } => self.listenbrainz(
+                token,
+                &json!({
+                    "listen_type": "playing_now",
+                    "payload": [{ "track_metadata": metadata(track) }],
+                }),
+            ),
+            Credentials::LastFm { .. } => {
+                self.lastfm("track.updateNowPlaying", track, None)
+            }
+        }
+    }
+
+    pub(crate) fn submit(&self, listen: &Listen) -> eyre::Result<()> {
+        match &self.credentials {
+            Credentials::ListenBrainz { token } => self.listenbrainz(
+                token,
+                &json!({
+                    "listen_type": "single",
+                    "payload": [{
+                        "listened_at": listen.listened_at,
+                        "track_metadata": metadata(&listen.track),
+                    }],
+                }),
+            ),
+            Credentials::LastFm { .. } => self.lastfm(
+                "track.scrobble",
+                &listen.track,
+                Some(listen.listened_at),
+            ),
+        }
+    }
+
+    /// Submits `listen`, spooling it when the service cannot be reached.
+    /// Returns whether it was delivered.
+    pub(crate) fn scrobble(&self, listen: Listen) -> eyre::Result<bool> {
+        match self.submit(&listen) {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                if let Err(spool_error) = self.spool(&[listen]) {
+                    return Err(eyre!(
+                        "scrobble failed ({e}) and could not be spooled: \
+                         {spool_error}"
+                    ));
+                }
+                println!("scrobble failed, spooled for later: {e}");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Retries every spooled listen, keeping those that still fail.
+    /// Returns the number delivered.
+    pub(crate) fn flush(&self) -> eyre::Result<usize> {
+        let listens = self.spooled()?;
+        if listens.is_empty() {
+            return Ok(0);
+        }
+
+        let total = listens.len();
+        let mut pending = Vec::new();
+        for listen in listens {
+            // once the service fails there is no point in trying the rest
+            if !pending.is_empty() || self.submit(&listen).is_err() {
+                pending.push(listen);
+            }
+        }
+
+        if pending.is_empty() {
+            fs::remove_file(&self.spool)?;
+        } else {
+            // the spool is only replaced once what is left is written, so
+            // that a failed write loses nothing
+            let mut temporary = self.spool.clone().into_os_string();
+            temporary.push(".tmp");
+            fs::write(&temporary, lines(&pending)?)?;
+            fs::rename(&temporary, &self.spool)?;
+        }
+
+        Ok(total - pending.len())
+    }
+
+    fn spooled(&self) -> eyre::Result<Vec<Listen>> {
+        if !self.spool.exists() {
+            return Ok(vec![]);
+        }
+
+        fs::read_to_string(&self.spool)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect::<eyre::Result<_>>()
+            .wrap_err(format!("corrupt spool: {}", self.spool.display()))
+    }
+
+    fn spool(&self, listens: &[Listen]) -> eyre::Result<()> {
+        if listens.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.spool.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spool)?;
+
+        Ok(file.write_all(lines(listens)?.as_bytes())?)
+    }
+
+    fn listenbrainz(
+        &self,
+        token: &str,
+        body: &serde_json::Value,
+    ) -> eyre::Result<()> {
+        let url =
+            format!("{}/1/submit-listens", self.endpoint.trim_end_matches('/'));
+
+        self.agent
+            .post(&url)
+            .header("Authorization", &format!("Token {token}"))
+            .send_json(body)?;
+
+        Ok(())
+    }
+
+    fn lastfm(
+        &self,
+        method: &str,
+        track: &Track,
+        timestamp: Option<i64>,
+    ) -> eyre::Result<()> {
+        let Credentials::LastFm {
+            api_key,
+            api_secret,
+            session_key,
+        } = &self.credentials
+        else {
+            unreachable!("lastfm called without lastfm credentials");
+        };
+
+        let mut params = BTreeMap::from([
+            ("method", method.to_string()),
+            ("artist", track.artist.clone()),
+            ("track", track.title.clone()),
+            ("api_key", api_key.clone()),
+            ("sk", session_key.clone()),
+        ]);
+        if !track.album.is_empty() {
+            params.insert("album", track.album.clone());
+        }
+        if track.duration > 0 {
+            params.insert("duration", track.duration.to_string());
+        }
+        if let Some(timestamp) = timestamp {
+            params.insert("timestamp", timestamp.to_string());
+        }
+        params.insert("api_sig", signature(&params, api_secret));
+        params.insert("format", "json".to_string());
+
+        self.agent.post(&self.endpoint).send_form(params)?;
+
+        Ok(())
+    }
+}
+
+fn metadata(track: &Track) -> serde_json::Value {
+    let mut metadata = json!({
+        "artist_name": track.artist,
+        "track_name": track.title,
+        "additional_info": {
+            "media_player": "MPD",
+            "submission_client": CLIENT,
+            "submission_client_version": VERSION,
+        },
+    });
+
+    if !track.album.is_empty() {
+        metadata["release_name"] = json!(track.album);
+    }
+    if track.duration > 0 {
+        metadata["additional_info"]["duration_ms"] =
+            json!(track.duration * 1000);
+    }
+
+    metadata
+}
+
+/// Last.fm request signature: md5 of the sorted parameters followed by the
+/// shared secret
+fn signature(params: &BTreeMap<&str, String>, secret: &str) -> String {
+    let mut input = String::new();
+    for (key, value) in params {
+        input.push_str(key);
+        input.push_str(value);
+    }
+    input.push_str(secret);
+
+    Md5::digest(input.as_bytes()).iter().fold(
+        String::with_capacity(32),
+        |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        },
+    )
+}
+
+fn describe(track: &Track) -> String {
+    format!("{} - {}", track.artist, track.title)
+}
+
+/// Reports `change`, logging failures so that scrobbling carries on
+fn handle(scrobbler: &Scrobbler, change: Change) {
+    match change {
+        Change::Started(track) => {
+            if track.artist.is_empty() || track.title.is_empty() {
+                return;
+            }
+
+            match scrobbler.now_playing(&track) {
+                Ok(()) => println!("now playing: {}", describe(&track)),
+                Err(e) => println!("now playing failed: {e}"),
+            }
+        }
+        Change::Finished(play) => {
+            if !play.counts()
+                || play.track.artist.is_empty()
+                || play.track.title.is_empty()
+            {
+                return;
+            }
+
+            let description = describe(&play.track);
+            let listen = Listen {
+                track: play.track,
+                listened_at: play.started_at,
+            };
+            match scrobbler.scrobble(listen) {
+                Ok(true) => println!("scrobbled: {description}"),
+                Ok(false) => return,
+                Err(e) => {
+                    println!("unable to scrobble {description}: {e}");
+                    return;
+                }
+            }
+
+            match scrobbler.flush() {
+                Ok(0) => {}
+                Ok(flushed) => {
+                    println!("scrobbled {flushed} spooled listen(s)");
+                }
+                Err(e) => println!("unable to flush spool: {e}"),
+            }
+        }
+    }
+}
+
+pub(crate) fn run(
+    mpd: &mut Client,
+    settings: Settings,
+) -> eyre::Result<Option<String>> {
+    let scrobbler = Scrobbler::new(settings)?;
+    let mut tracker = Tracker::default();
+
+    match scrobbler.flush() {
+        Ok(0) => {}
+        Ok(flushed) => println!("scrobbled {flushed} spooled listen(s)"),
+        Err(e) => println!("unable to flush spool: {e}"),
+    }
+
+    loop {
+        let status = mpd.status()?;
+        for change in tracker.update(&status, tracker::now()) {
+            handle(&scrobbler, change);
+        }
+
+        mpd.wait(&[Event::Player])?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    type Requests = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// A minimal HTTP server answering every request with `status`,
+    /// recording the headers and bodies it receives
+    fn stub(status: u16) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line
+                        .to_ascii_lowercase()
+                        .strip_prefix("content-length:")
+                    {
+                        length = value.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                }
+
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let body = String::from_utf8(body).unwrap();
+                recorded.lock().unwrap().push((request, body));
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} X\r\nContent-Length: 2\r\n\
+                     Connection: close\r\n\r\n{{}}"
+                )
+                .unwrap();
+            }
+        });
+
+        (address, requests)
+    }
+
+    fn listen() -> Listen {
+        Listen {
+            track: Track {
+                file: "a.flac".to_string(),
+                artist: "Phish".to_string(),
+                album: "Junta".to_string(),
+                title: "Fee".to_string(),
+                duration: 300,
+            },
+            listened_at: 1_700_000_000,
+        }
+    }
+
+    fn settings(endpoint: String, spool: PathBuf) -> Settings {
+        Settings {
+            endpoint: Some(endpoint),
+            token: Some("secret".to_string()),
+            spool: Some(spool),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_listenbrainz_submission() {
+        let dir = tempdir::TempDir::new("scrobble").unwrap();
+        let (endpoint, requests) = stub(200);
+        let scrobbler =
+            Scrobbler::new(settings(endpoint, dir.path().join("spool")))
+                .unwrap();
+
+        assert!(scrobbler.scrobble(listen()).unwrap());
+
+        let (head, body) = &requests.lock().unwrap()[0];
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        let payload = &body["payload"][0];
+        assert!(head.starts_with("POST /1/submit-listens "));
+        assert!(head.contains("authorization: Token secret"));
+        assert_eq!(body["listen_type"], "single");
+        assert_eq!(payload["listened_at"], 1_700_000_000);
+        assert_eq!(
+            payload["track_metadata"]["additional_info"]["duration_ms"],
+            300_000
+        );
+    }
+
+    #[test]
+    fn test_failed_listens_are_spooled_and_retried() {
+        let dir = tempdir::TempDir::new("scrobble").unwrap();
+        let spool = dir.path().join("spool");
+
+        let (down, _) = stub(503);
+        let scrobbler = Scrobbler::new(settings(down, spool.clone())).unwrap();
+        assert!(!scrobbler.scrobble(listen()).unwrap());
+        assert!(!scrobbler.scrobble(listen()).unwrap());
+        assert_eq!(scrobbler.spooled().unwrap(), vec![listen(), listen()]);
+
+        let (up, requests) = stub(200);
+        let scrobbler = Scrobbler::new(settings(up, spool.clone())).unwrap();
+        assert_eq!(scrobbler.flush().unwrap(), 2);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(!spool.exists());
+    }
+
+    #[test]
+    fn test_flush_keeps_what_is_still_undelivered() {
+        let dir = tempdir::TempDir::new("scrobble").unwrap();
+        let spool = dir.path().join("spool.jsonl");
+        let (down, _) = stub(503);
+        let scrobbler = Scrobbler::new(settings(down, spool.clone())).unwrap();
+        scrobbler.spool(&[listen(), listen()]).unwrap();
+
+        assert_eq!(scrobbler.flush().unwrap(), 0);
+        assert_eq!(scrobbler.spooled().unwrap(), vec![listen(), listen()]);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_spool_failures_do_not_stop_scrobbling() {
+        let dir = tempdir::TempDir::new("scrobble").unwrap();
+        // the spool can't be written inside a file
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let (down, requests) = stub(503);
+        let scrobbler =
+            Scrobbler::new(settings(down, file.join("spool"))).unwrap();
+        let listen = listen();
+        let play = || {
+            Change::Finished(tracker::Play {
+                track: listen.track.clone(),
+                started_at: listen.listened_at,
+                played: 300,
+                position: 300,
+            })
+        };
+
+        handle(&scrobbler, play());
+        handle(&scrobbler, play());
+
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_lastfm_signature() {
+        let params = BTreeMap::from([
+            ("method", "track.scrobble".to_string()),
+            ("api_key", "key".to_string()),
+        ]);
+
+        assert_eq!(
+            signature(&params, "secret"),
+            "d7a2d80e182cf1fea315ddc2d0bbfe44"
+        );
+    }
+}
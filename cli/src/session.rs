@@ -0,0 +1,156 @@
+//! Named snapshots of the whole player (queue, playback, modes, volume and
+//! outputs), kept as JSON in `$XDG_DATA_HOME/mp-cli/sessions`.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, WrapErr};
+use mpd_easy::{dirs, Client, Listing, Session};
+
+use crate::args::SessionCommand;
+
+pub(crate) struct Store {
+    directory: PathBuf,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new(dirs::data_dir().join("sessions"))
+    }
+}
+
+impl Store {
+    pub(crate) fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    fn path(&self, name: &str) -> eyre::Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains('/') {
+            return Err(eyre!("invalid session name: {name}"));
+        }
+
+        Ok(self.directory.join(format!("{name}.json")))
+    }
+
+    pub(crate) fn save(
+        &self,
+        name: &str,
+        session: &Session,
+    ) -> eyre::Result<()> {
+        let path = self.path(name)?;
+        fs::create_dir_all(&self.directory)?;
+
+        fs::write(&path, serde_json::to_vec_pretty(session)?)
+            .wrap_err(format!("unable to write session: {}", path.display()))
+    }
+
+    pub(crate) fn load(&self, name: &str) -> eyre::Result<Session> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Err(eyre!("unknown session: {name}"));
+        }
+
+        serde_json::from_slice(&fs::read(&path)?)
+            .wrap_err(format!("corrupt session: {}", path.display()))
+    }
+
+    /// Names of the saved sessions, sorted
+    pub(crate) fn names(&self) -> eyre::Result<Vec<String>> {
+        let Ok(entries) = fs::read_dir(&self.directory) else {
+            return Ok(vec![]);
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                names.extend(stem(&path));
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+}
+
+fn stem(path: &Path) -> Option<String> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(String::from)
+}
+
+pub(crate) fn run(
+    mpd: &mut Client,
+    command: SessionCommand,
+) -> eyre::Result<Option<String>> {
+    let store = Store::default();
+
+    match command {
+        SessionCommand::Save { name } => {
+            store.save(&name, &mpd.session()?)?;
+            Ok(Some(format!("saved: {name}")))
+        }
+        SessionCommand::Restore { name } => {
+            mpd.restore(&store.load(&name)?)?;
+            mpd.current_status()
+        }
+        SessionCommand::List => mpd.render(&Listing::from(store.names()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+
+    #[test]
+    fn test_save_and_restore() {
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                "status" => concat!(
+                    "volume: 30\nrepeat: 1\nrandom: 0\nsingle: oneshot\n",
+                    "consume: 0\nxfade: 5\nstate: play\nsong: 0\nsongid: 1\n",
+                    "playlistlength: 1\nelapsed: 61.000\n",
+                ),
+                "playlistinfo" => "file: book/01.mp3\nPos: 0\nId: 1\n",
+                "outputs" => concat!(
+                    "outputid: 0\noutputname: Speakers\noutputenabled: 1\n",
+                    "outputid: 1\noutputname: Stream\noutputenabled: 0\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let dir = tempdir::TempDir::new("sessions").unwrap();
+        let store = Store::new(dir.path().join("sessions"));
+        let mut mpd = server.client();
+
+        store.save("audiobook", &mpd.session().unwrap()).unwrap();
+        assert_eq!(store.names().unwrap(), ["audiobook"]);
+        assert!(store.save("../work", &mpd.session().unwrap()).is_err());
+        assert!(store.load("dinner").is_err());
+
+        mpd.restore(&store.load("audiobook").unwrap()).unwrap();
+        let commands = server.commands();
+        let start = commands.iter().position(|line| line == "clear").unwrap();
+        assert_eq!(
+            commands[start..start + 11],
+            [
+                "clear",
+                r#"add "book/01.mp3""#,
+                r#"random "0""#,
+                r#"repeat "1""#,
+                r#"single "oneshot""#,
+                r#"consume "0""#,
+                "crossfade 5",
+                "setvol 30",
+                "enableoutput 0",
+                "disableoutput 1",
+                "seek 0 61",
+            ]
+        );
+    }
+}
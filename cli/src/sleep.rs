@@ -0,0 +1,270 @@
+//! A sleep timer: waits for a while or for the end of the current song,
+//! album or queue, optionally fading the volume out first, then stops
+//! playback and puts the volume back where it was.
+use std::env;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mpd_easy::{ramp, Client, OnOff, State, Status, Time};
+
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Target {
+    After(u64),
+    EndOfSong,
+    EndOfAlbum,
+    EndOfQueue,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "end-of-song" => Ok(Target::EndOfSong),
+            "end-of-album" => Ok(Target::EndOfAlbum),
+            "end-of-queue" => Ok(Target::EndOfQueue),
+            _ => parse_seconds(input).map(Target::After),
+        }
+    }
+}
+
+/// Parses a duration such as `90`, `90s`, `30m` or `1h30m` into seconds
+pub(crate) fn parse_seconds(input: &str) -> Result<u64, String> {
+    let time: Time = input.parse().map_err(|e: eyre::Report| e.to_string())?;
+
+    u64::try_from(time.as_secs())
+        .map_err(|_| format!("invalid duration: {input} (must be positive)"))
+}
+
+fn volume(status: &Status) -> Option<u8> {
+    status.volume.parse().ok()
+}
+
+/// Queue position of the last song to play before stopping
+fn last_position(
+    mpd: &mut Client,
+    target: Target,
+    status: &Status,
+) -> eyre::Result<u32> {
+    match target {
+        Target::After(_) | Target::EndOfSong => Ok(status.position),
+        Target::EndOfQueue => Ok(status.queue_count.saturating_sub(1)),
+        Target::EndOfAlbum => {
+            let albums: Vec<Option<String>> = mpd
+                .songs(None)?
+                .into_iter()
+                .map(|song| {
+                    song.tags
+                        .into_iter()
+                        .find(|(tag, _)| tag == "Album")
+                        .map(|(_, album)| album)
+                })
+                .collect();
+            let start = status.position as usize;
+            let current = albums.get(start).cloned().flatten();
+            let length = albums
+                .iter()
+                .skip(start)
+                .take_while(|album| **album == current)
+                .count();
+
+            Ok(status.position + u32::try_from(length.max(1) - 1)?)
+        }
+    }
+}
+
+/// Starts the same command again in the background, in a session of its
+/// own so that closing the terminal doesn't hang it up
+fn detach() -> eyre::Result<Option<String>> {
+    let args = env::args_os().skip(1).filter(|arg| arg != "--detach");
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // SAFETY: setsid is async-signal-safe and touches no memory of the
+    // parent; it can't fail in a freshly forked child, which never leads
+    // a process group
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+
+    Ok(Some(format!(
+        "sleep timer running in the background (pid {})",
+        child.id()
+    )))
+}
+
+pub(crate) fn run(
+    mpd: &mut Client,
+    target: Target,
+    fade: Option<u64>,
+    detach_from_terminal: bool,
+) -> eyre::Result<Option<String>> {
+    if detach_from_terminal {
+        return detach();
+    }
+
+    let started = Instant::now();
+    let fade = fade.unwrap_or(0);
+    let status = mpd.status()?;
+    let was_single = status.single == OnOff::On;
+    let last = last_position(mpd, target, &status)?;
+    let mut armed = false;
+    // the volume when the fade started, put back once playback stopped
+    let mut fading_from = None;
+
+    loop {
+        let status = mpd.status()?;
+
+        let remaining = match target {
+            Target::After(seconds) => {
+                seconds.saturating_sub(started.elapsed().as_secs())
+            }
+            _ if status.state == State::Stop => 0,
+            _ if status.position >= last => {
+                if !armed {
+                    armed = true;
+                    mpd.stop_after_current()?;
+                }
+
+                u64::try_from(
+                    status.track_length.as_secs() - status.elapsed.as_secs(),
+                )
+                .unwrap_or(0)
+            }
+            _ => u64::MAX,
+        };
+
+        if remaining == 0 {
+            break;
+        }
+
+        // until the fade, the volume is left to the user
+        if remaining <= fade {
+            let current = volume(&status);
+            if fading_from.is_none() {
+                fading_from = current;
+            }
+            if let (Some(from), Some(current)) = (fading_from, current) {
+                // only ever lower it, in case the user turned it down
+                let level = ramp(
+                    from,
+                    0,
+                    Duration::from_secs(fade - remaining),
+                    Duration::from_secs(fade),
+                );
+                if level < current {
+                    mpd.set_volume(&level.to_string())?;
+                }
+            }
+        }
+
+        thread::sleep(TICK);
+    }
+
+    if matches!(target, Target::After(_)) {
+        mpd.pause()?;
+    } else {
+        // wait for the end of the song to stop playback
+        while mpd.status()?.state == State::Play {
+            thread::sleep(TICK);
+        }
+    }
+
+    if armed {
+        mpd.single(Some(OnOff::from(was_single)))?;
+    }
+    if let Some(from) = fading_from {
+        mpd.set_volume(&from.to_string())?;
+    }
+
+    mpd.current_status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!("end-of-album".parse(), Ok(Target::EndOfAlbum));
+        assert_eq!("45".parse(), Ok(Target::After(45)));
+        assert_eq!("1h30m".parse(), Ok(Target::After(5400)));
+        assert_eq!("90s".parse(), Ok(Target::After(90)));
+        assert!("30x".parse::<Target>().is_err());
+        assert!("m".parse::<Target>().is_err());
+        assert!("1h3".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn test_volume_changed_during_the_timer_is_kept() {
+        let asked = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&asked);
+        let server = FakeServer::start(move |line| {
+            let body = match line {
+                // the user turns the volume down after the first look
+                "status" if flag.swap(true, Ordering::SeqCst) => {
+                    "volume: 30\nstate: play\nsong: 0\nsongid: 1\n"
+                }
+                "status" => "volume: 50\nstate: play\nsong: 0\nsongid: 1\n",
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+
+        run(&mut client, Target::After(2), Some(1), false).unwrap();
+
+        let commands = server.commands();
+        assert!(!commands.contains(&"setvol \"50\"".to_string()));
+        assert!(commands.contains(&"setvol \"30\"".to_string()));
+    }
+
+    #[test]
+    fn test_end_of_song_uses_oneshot_and_restores() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stopped);
+        let server = FakeServer::start(move |line| {
+            let body = match line {
+                "status" if flag.load(Ordering::SeqCst) => {
+                    "volume: 50\nstate: stop\nplaylistlength: 1\n"
+                }
+                "status" => concat!(
+                    "volume: 50\nstate: play\nsong: 0\nsongid: 1\n",
+                    "playlistlength: 1\ntime: 10:300\n",
+                ),
+                "single \"oneshot\"" => {
+                    flag.store(true, Ordering::SeqCst);
+                    ""
+                }
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+
+        run(&mut client, Target::EndOfSong, None, false).unwrap();
+
+        let commands = server.commands();
+        assert!(commands.contains(&"single \"oneshot\"".to_string()));
+        assert!(commands.contains(&"single \"0\"".to_string()));
+        // without a fade the volume is left alone
+        assert!(!commands.iter().any(|line| line.starts_with("setvol")));
+    }
+}
@@ -0,0 +1,693 @@
+//! Smart playlists: the songs matching rules such as
+//! `genre == "Jazz" AND date >= 1960 AND rating >= 4 AND not played in 30d`,
+//! defined in `[smart.<name>]` sections of the config file or in
+//! `$XDG_CONFIG_HOME/mp-cli/smart/<name>.toml`.
+//!
+//! Rules over tags become an MPD filter where MPD can evaluate them; numeric
+//! comparisons, stickers (any name that isn't a tag, eg. `rating`) and the
+//! play history are evaluated here.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use chrono::Local;
+use eyre::{eyre, WrapErr};
+use mpd_easy::{dirs, random, Client, Listing, Song, Time};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::args::SmartCommand;
+use crate::config::Config;
+use crate::history::{self, Filter, Store};
+
+/// Tags MPD knows, any other name in a rule is a sticker
+const TAGS: &[&str] = &[
+    "file",
+    "artist",
+    "artistsort",
+    "album",
+    "albumsort",
+    "albumartist",
+    "albumartistsort",
+    "title",
+    "titlesort",
+    "track",
+    "name",
+    "genre",
+    "mood",
+    "date",
+    "originaldate",
+    "composer",
+    "composersort",
+    "performer",
+    "conductor",
+    "work",
+    "movement",
+    "movementnumber",
+    "grouping",
+    "comment",
+    "disc",
+    "label",
+];
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Definition {
+    pub(crate) rules: String,
+    /// `random`, or a tag optionally followed by `desc` (eg. `date desc`)
+    pub(crate) sort: Option<String>,
+    /// A number of songs (eg. 50) or a duration (eg. 3h)
+    #[serde(default, deserialize_with = "deserialize_limit")]
+    pub(crate) limit: Option<String>,
+}
+
+fn deserialize_limit<'de, D>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Limit {
+        Songs(u64),
+        Text(String),
+    }
+
+    Ok(Some(match Limit::deserialize(deserializer)? {
+        Limit::Songs(songs) => songs.to_string(),
+        Limit::Text(text) => text,
+    }))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+    Contains,
+    Regex,
+    NotRegex,
+}
+
+impl Op {
+    fn parse(token: &str) -> Option<Self> {
+        Some(match token {
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            ">=" => Op::Ge,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            "<" => Op::Lt,
+            "=~" => Op::Regex,
+            "!~" => Op::NotRegex,
+            _ if token.eq_ignore_ascii_case("contains") => Op::Contains,
+            _ => return None,
+        })
+    }
+
+    /// The operator in an MPD filter, `None` when MPD can't compare this way
+    fn filter(self) -> Option<&'static str> {
+        match self {
+            Op::Eq => Some("=="),
+            Op::Ne => Some("!="),
+            Op::Contains => Some("contains"),
+            Op::Regex => Some("=~"),
+            Op::NotRegex => Some("!~"),
+            Op::Ge | Op::Le | Op::Gt | Op::Lt => None,
+        }
+    }
+
+    fn holds(self, actual: &str, expected: &str) -> eyre::Result<bool> {
+        let ordering = compare(actual, expected);
+
+        Ok(match self {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Contains => {
+                actual.to_lowercase().contains(&expected.to_lowercase())
+            }
+            Op::Regex | Op::NotRegex => {
+                return Err(eyre!("regular expressions only work on tags"))
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Rule {
+    Tag {
+        tag: String,
+        op: Op,
+        value: String,
+    },
+    Sticker {
+        name: String,
+        op: Op,
+        value: String,
+    },
+    /// Played at all, or since the given unix time
+    Played(Option<i64>),
+    Not(Box<Rule>),
+}
+
+impl Rule {
+    /// The rule as an MPD filter, `None` when it is evaluated here
+    fn filter(&self) -> Option<String> {
+        match self {
+            Rule::Tag { tag, op, value } => {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                Some(format!("({tag} {} \"{value}\")", op.filter()?))
+            }
+            Rule::Not(rule) => Some(format!("(!{})", rule.filter()?)),
+            Rule::Sticker { .. } | Rule::Played(_) => None,
+        }
+    }
+
+    fn sticker(&self) -> Option<&str> {
+        match self {
+            Rule::Sticker { name, .. } => Some(name),
+            Rule::Not(rule) => rule.sticker(),
+            Rule::Tag { .. } | Rule::Played(_) => None,
+        }
+    }
+
+    fn uses_history(&self) -> bool {
+        match self {
+            Rule::Played(_) => true,
+            Rule::Not(rule) => rule.uses_history(),
+            Rule::Tag { .. } | Rule::Sticker { .. } => false,
+        }
+    }
+
+    fn holds(&self, song: &Song, facts: &Facts) -> eyre::Result<bool> {
+        match self {
+            Rule::Tag { tag, op, value } => Ok(match tag_value(song, tag) {
+                Some(actual) => op.holds(actual, value)?,
+                None => false,
+            }),
+            Rule::Sticker { name, op, value } => Ok(
+                match facts
+                    .stickers
+                    .get(name)
+                    .and_then(|by_file| by_file.get(&song.file))
+                {
+                    Some(actual) => op.holds(actual, value)?,
+                    None => false,
+                },
+            ),
+            Rule::Played(since) => Ok(facts
+                .played
+                .get(&song.file)
+                .is_some_and(|last| since.is_none_or(|since| *last >= since))),
+            Rule::Not(rule) => Ok(!rule.holds(song, facts)?),
+        }
+    }
+}
+
+/// What the rules evaluated here look at besides the songs' tags
+#[derive(Default)]
+struct Facts {
+    /// Sticker values by sticker name and file
+    stickers: HashMap<String, HashMap<String, String>>,
+    /// When each file was last played
+    played: HashMap<String, i64>,
+}
+
+/// Compares numerically when both sides start with a number (so that a
+/// date of `1965-03-02` is at least `1960`), as text otherwise
+fn compare(actual: &str, expected: &str) -> Ordering {
+    let number = |text: &str| -> Option<f64> {
+        let end = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(text.len());
+        text[..end].parse().ok()
+    };
+
+    match (number(actual), number(expected)) {
+        (Some(actual), Some(expected)) => actual.total_cmp(&expected),
+        _ => actual.to_lowercase().cmp(&expected.to_lowercase()),
+    }
+}
+
+fn tag_value<'a>(song: &'a Song, tag: &str) -> Option<&'a str> {
+    match tag {
+        "file" => Some(&song.file),
+        "artist" => song.artist.as_deref(),
+        "title" => song.title.as_deref(),
+        "name" => song.name.as_deref(),
+        _ => song
+            .tags
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+            .map(|(_, value)| value.as_str()),
+    }
+}
+
+/// Splits rules into words, quoted strings and operators
+fn tokens(input: &str) -> eyre::Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut token = String::new();
+            loop {
+                match chars.next() {
+                    Some('\\') => token.extend(chars.next()),
+                    Some(next) if next == c => break,
+                    Some(next) => token.push(next),
+                    None => return Err(eyre!("unterminated quote: {input}")),
+                }
+            }
+            // marks the token as a value so that `"and"` isn't a keyword
+            tokens.push(format!("\"{token}"));
+        } else if "=!<>~".contains(c) {
+            let mut token = String::new();
+            while let Some(&next) = chars.peek() {
+                if !"=!<>~".contains(next) {
+                    break;
+                }
+                token.push(next);
+                chars.next();
+            }
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            while let Some(&next) = chars.peek() {
+                if next.is_whitespace() || "\"'=!<>~".contains(next) {
+                    break;
+                }
+                token.push(next);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses `condition AND condition ...`, where a condition is
+/// `[not] <tag or sticker> <op> <value>` or `[not] played [in <age>]`
+fn parse(rules: &str, now: chrono::DateTime<Local>) -> eyre::Result<Vec<Rule>> {
+    let tokens = tokens(rules)?;
+    let mut tokens = tokens.iter().map(String::as_str).peekable();
+    let mut parsed = Vec::new();
+
+    loop {
+        let mut negated = false;
+        while tokens
+            .next_if(|token| token.eq_ignore_ascii_case("not"))
+            .is_some()
+        {
+            negated = !negated;
+        }
+
+        let name = tokens
+            .next()
+            .ok_or_else(|| eyre!("missing condition: {rules}"))?
+            .to_lowercase();
+        let rule = if name == "played" {
+            let since = if tokens
+                .next_if(|token| token.eq_ignore_ascii_case("in"))
+                .is_some()
+            {
+                let age = tokens
+                    .next()
+                    .ok_or_else(|| eyre!("missing age: {rules}"))?;
+                Some(history::parse_time(age, now)?)
+            } else {
+                None
+            };
+            Rule::Played(since)
+        } else {
+            let op = tokens.next().and_then(Op::parse).ok_or_else(|| {
+                eyre!("missing operator after {name}: {rules}")
+            })?;
+            let value = tokens
+                .next()
+                .ok_or_else(|| eyre!("missing value after {name}: {rules}"))?;
+            let value = value.strip_prefix('"').unwrap_or(value).to_string();
+
+            if TAGS.contains(&name.as_str()) {
+                Rule::Tag {
+                    tag: name,
+                    op,
+                    value,
+                }
+            } else {
+                Rule::Sticker { name, op, value }
+            }
+        };
+
+        parsed.push(if negated {
+            Rule::Not(Box::new(rule))
+        } else {
+            rule
+        });
+
+        match tokens.next() {
+            None => return Ok(parsed),
+            Some(token) if token.eq_ignore_ascii_case("and") => {}
+            Some(token) => {
+                return Err(eyre!("expected AND instead of {token}: {rules}"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Limit {
+    Songs(usize),
+    Seconds(u64),
+}
+
+impl Limit {
+    fn parse(input: &str) -> eyre::Result<Self> {
+        if let Ok(songs) = input.parse() {
+            return Ok(Limit::Songs(songs));
+        }
+
+        let time: Time = input.parse()?;
+        u64::try_from(time.as_secs())
+            .map(Limit::Seconds)
+            .map_err(|_| eyre!("invalid limit: {input}"))
+    }
+
+    fn apply(&self, songs: &mut Vec<Song>) {
+        match self {
+            Limit::Songs(count) => songs.truncate(*count),
+            Limit::Seconds(seconds) => {
+                let mut total = 0;
+                let fits = songs
+                    .iter()
+                    .take_while(|song| {
+                        total += song.duration.map_or(0, |d| d.as_secs());
+                        total <= *seconds
+                    })
+                    .count();
+                songs.truncate(fits);
+            }
+        }
+    }
+}
+
+fn sort(songs: &mut [Song], order: &str) -> eyre::Result<()> {
+    let mut words = order.split_whitespace();
+    let tag = words.next().unwrap_or_default().to_lowercase();
+    let descending = match words.next() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(_) => return Err(eyre!("invalid sort: {order}")),
+    };
+
+    if tag == "random" {
+        random::shuffle(songs);
+        return Ok(());
+    }
+
+    // songs without the tag go last either way
+    songs.sort_by(|a, b| match (tag_value(a, &tag), tag_value(b, &tag)) {
+        (Some(a), Some(b)) if descending => compare(b, a),
+        (Some(a), Some(b)) => compare(a, b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    });
+
+    Ok(())
+}
+
+/// The songs of a smart playlist, in order and within its limit
+fn songs(
+    mpd: &mut Client,
+    store: &Store,
+    definition: &Definition,
+) -> eyre::Result<Vec<Song>> {
+    let rules = parse(&definition.rules, Local::now())?;
+    let (filters, local): (Vec<&Rule>, Vec<&Rule>) =
+        rules.iter().partition(|rule| rule.filter().is_some());
+    let filters: Vec<String> =
+        filters.iter().filter_map(|rule| rule.filter()).collect();
+    let expression = match filters.len() {
+        0 => None,
+        1 => Some(filters[0].clone()),
+        _ => Some(format!("({})", filters.join(" AND "))),
+    };
+
+    let mut facts = Facts::default();
+    for name in local.iter().filter_map(|rule| rule.sticker()) {
+        if !facts.stickers.contains_key(name) {
+            facts.stickers.insert(name.to_string(), mpd.stickers(name)?);
+        }
+    }
+    if local.iter().any(|rule| rule.uses_history()) {
+        for entry in store.entries(&Filter::default())? {
+            let last = facts.played.entry(entry.file).or_default();
+            *last = (*last).max(entry.played_at);
+        }
+    }
+
+    let mut songs = Vec::new();
+    for song in mpd.filter(expression.as_deref())? {
+        if local.iter().try_fold(true, |holds, rule| {
+            Ok::<_, eyre::Report>(holds && rule.holds(&song, &facts)?)
+        })? {
+            songs.push(song);
+        }
+    }
+
+    if let Some(order) = &definition.sort {
+        sort(&mut songs, order)?;
+    }
+    if let Some(limit) = &definition.limit {
+        Limit::parse(limit)?.apply(&mut songs);
+    }
+
+    Ok(songs)
+}
+
+/// The smart playlists of the config file and of the `smart` directory,
+/// the config file winning when both define the same name
+pub(crate) fn definitions(
+    config: Config,
+    directory: &Path,
+) -> eyre::Result<BTreeMap<String, Definition>> {
+    let mut definitions = config.smart;
+
+    let Ok(files) = fs::read_dir(directory) else {
+        return Ok(definitions);
+    };
+
+    for file in files {
+        let path = file?.path();
+        if path.extension().is_none_or(|extension| extension != "toml") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if definitions.contains_key(name) {
+            continue;
+        }
+
+        let contents = fs::read_to_string(&path)?;
+        let definition = toml::from_str(&contents)
+            .wrap_err(format!("invalid smart playlist: {}", path.display()))?;
+        definitions.insert(name.to_string(), definition);
+    }
+
+    Ok(definitions)
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Definitions(BTreeMap<String, Definition>);
+
+impl fmt::Display for Definitions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .0
+            .iter()
+            .map(|(name, definition)| format!("{name}: {}", definition.rules))
+            .collect();
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+pub(crate) fn run(
+    mpd: &mut Client,
+    command: SmartCommand,
+) -> eyre::Result<Option<String>> {
+    let mut definitions =
+        definitions(Config::load()?, &dirs::config_dir().join("smart"))?;
+    let name = match &command {
+        SmartCommand::List => return mpd.render(&Definitions(definitions)),
+        SmartCommand::Show { name }
+        | SmartCommand::Load { name }
+        | SmartCommand::SaveAs { name, .. } => name.clone(),
+    };
+    let definition = definitions
+        .remove(&name)
+        .ok_or_else(|| eyre!("unknown smart playlist: {name}"))?;
+    let songs = songs(mpd, &Store::default(), &definition)?;
+
+    match command {
+        SmartCommand::List => Ok(None),
+        SmartCommand::Show { .. } => mpd.render(&Listing::from(songs)),
+        SmartCommand::Load { .. } => {
+            mpd.enqueue(&songs)?;
+            Ok(Some(format!("loading: {name}")))
+        }
+        SmartCommand::SaveAs { playlist, .. } => {
+            let playlist = playlist.unwrap_or(name);
+            mpd.save_songs(&playlist, &songs)?;
+            Ok(Some(format!("saved: {playlist}")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Entry;
+    use mpd_easy::testing::FakeServer;
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = parse(
+            r#"genre == "Jazz" AND date >= 1960 AND rating >= 4
+            AND not played in 30d AND NOT artist contains 'Kenny G'"#,
+            Local::now(),
+        )
+        .unwrap();
+        let filters: Vec<Option<String>> =
+            rules.iter().map(Rule::filter).collect();
+
+        assert_eq!(
+            filters,
+            [
+                Some(r#"(genre == "Jazz")"#.to_string()),
+                None,
+                None,
+                None,
+                Some(r#"(!(artist contains "Kenny G"))"#.to_string()),
+            ]
+        );
+        assert_eq!(rules[2].sticker(), Some("rating"));
+        assert!(rules[3].uses_history());
+
+        assert!(parse("genre Jazz", Local::now()).is_err());
+        assert!(parse("genre == Jazz OR date > 1960", Local::now()).is_err());
+        assert!(parse(r#"genre == "Jazz"#, Local::now()).is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        let song = |seconds| Song {
+            duration: Some(std::time::Duration::from_secs(seconds)),
+            ..Default::default()
+        };
+        let mut songs = vec![song(3000), song(3000), song(3000), song(3000)];
+
+        assert_eq!(Limit::parse("50").unwrap(), Limit::Songs(50));
+        assert_eq!(Limit::parse("3h").unwrap(), Limit::Seconds(3 * 3600));
+        assert!(Limit::parse("soon").is_err());
+
+        Limit::parse("3h").unwrap().apply(&mut songs);
+        assert_eq!(songs.len(), 3);
+    }
+
+    #[test]
+    fn test_songs_evaluate_stickers_and_history() {
+        let dir = tempdir::TempDir::new("smart").unwrap();
+        let store = Store::new(dir.path().join("history.jsonl"));
+        store
+            .append(&Entry {
+                played_at: Local::now().timestamp(),
+                file: "b.flac".to_string(),
+                artist: String::new(),
+                album: String::new(),
+                title: String::new(),
+                duration: 0,
+                elapsed: 0,
+                skipped: false,
+            })
+            .unwrap();
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                r#"find "(genre == \"Jazz\")""# => concat!(
+                    "file: a.flac\nDate: 1959\n",
+                    "file: b.flac\nDate: 1964\n",
+                    "file: c.flac\nDate: 1965-03-02\n",
+                    "file: d.flac\nDate: 1970\n",
+                    "file: e.flac\nDate: 1975\n",
+                ),
+                r#"sticker find "song" "" "rating""# => concat!(
+                    "file: a.flac\nsticker: rating=5\n",
+                    "file: b.flac\nsticker: rating=5\n",
+                    "file: c.flac\nsticker: rating=4\n",
+                    "file: d.flac\nsticker: rating=2\n",
+                    "file: e.flac\nsticker: rating=4\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut mpd = server.client();
+        let definition = Definition {
+            rules: r#"genre == "Jazz" AND date >= 1960 AND rating >= 4
+                AND not played in 30d"#
+                .to_string(),
+            sort: Some("date desc".to_string()),
+            limit: Some("1".to_string()),
+        };
+
+        let files = |songs: Vec<Song>| -> Vec<String> {
+            songs.into_iter().map(|song| song.file).collect()
+        };
+
+        assert_eq!(
+            files(songs(&mut mpd, &store, &definition).unwrap()),
+            ["e.flac"]
+        );
+        let all = Definition {
+            limit: None,
+            ..definition
+        };
+        assert_eq!(
+            files(songs(&mut mpd, &store, &all).unwrap()),
+            ["e.flac", "c.flac"]
+        );
+    }
+
+    #[test]
+    fn test_definitions_from_config_and_files() {
+        let dir = tempdir::TempDir::new("smart").unwrap();
+        fs::write(dir.path().join("jazz.toml"), "rules = 'genre == x'")
+            .unwrap();
+        fs::write(dir.path().join("rock.toml"), "rules = 'genre == Rock'")
+            .unwrap();
+        let config = Config::parse(
+            "[smart.jazz]\nrules = 'genre == Jazz'\nsort = 'random'\nlimit = 50",
+        )
+        .unwrap();
+
+        let definitions = definitions(config, dir.path()).unwrap();
+
+        assert_eq!(definitions["jazz"].rules, "genre == Jazz");
+        assert_eq!(definitions["jazz"].limit.as_deref(), Some("50"));
+        assert_eq!(definitions["rock"].rules, "genre == Rock");
+    }
+}
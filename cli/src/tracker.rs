@@ -0,0 +1,297 @@
+//! Follows the player through successive status snapshots and reports when a
+//! song starts and, once it is left, how long it was actually listened to.
+//!
+//! Listening time is measured by the wall clock while the player is in the
+//! `play` state so that seeking does not count as listening.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mpd_easy::{State, Status};
+use serde::{Deserialize, Serialize};
+
+/// Songs shorter than this are never scrobbled
+const MINIMUM_LENGTH: u64 = 30;
+/// Listening for this long always counts, however long the song is
+const ENOUGH_PLAYED: u64 = 240;
+/// Stopping this close to the end of a song is not a skip (eg. crossfade)
+const SKIP_GRACE: u64 = 10;
+/// A song seen again this close to its start after more than this many
+/// seconds of listening is treated as a fresh play (eg. single repeat)
+const RESTART_WINDOW: i64 = 3;
+const RESTART_AFTER: i64 = 10;
+
+/// The current unix timestamp
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| i64::try_from(since.as_secs()).unwrap_or(0))
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Track {
+    pub(crate) file: String,
+    pub(crate) artist: String,
+    pub(crate) album: String,
+    pub(crate) title: String,
+    /// Length of the song in seconds, 0 when unknown (eg. streams)
+    pub(crate) duration: u64,
+}
+
+impl Track {
+    fn from_status(status: &Status) -> Option<Self> {
+        let file = status.file_path.clone()?;
+
+        Some(Self {
+            file,
+            artist: status.artist.clone(),
+            album: status.album.clone(),
+            title: status.title.clone(),
+            duration: u64::try_from(status.track_length.as_secs()).unwrap_or(0),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Play {
+    pub(crate) track: Track,
+    /// Unix timestamp of when the song started playing
+    pub(crate) started_at: i64,
+    /// Seconds actually spent playing the song
+    pub(crate) played: u64,
+    /// Position within the song when it was left
+    pub(crate) position: u64,
+}
+
+impl Play {
+    /// The common scrobbling rule: a song longer than 30 seconds counts once
+    /// half of it, or four minutes, has been played.
+    pub(crate) fn counts(&self) -> bool {
+        let duration = self.track.duration;
+
+        if duration == 0 {
+            return self.played >= ENOUGH_PLAYED;
+        }
+
+        duration > MINIMUM_LENGTH
+            && self.played >= (duration / 2).min(ENOUGH_PLAYED)
+    }
+
+    /// Whether the song was left before reaching its end
+    pub(crate) fn skipped(&self) -> bool {
+        self.track.duration > 0
+            && self.position + SKIP_GRACE < self.track.duration
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Change {
+    Started(Track),
+    Finished(Play),
+}
+
+struct Current {
+    track: Track,
+    started_at: i64,
+    played: u64,
+    /// When playback last (re)started, `None` while paused
+    playing_since: Option<i64>,
+    elapsed: i64,
+    seen_at: i64,
+}
+
+impl Current {
+    fn expected_elapsed(&self, now: i64) -> i64 {
+        match self.playing_since {
+            Some(_) => self.elapsed + (now - self.seen_at),
+            None => self.elapsed,
+        }
+    }
+
+    fn settle(&mut self, now: i64) {
+        if let Some(since) = self.playing_since.take() {
+            self.played += u64::try_from(now - since).unwrap_or(0);
+        }
+    }
+
+    fn finish(mut self, now: i64) -> Play {
+        let position = u64::try_from(self.expected_elapsed(now)).unwrap_or(0);
+        self.settle(now);
+
+        let (played, position) = if self.track.duration > 0 {
+            (
+                self.played.min(self.track.duration),
+                position.min(self.track.duration),
+            )
+        } else {
+            (self.played, position)
+        };
+
+        Play {
+            track: self.track,
+            started_at: self.started_at,
+            played,
+            position,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Tracker {
+    current: Option<Current>,
+}
+
+impl Tracker {
+    /// Feeds the latest status (taken at unix time `now`) to the tracker.
+    pub(crate) fn update(&mut self, status: &Status, now: i64) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let elapsed = status.elapsed.as_secs();
+        let track = if status.state == State::Stop {
+            None
+        } else {
+            Track::from_status(status)
+        };
+
+        let same = match (&self.current, &track) {
+            (Some(current), Some(track)) => {
+                current.track.file == track.file
+                    && !(elapsed < RESTART_WINDOW
+                        && current.expected_elapsed(now) > RESTART_AFTER)
+            }
+            _ => false,
+        };
+
+        if !same {
+            if let Some(current) = self.current.take() {
+                changes.push(Change::Finished(current.finish(now)));
+            }
+
+            if let Some(track) = track {
+                changes.push(Change::Started(track.clone()));
+                self.current = Some(Current {
+                    track,
+                    started_at: now - elapsed,
+                    played: u64::try_from(elapsed).unwrap_or(0),
+                    playing_since: None,
+                    elapsed,
+                    seen_at: now,
+                });
+            }
+        }
+
+        if let Some(current) = self.current.as_mut() {
+            current.settle(now);
+            if status.state == State::Play {
+                current.playing_since = Some(now);
+            }
+            current.elapsed = elapsed;
+            current.seen_at = now;
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use mpd_easy::OnOff;
+
+    pub(crate) fn status(
+        state: State,
+        file: &str,
+        elapsed: u64,
+        length: u64,
+    ) -> Status {
+        Status {
+            volume: "100".to_string(),
+            state,
+            artist: "Phish".to_string(),
+            album: "Junta".to_string(),
+            title: file.to_string(),
+            position: 0,
+            queue_count: 2,
+            elapsed: elapsed.into(),
+            track_length: length.into(),
+            #[allow(clippy::cast_precision_loss)]
+            elapsed_secs: elapsed as f64,
+            #[allow(clippy::cast_precision_loss)]
+            track_length_secs: length as f64,
+            repeat: OnOff::Off,
+            random: OnOff::Off,
+            single: OnOff::Off,
+            consume: OnOff::Off,
+            file_path: Some(file.to_string()),
+        }
+    }
+
+    fn finished(changes: Vec<Change>) -> Play {
+        changes
+            .into_iter()
+            .find_map(|change| match change {
+                Change::Finished(play) => Some(play),
+                Change::Started(_) => None,
+            })
+            .expect("a finished play")
+    }
+
+    #[test]
+    fn test_counts_half_or_four_minutes() {
+        let play = |duration, played| Play {
+            track: Track {
+                duration,
+                ..Default::default()
+            },
+            started_at: 0,
+            played,
+            position: played,
+        };
+
+        assert!(play(200, 100).counts());
+        assert!(!play(200, 99).counts());
+        assert!(play(1200, 240).counts());
+        assert!(!play(20, 20).counts());
+        assert!(play(0, 240).counts());
+    }
+
+    #[test]
+    fn test_wall_clock_listening_excludes_pauses() {
+        let mut tracker = Tracker::default();
+
+        let started = tracker.update(&status(State::Play, "a", 0, 300), 1000);
+        assert!(matches!(started[..], [Change::Started(_)]));
+
+        tracker.update(&status(State::Pause, "a", 60, 300), 1060);
+        tracker.update(&status(State::Play, "a", 60, 300), 2000);
+        let play =
+            finished(tracker.update(&status(State::Play, "b", 0, 300), 2090));
+
+        assert_eq!(play.track.file, "a");
+        assert_eq!(play.started_at, 1000);
+        assert_eq!(play.played, 150);
+        assert!(play.counts());
+    }
+
+    #[test]
+    fn test_repeated_song_is_a_new_play() {
+        let mut tracker = Tracker::default();
+
+        tracker.update(&status(State::Play, "a", 0, 100), 0);
+        let changes = tracker.update(&status(State::Play, "a", 1, 100), 101);
+
+        let play = finished(changes);
+        assert_eq!(play.played, 100);
+        assert!(!play.skipped());
+    }
+
+    #[test]
+    fn test_stop_finishes_the_play() {
+        let mut tracker = Tracker::default();
+
+        tracker.update(&status(State::Play, "a", 30, 100), 0);
+        let play =
+            finished(tracker.update(&status(State::Stop, "a", 0, 0), 10));
+
+        assert_eq!(play.played, 40);
+        assert_eq!(play.position, 40);
+        assert!(play.skipped());
+    }
+}
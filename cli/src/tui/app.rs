@@ -0,0 +1,509 @@
+use mpd_easy::{Client, Event, OnOff, State, Status, Time};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::widgets::ListState;
+
+const SEEK_STEP: i64 = 5;
+const VOLUME_STEP: &str = "5";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Pane {
+    Queue,
+    Library,
+    Playlists,
+}
+
+impl Pane {
+    pub(crate) const ALL: [Pane; 3] =
+        [Pane::Queue, Pane::Library, Pane::Playlists];
+
+    pub(crate) fn title(self) -> &'static str {
+        match self {
+            Pane::Queue => "Queue",
+            Pane::Library => "Library",
+            Pane::Playlists => "Playlists",
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Pane::Queue => Pane::Library,
+            Pane::Library => Pane::Playlists,
+            Pane::Playlists => Pane::Queue,
+        }
+    }
+
+    fn prev(self) -> Self {
+        match self {
+            Pane::Queue => Pane::Playlists,
+            Pane::Library => Pane::Queue,
+            Pane::Playlists => Pane::Library,
+        }
+    }
+}
+
+/// How the library pane is browsed: by directory (`ls`) or by artist (`list`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LibraryMode {
+    Files,
+    Artists,
+}
+
+pub(crate) struct Row {
+    pub(crate) position: u32,
+    pub(crate) artist: String,
+    pub(crate) title: String,
+    pub(crate) seconds: u64,
+}
+
+pub(crate) struct App<'a> {
+    client: &'a mut Client,
+    pub(crate) status: Option<Status>,
+    pub(crate) queue: Vec<Row>,
+    pub(crate) library: Vec<String>,
+    pub(crate) library_mode: LibraryMode,
+    pub(crate) directory: Option<String>,
+    pub(crate) playlists: Vec<String>,
+    pub(crate) pane: Pane,
+    pub(crate) queue_state: ListState,
+    pub(crate) library_state: ListState,
+    pub(crate) playlists_state: ListState,
+    pub(crate) message: Option<String>,
+    pub(crate) quit: bool,
+}
+
+impl<'a> App<'a> {
+    pub(crate) fn new(client: &'a mut Client) -> Self {
+        Self {
+            client,
+            status: None,
+            queue: Vec::new(),
+            library: Vec::new(),
+            library_mode: LibraryMode::Files,
+            directory: None,
+            playlists: Vec::new(),
+            pane: Pane::Queue,
+            queue_state: ListState::default(),
+            library_state: ListState::default(),
+            playlists_state: ListState::default(),
+            message: None,
+            quit: false,
+        }
+    }
+
+    /// Reloads the parts of the screen affected by the given events, or
+    /// everything when `events` is empty.
+    pub(crate) fn refresh(&mut self, events: &[Event]) -> eyre::Result<()> {
+        let all = events.is_empty();
+        let changed = |event: Event| all || events.contains(&event);
+
+        if changed(Event::Player)
+            || changed(Event::Mixer)
+            || changed(Event::Options)
+        {
+            self.status = Some(self.client.status()?);
+        }
+
+        if changed(Event::Playlist) {
+            self.queue = self
+                .client
+                .songs(None)?
+                .into_iter()
+                .enumerate()
+                .map(|(index, song)| Row {
+                    position: song.place.map_or(
+                        u32::try_from(index).unwrap_or(u32::MAX),
+                        |place| place.pos,
+                    ),
+                    artist: song.artist.unwrap_or_default(),
+                    title: song.title.unwrap_or(song.file),
+                    seconds: song.duration.map_or(0, |d| d.as_secs()),
+                })
+                .collect();
+            clamp(&mut self.queue_state, self.queue.len());
+        }
+
+        if changed(Event::Database) {
+            self.library = match self.library_mode {
+                LibraryMode::Files => {
+                    self.client.directory_listing(self.directory.as_deref())?
+                }
+                LibraryMode::Artists => self.client.tag_values("Artist")?,
+            };
+            clamp(&mut self.library_state, self.library.len());
+        }
+
+        if changed(Event::StoredPlaylist) {
+            self.playlists = self.client.playlist_names()?;
+            clamp(&mut self.playlists_state, self.playlists.len());
+        }
+
+        Ok(())
+    }
+
+    /// Only the status moves on its own (elapsed time) so refresh it on tick.
+    pub(crate) fn tick(&mut self) -> eyre::Result<()> {
+        let playing = self
+            .status
+            .as_ref()
+            .is_some_and(|status| status.state == State::Play);
+
+        if playing {
+            self.refresh(&[Event::Player])?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies a key press, reporting failures in the footer rather than
+    /// leaving the interface.
+    pub(crate) fn handle_key(&mut self, key: KeyEvent) {
+        self.message = None;
+
+        if let Err(e) = self.apply_key(key) {
+            self.message = Some(e.to_string());
+        }
+    }
+
+    fn apply_key(&mut self, key: KeyEvent) -> eyre::Result<()> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if ctrl => self.quit = true,
+            KeyCode::Tab => self.pane = self.pane.next(),
+            KeyCode::BackTab => self.pane = self.pane.prev(),
+            KeyCode::Char('1') => self.pane = Pane::Queue,
+            KeyCode::Char('2') => self.pane = Pane::Library,
+            KeyCode::Char('3') => self.pane = Pane::Playlists,
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::PageDown => self.select(10),
+            KeyCode::PageUp => self.select(-10),
+            KeyCode::Home | KeyCode::Char('g') => self.select(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.select(isize::MAX),
+            KeyCode::Enter => self.activate()?,
+            KeyCode::Backspace | KeyCode::Char('h') => self.up()?,
+            KeyCode::Char('a') => self.add_selected()?,
+            KeyCode::Char('d') | KeyCode::Delete => self.delete_selected()?,
+            KeyCode::Char('m') => self.toggle_library_mode()?,
+            KeyCode::Char(' ' | 'p') => {
+                self.client.toggle()?;
+            }
+            KeyCode::Char('s') => {
+                self.client.stop()?;
+            }
+            KeyCode::Char('>') => {
+                self.client.next()?;
+            }
+            KeyCode::Char('<') => {
+                self.client.prev()?;
+            }
+            KeyCode::Right => self.seek_by(SEEK_STEP)?,
+            KeyCode::Left => self.seek_by(-SEEK_STEP)?,
+            KeyCode::Char('+' | '=') => {
+                self.client.set_volume(&format!("+{VOLUME_STEP}"))?;
+            }
+            KeyCode::Char('-') => {
+                self.client.set_volume(&format!("-{VOLUME_STEP}"))?;
+            }
+            KeyCode::Char('r') => {
+                self.client.repeat(None)?;
+            }
+            KeyCode::Char('z') => {
+                self.client.random(None)?;
+            }
+            KeyCode::Char('y') => {
+                self.client.single(None)?;
+            }
+            KeyCode::Char('C') => {
+                self.client.consume(None)?;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn state(&mut self) -> (&mut ListState, usize) {
+        match self.pane {
+            Pane::Queue => (&mut self.queue_state, self.queue.len()),
+            Pane::Library => (&mut self.library_state, self.library.len()),
+            Pane::Playlists => {
+                (&mut self.playlists_state, self.playlists.len())
+            }
+        }
+    }
+
+    fn select(&mut self, offset: isize) {
+        let (state, len) = self.state();
+
+        if len == 0 {
+            state.select(None);
+            return;
+        }
+
+        let current = state.selected().unwrap_or(0);
+        let target = current.saturating_add_signed(offset).min(len - 1);
+
+        state.select(Some(target));
+    }
+
+    fn selected<'b>(state: &ListState, items: &'b [String]) -> Option<&'b str> {
+        state
+            .selected()
+            .and_then(|index| items.get(index))
+            .map(String::as_str)
+    }
+
+    fn activate(&mut self) -> eyre::Result<()> {
+        match self.pane {
+            Pane::Queue => {
+                if let Some(row) = self
+                    .queue_state
+                    .selected()
+                    .and_then(|index| self.queue.get(index))
+                {
+                    self.client.play(Some(row.position))?;
+                }
+            }
+            Pane::Library => match self.library_mode {
+                LibraryMode::Files => {
+                    if let Some(entry) =
+                        Self::selected(&self.library_state, &self.library)
+                    {
+                        let entry = entry.to_string();
+                        let listing =
+                            self.client.directory_listing(Some(&entry))?;
+
+                        if listing.is_empty() || listing == [entry.clone()] {
+                            self.client.add(&entry)?;
+                            self.message = Some(format!("added: {entry}"));
+                        } else {
+                            self.directory = Some(entry);
+                            self.library = listing;
+                            self.library_state.select(Some(0));
+                        }
+                    }
+                }
+                LibraryMode::Artists => self.add_selected()?,
+            },
+            Pane::Playlists => {
+                if let Some(name) =
+                    Self::selected(&self.playlists_state, &self.playlists)
+                {
+                    let name = name.to_string();
+                    self.message = self.client.load(&name, None)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn up(&mut self) -> eyre::Result<()> {
+        if self.pane != Pane::Library || self.library_mode != LibraryMode::Files
+        {
+            return Ok(());
+        }
+
+        if let Some(directory) = self.directory.take() {
+            self.directory = directory
+                .rsplit_once('/')
+                .map(|(parent, _)| parent.to_string());
+            self.library_state.select(Some(0));
+            self.refresh(&[Event::Database])?;
+        }
+
+        Ok(())
+    }
+
+    fn add_selected(&mut self) -> eyre::Result<()> {
+        if self.pane != Pane::Library {
+            return Ok(());
+        }
+
+        if let Some(entry) = Self::selected(&self.library_state, &self.library)
+        {
+            let entry = entry.to_string();
+
+            match self.library_mode {
+                LibraryMode::Files => self.client.add(&entry)?,
+                LibraryMode::Artists => {
+                    self.client.find_add("Artist", &entry)?
+                }
+            };
+
+            self.message = Some(format!("added: {entry}"));
+        }
+
+        Ok(())
+    }
+
+    fn delete_selected(&mut self) -> eyre::Result<()> {
+        if self.pane != Pane::Queue {
+            return Ok(());
+        }
+
+        if let Some(row) = self
+            .queue_state
+            .selected()
+            .and_then(|index| self.queue.get(index))
+        {
+            self.client.del(Some(row.position))?;
+        }
+
+        Ok(())
+    }
+
+    fn toggle_library_mode(&mut self) -> eyre::Result<()> {
+        if self.pane != Pane::Library {
+            return Ok(());
+        }
+
+        self.library_mode = match self.library_mode {
+            LibraryMode::Files => LibraryMode::Artists,
+            LibraryMode::Artists => LibraryMode::Files,
+        };
+        self.library_state.select(Some(0));
+
+        self.refresh(&[Event::Database])
+    }
+
+    fn seek_by(&mut self, seconds: i64) -> eyre::Result<()> {
+        let Some(status) = self.status.as_ref() else {
+            return Ok(());
+        };
+
+        if status.state == State::Stop {
+            return Ok(());
+        }
+
+        let target = (status.elapsed + Time::from_secs(seconds))
+            .clamp(Time::ZERO, status.track_length.max(Time::ZERO));
+
+        self.client.seek_to(target)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn flags(status: &Status) -> String {
+        let flag = |name: &str, value: &OnOff| format!("{name}: {value}");
+
+        format!(
+            "volume: {}% | {} | {} | {} | {}",
+            status.volume,
+            flag("repeat", &status.repeat),
+            flag("random", &status.random),
+            flag("single", &status.single),
+            flag("consume", &status.consume),
+        )
+    }
+}
+
+/// Keeps a selection inside a list that may have shrunk.
+fn clamp(state: &mut ListState, len: usize) {
+    match (state.selected(), len) {
+        (_, 0) => state.select(None),
+        (None, _) => state.select(Some(0)),
+        (Some(index), len) if index >= len => state.select(Some(len - 1)),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+
+    fn server() -> FakeServer {
+        FakeServer::start(|line| {
+            let body = match line {
+                "status" => concat!(
+                    "volume: 50\nrepeat: 0\nrandom: 1\nsingle: 0\n",
+                    "consume: 0\nplaylistlength: 2\nstate: play\n",
+                    "song: 0\nsongid: 1\ntime: 30:200\n",
+                ),
+                "currentsong" => concat!(
+                    "file: a.flac\nArtist: Phish\nTitle: Tweezer\n",
+                    "Pos: 0\nId: 1\n",
+                ),
+                "playlistinfo" => concat!(
+                    "file: a.flac\nArtist: Phish\nTitle: Tweezer\n",
+                    "Time: 200\nPos: 0\nId: 1\n",
+                    "file: b.flac\nArtist: Phish\nTitle: Reba\n",
+                    "Time: 300\nPos: 1\nId: 2\n",
+                ),
+                r#"listfiles """# => "directory: Phish\ndirectory: Gus\n",
+                r#"listfiles "Phish""# => "directory: Junta\n",
+                "listplaylists" => concat!(
+                    "playlist: Morning\nLast-Modified: 2024-01-01T06:00:00Z\n",
+                    "playlist: Night\nLast-Modified: 2024-01-01T22:00:00Z\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        })
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    #[test]
+    fn test_refresh_loads_every_pane() {
+        let server = server();
+        let mut client = server.client();
+        let mut app = App::new(&mut client);
+
+        app.refresh(&[]).unwrap();
+
+        let status = app.status.as_ref().unwrap();
+        assert_eq!(status.title, "Tweezer");
+        assert_eq!(app.queue.len(), 2);
+        assert_eq!(app.queue[1].title, "Reba");
+        assert_eq!(app.library, vec!["Phish", "Gus"]);
+        assert_eq!(app.playlists, vec!["Morning", "Night"]);
+        assert_eq!(app.queue_state.selected(), Some(0));
+    }
+
+    #[test]
+    fn test_navigation_and_library_browsing() {
+        let server = server();
+        let mut client = server.client();
+        let mut app = App::new(&mut client);
+        app.refresh(&[]).unwrap();
+
+        press(&mut app, KeyCode::Tab);
+        assert_eq!(app.pane, Pane::Library);
+
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.directory.as_deref(), Some("Phish"));
+        assert_eq!(app.library, vec!["Phish/Junta"]);
+
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(app.directory, None);
+        assert_eq!(app.library, vec!["Phish", "Gus"]);
+    }
+
+    #[test]
+    fn test_keybindings_issue_commands() {
+        let server = server();
+        let mut client = server.client();
+        let mut app = App::new(&mut client);
+        app.refresh(&[]).unwrap();
+
+        press(&mut app, KeyCode::Char(' '));
+        press(&mut app, KeyCode::Right);
+        press(&mut app, KeyCode::Char('+'));
+        press(&mut app, KeyCode::Char('3'));
+        press(&mut app, KeyCode::Enter);
+
+        let commands = server.commands();
+        assert!(commands.contains(&r#"pause "1""#.to_string()));
+        assert!(commands.contains(&r#"seek "0" "35""#.to_string()));
+        assert!(commands.contains(&r#"setvol "55""#.to_string()));
+        assert!(commands.contains(&r#"load "Morning" "0:""#.to_string()));
+        assert!(app.message.is_some());
+    }
+}
@@ -0,0 +1,151 @@
+//! Full-screen terminal interface built on top of `mpd_easy::Client`.
+//!
+//! A second connection sits in MPD's `idle` mode on a background thread and
+//! forwards changed subsystems so that only the affected panes are reloaded.
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mpd_easy::{Client, Event, OutputFormat};
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyEventKind};
+use ratatui::{backend::Backend, Terminal};
+
+mod app;
+mod ui;
+
+use app::App;
+
+const TICK: Duration = Duration::from_secs(1);
+const POLL: Duration = Duration::from_millis(100);
+
+pub(crate) fn run(
+    mpd: &mut Client,
+    bind_to_address: &str,
+    port: &str,
+) -> eyre::Result<Option<String>> {
+    let events = watch(bind_to_address, port)?;
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, mpd, &events);
+    ratatui::restore();
+
+    result.map(|()| None)
+}
+
+/// Forwards idle events from a dedicated connection until the receiver hangs
+/// up or the connection drops.
+fn watch(
+    bind_to_address: &str,
+    port: &str,
+) -> eyre::Result<mpsc::Receiver<Vec<Event>>> {
+    let mut idle =
+        Client::new(bind_to_address, port, Some(OutputFormat::None))?;
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        while let Ok(events) = idle.wait(&[]) {
+            if sender.send(events).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+fn event_loop<B: Backend>(
+    terminal: &mut Terminal<B>,
+    mpd: &mut Client,
+    events: &mpsc::Receiver<Vec<Event>>,
+) -> eyre::Result<()>
+where
+    B::Error: Send + Sync + 'static,
+{
+    let mut app = App::new(mpd);
+    app.refresh(&[])?;
+
+    let mut last_tick = Instant::now();
+
+    while !app.quit {
+        terminal.draw(|frame| ui::draw(frame, &mut app))?;
+
+        if event::poll(POLL)? {
+            if let TermEvent::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key);
+                }
+            }
+        }
+
+        while let Ok(changed) = events.try_recv() {
+            app.refresh(&changed)?;
+        }
+
+        if last_tick.elapsed() >= TICK {
+            app.tick()?;
+            last_tick = Instant::now();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mpd_easy::testing::FakeServer;
+    use ratatui::backend::TestBackend;
+
+    fn screen(terminal: &Terminal<TestBackend>) -> String {
+        let buffer = terminal.backend().buffer();
+
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| {
+                row.iter()
+                    .map(ratatui::buffer::Cell::symbol)
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_draw_now_playing_and_queue() {
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                "status" => concat!(
+                    "volume: 70\nstate: play\nsong: 1\nsongid: 2\n",
+                    "playlistlength: 2\ntime: 60:300\n",
+                ),
+                "currentsong" => concat!(
+                    "file: b.flac\nArtist: Phish\nTitle: Reba\n",
+                    "Album: Lawn Boy\nPos: 1\n",
+                ),
+                "playlistinfo" => concat!(
+                    "file: a.flac\nArtist: Phish\nTitle: Tweezer\n",
+                    "Time: 200\nPos: 0\n",
+                    "file: b.flac\nArtist: Phish\nTitle: Reba\n",
+                    "Time: 300\nPos: 1\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+        let mut app = App::new(&mut client);
+        app.refresh(&[]).unwrap();
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 16)).unwrap();
+        terminal.draw(|frame| ui::draw(frame, &mut app)).unwrap();
+
+        let screen = screen(&terminal);
+        assert!(screen.contains("[play] Phish - Reba (Lawn Boy)"));
+        assert!(screen.contains("00:01:00 / 00:05:00"));
+        assert!(screen.contains("volume: 70%"));
+        assert!(screen.contains("Queue (2)"));
+        assert!(screen.contains(">   2 Phish - Reba (5:00)"));
+    }
+}
@@ -0,0 +1,171 @@
+use mpd_easy::State;
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Gauge, List, ListItem, Paragraph, Tabs},
+    Frame,
+};
+
+use super::app::{App, LibraryMode, Pane};
+
+const HELP: &str = "q quit | tab pane | enter play/open/load | a add | \
+                    d delete | m library mode | space toggle | < > prev/next | \
+                    ←/→ seek | +/- volume | r z y C modes";
+
+pub(crate) fn draw(frame: &mut Frame, app: &mut App) {
+    let [now_playing, tabs, main, footer] = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Length(1),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_now_playing(frame, app, now_playing);
+    draw_tabs(frame, app, tabs);
+
+    match app.pane {
+        Pane::Queue => draw_queue(frame, app, main),
+        Pane::Library => draw_library(frame, app, main),
+        Pane::Playlists => draw_playlists(frame, app, main),
+    }
+
+    let footer_text = app.message.clone().unwrap_or_else(|| HELP.to_string());
+    frame.render_widget(Paragraph::new(footer_text), footer);
+}
+
+fn draw_now_playing(frame: &mut Frame, app: &App, area: Rect) {
+    let block = Block::default().borders(Borders::ALL).title("Now Playing");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let Some(status) = app.status.as_ref() else {
+        frame.render_widget(Paragraph::new("connecting..."), inner);
+        return;
+    };
+
+    let [song, progress, flags] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(inner);
+
+    let song_text = if status.state == State::Stop {
+        format!("[{}]", status.state)
+    } else {
+        format!(
+            "[{}] {} - {} ({})",
+            status.state, status.artist, status.title, status.album
+        )
+    };
+    frame.render_widget(Paragraph::new(song_text), song);
+
+    let elapsed = status.elapsed.as_secs().max(0);
+    let total = status.track_length.as_secs().max(0);
+    #[allow(clippy::cast_precision_loss)]
+    let ratio = if total > 0 {
+        (elapsed as f64 / total as f64).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let gauge = Gauge::default()
+        .ratio(ratio)
+        .label(format!("{} / {}", status.elapsed, status.track_length));
+    frame.render_widget(gauge, progress);
+
+    frame.render_widget(Paragraph::new(App::flags(status)), flags);
+}
+
+fn draw_tabs(frame: &mut Frame, app: &App, area: Rect) {
+    let titles = Pane::ALL.iter().map(|pane| pane.title());
+    let selected = Pane::ALL.iter().position(|&pane| pane == app.pane);
+    let tabs = Tabs::new(titles)
+        .select(selected)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+    frame.render_widget(tabs, area);
+}
+
+fn highlighted(items: Vec<ListItem<'_>>, title: String) -> List<'_> {
+    List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+fn draw_queue(frame: &mut Frame, app: &mut App, area: Rect) {
+    let current = app
+        .status
+        .as_ref()
+        .filter(|status| status.state != State::Stop)
+        .map(|status| status.position);
+
+    let items = app
+        .queue
+        .iter()
+        .map(|row| {
+            let marker = if Some(row.position) == current {
+                ">"
+            } else {
+                " "
+            };
+            let line = format!(
+                "{marker}{:>4} {} - {} ({}:{:02})",
+                row.position + 1,
+                row.artist,
+                row.title,
+                row.seconds / 60,
+                row.seconds % 60,
+            );
+
+            if Some(row.position) == current {
+                ListItem::new(Line::from(line))
+                    .style(Style::default().add_modifier(Modifier::BOLD))
+            } else {
+                ListItem::new(Line::from(line))
+            }
+        })
+        .collect();
+
+    let title = format!("Queue ({})", app.queue.len());
+    frame.render_stateful_widget(
+        highlighted(items, title),
+        area,
+        &mut app.queue_state,
+    );
+}
+
+fn draw_library(frame: &mut Frame, app: &mut App, area: Rect) {
+    let items = app
+        .library
+        .iter()
+        .map(|entry| ListItem::new(entry.as_str()))
+        .collect();
+
+    let title = match app.library_mode {
+        LibraryMode::Files => {
+            format!("Library: /{}", app.directory.as_deref().unwrap_or(""))
+        }
+        LibraryMode::Artists => "Library: artists".to_string(),
+    };
+    frame.render_stateful_widget(
+        highlighted(items, title),
+        area,
+        &mut app.library_state,
+    );
+}
+
+fn draw_playlists(frame: &mut Frame, app: &mut App, area: Rect) {
+    let items = app
+        .playlists
+        .iter()
+        .map(|name| ListItem::new(name.as_str()))
+        .collect();
+
+    frame.render_stateful_widget(
+        highlighted(items, "Playlists".to_string()),
+        area,
+        &mut app.playlists_state,
+    );
+}
@@ -26,8 +26,8 @@ fn main() {
             status.title,
             status.artist,
             status.album,
-            status.elapsed,
-            status.track_length,
+            status.elapsed.truncated(),
+            status.track_length.truncated(),
         );
     }
 
@@ -0,0 +1,168 @@
+//! An on-disk copy of `listallinfo`, so that library-wide commands don't
+//! download the whole database every time. It belongs to one server and
+//! is stale once the server's `db_update` differs from the one it was
+//! taken at.
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dirs;
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Snapshot {
+    /// The server's `db_update` (unix time) when the songs were listed
+    pub(crate) db_update: u64,
+    pub(crate) songs: Vec<mpd::Song>,
+}
+
+impl Snapshot {
+    /// The cached library, `None` when missing or unreadable
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        let contents = fs::read(path).ok()?;
+
+        serde_json::from_slice(&contents).ok()
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> eyre::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Ok(fs::write(path, serde_json::to_vec(self)?)?)
+    }
+}
+
+/// Where the library of the server at `address` is cached
+pub(crate) fn path(address: &str) -> PathBuf {
+    let name = address.replace([':', '/'], "_");
+
+    dirs::cache_dir().join(format!("library-{name}.json"))
+}
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub path: Option<PathBuf>,
+    pub songs: usize,
+    /// `db_update` of the cached library, `None` when nothing is cached
+    pub cached: Option<u64>,
+    /// `db_update` of the server
+    pub db_update: u64,
+    pub fresh: bool,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self
+            .path
+            .as_ref()
+            .map_or("none".to_string(), |path| path.display().to_string());
+        let cached = self
+            .cached
+            .map_or("none".to_string(), |cached| cached.to_string());
+
+        write!(
+            f,
+            "path={path}\nsongs={}\ncached={cached}\ndb_update={}\nfresh={}",
+            self.songs, self.db_update, self.fresh
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeServer;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_library_is_cached_until_db_update_changes() {
+        let dir = tempdir::TempDir::new("cache").unwrap();
+        let path = dir.path().join("library.json");
+        let db_update = Arc::new(AtomicU64::new(100));
+        let current = Arc::clone(&db_update);
+        let server = FakeServer::start(move |line| {
+            let body = match line {
+                "stats" => {
+                    format!("db_update: {}\n", current.load(Ordering::SeqCst))
+                }
+                "listallinfo" => "file: a.flac\nfile: b.flac\n".to_string(),
+                r#"find modified-since "100""# => {
+                    "file: b.flac\nTitle: New\n".to_string()
+                }
+                "listall" => "file: b.flac\nfile: c.flac\n".to_string(),
+                r#"find "file" "c.flac""# => "file: c.flac\n".to_string(),
+                _ => String::new(),
+            };
+
+            Ok(body)
+        });
+        let mut client = server.client();
+        client.set_cache(Some(path.clone()));
+        let files = |songs: Vec<mpd::Song>| -> Vec<String> {
+            songs.into_iter().map(|song| song.file).collect()
+        };
+
+        assert_eq!(files(client.library().unwrap()), ["a.flac", "b.flac"]);
+        assert_eq!(files(client.library().unwrap()), ["a.flac", "b.flac"]);
+        let listings = |commands: Vec<String>| {
+            commands
+                .iter()
+                .filter(|line| *line == "listallinfo")
+                .count()
+        };
+        assert_eq!(listings(server.commands()), 1);
+
+        db_update.store(200, Ordering::SeqCst);
+        let songs = client.library().unwrap();
+        assert_eq!(songs[0].title.as_deref(), Some("New"));
+        assert_eq!(files(songs), ["b.flac", "c.flac"]);
+        assert_eq!(listings(server.commands()), 1);
+        assert_eq!(Snapshot::load(&path).unwrap().db_update, 200);
+
+        client.cache_clear().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_listall_only_uses_a_fresh_cache() {
+        let dir = tempdir::TempDir::new("cache").unwrap();
+        let path = dir.path().join("library.json");
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                "stats" => "db_update: 100\n",
+                "listall" => "file: a.flac\n",
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+        client.set_cache(Some(path.clone()));
+
+        assert_eq!(
+            client.listall(None).unwrap().unwrap(),
+            r#"{"listing":["a.flac"]}"#
+        );
+        assert!(!server.commands().contains(&"listallinfo".to_string()));
+
+        let cached = mpd::Song {
+            file: "b.flac".to_string(),
+            ..Default::default()
+        };
+        for (db_update, listing) in [
+            (90, r#"{"listing":["a.flac"]}"#),
+            (100, r#"{"listing":["b.flac"]}"#),
+        ] {
+            Snapshot {
+                db_update,
+                songs: vec![cached.clone()],
+            }
+            .save(&path)
+            .unwrap();
+            assert_eq!(client.listall(None).unwrap().unwrap(), listing);
+        }
+    }
+}
@@ -1,25 +1,46 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::path::{Path, PathBuf};
+use std::fs;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::thread;
 use std::time::Duration;
 
 use eyre::WrapErr;
+use mpd::Idle;
 use serde::Serialize;
 
 use crate::{
-    range,
+    cache::{self, Snapshot},
+    event::Event,
+    fuzzy::{self, Enqueue, Field, Matcher},
+    journal::{self, QueueState, Queued, Summaries},
+    lyrics::{self, Lyrics},
+    playlist_file::{self, Entry, ExportFormat, Format, Imported},
+    queue::{self, DedupeBy, Keep},
+    random, range,
     range::INVALID_RANGE,
+    raw,
+    session::{Handoff, Session},
     song::Current,
+    song::File,
     song::Finder,
+    song::FinderOptions,
     song::Listing,
+    song::Picked,
     song::Playlist,
     song::Playlists,
     song::Song,
     song::TrackList,
+    song::{self, VALID_EXTENSIONS},
     stats::Output,
     stats::Outputs,
     stats::Stats,
     status::Status,
-    time, {OnOff, OutputFormat},
+    stream::{self, Stream},
+    time::{Time, Track},
+    volume::{self, Volume},
+    {OnOff, OutputFormat},
 };
 
 const NO_OUT: String = String::new();
@@ -42,29 +63,118 @@ impl fmt::Display for Versions {
     }
 }
 
+#[allow(clippy::struct_field_names)]
 pub struct Client {
-    client: mpd::Client,
+    client: mpd::Client<Stream>,
     format: OutputFormat,
+    address: String,
+    finder_options: FinderOptions,
+    /// Where the library is cached, `None` to always ask the server
+    cache: Option<PathBuf>,
+    /// Where the queue is saved before destructive commands, `None` to
+    /// not save it
+    journal: Option<PathBuf>,
 }
 
 impl Client {
+    /// Connects over TCP, or to the Unix socket at `bind_to_address` when
+    /// it is a path (the port is then ignored)
     pub fn new(
         bind_to_address: &str,
         port: &str,
         format: Option<OutputFormat>,
     ) -> eyre::Result<Client> {
         let format = format.unwrap_or(OutputFormat::Json);
-        let client = mpd::Client::connect(format!("{bind_to_address}:{port}"))
-            .wrap_err("Error connecting to mpd server".to_string())?;
+        let address = if stream::is_socket(bind_to_address) {
+            bind_to_address.to_string()
+        } else {
+            format!("{bind_to_address}:{port}")
+        };
+        let stream = Stream::connect(&address)
+            .wrap_err("Error connecting to mpd server")?;
+        let client = mpd::Client::new(stream)
+            .wrap_err("Error connecting to mpd server")?;
+
+        Ok(Self {
+            client,
+            format,
+            cache: Some(cache::path(&address)),
+            journal: Some(journal::path(&address)),
+            address,
+            finder_options: FinderOptions::default(),
+        })
+    }
+
+    /// Changes where the library is cached, `None` disabling the cache
+    pub fn set_cache(&mut self, path: Option<PathBuf>) {
+        self.cache = path;
+    }
+
+    /// Changes where the queue is journaled, `None` disabling `undo`
+    pub fn set_journal(&mut self, path: Option<PathBuf>) {
+        self.journal = path;
+    }
 
-        Ok(Self { client, format })
+    /// Changes how `add_local` walks directories
+    pub fn set_finder_options(&mut self, options: FinderOptions) {
+        self.finder_options = options;
     }
 
     //
     // queue related commands
     //
+    /// Adds a path from MPD's database (a song, a directory or a playlist
+    /// file, `/` being the whole library) or a URL such as a stream. The
+    /// server resolves the path, so this works when the music directory
+    /// isn't on this machine. Over the Unix socket, where MPD and this
+    /// machine share a filesystem, absolute paths are walked locally with
+    /// `add_local`.
     pub fn add(&mut self, path: &str) -> eyre::Result<Option<String>> {
-        let music_dir = self.client.music_directory()?;
+        if playlist_file::is_url(path) {
+            return self.add_url(path).map(|()| None);
+        }
+        if path != "/" && Path::new(path).is_absolute() && self.is_local() {
+            return self.add_local(path);
+        }
+
+        let uri = path.trim_matches('/');
+
+        if Format::of(Path::new(uri)).is_some() {
+            self.client
+                .load(uri, ..)
+                .wrap_err(format!("unable to load playlist file: {uri}"))?;
+
+            return Ok(None);
+        }
+
+        if !uri.is_empty() {
+            let song = mpd::song::Song {
+                file: uri.to_string(),
+                ..Default::default()
+            };
+            self.client
+                .lsinfo(&song)
+                .wrap_err(format!("not found in the mpd database: {uri}"))?;
+        }
+
+        // unlike `addid`, `add` takes directories and adds them recursively
+        self.raw(&format!(
+            "add {}",
+            raw::quote(if uri.is_empty() { "/" } else { uri })
+        ))?;
+
+        Ok(None)
+    }
+
+    /// Adds songs and playlist files (m3u, pls, xspf and cue) found by
+    /// walking a path on this machine, relative to the music directory
+    /// unless absolute. Files outside the music directory are added as
+    /// `file://` URIs, which MPD only accepts over its Unix socket.
+    pub fn add_local(&mut self, path: &str) -> eyre::Result<Option<String>> {
+        let music_dir = self.client.music_directory().wrap_err(
+            "unable to get the music directory \
+             (mpd only tells clients connected to its unix socket)",
+        )?;
 
         let absolute_path = if path.starts_with(&music_dir) {
             path.to_string()
@@ -76,28 +186,158 @@ impl Client {
                 .to_string()
         };
 
-        let mut finder = Finder::new(music_dir);
+        let extensions = match self.finder_options.extensions.clone() {
+            Some(extensions) => extensions,
+            None => self.decoder_suffixes(),
+        };
+        let mut finder = Finder::new(music_dir)
+            .extensions(extensions)
+            .hidden(self.finder_options.hidden);
 
         finder.find(Path::new(Path::new(&absolute_path)))?;
 
-        for file in finder.found {
-            let song = mpd::song::Song {
-                file: file.relative_path,
+        // MPD knows the track numbers of the songs in its database
+        let mut directories: Vec<String> = finder
+            .found
+            .iter()
+            .filter_map(|file| file.relative_path.as_deref())
+            .filter_map(|path| Path::new(path).parent()?.to_str())
+            .map(String::from)
+            .collect();
+        directories.dedup();
+        let mut songs = Vec::new();
+        for directory in directories {
+            let directory = mpd::song::Song {
+                file: directory,
                 ..Default::default()
             };
+            // songs MPD hasn't scanned yet keep the order of their names
+            songs.extend(self.client.lsinfo(&directory).unwrap_or_default());
+        }
+        finder.order_by_tags(&songs);
 
-            self.client
-                .push(song.clone())
-                .wrap_err(format!("unkown or inalid path: {}", song.file))?;
+        for file in &finder.found {
+            match file.playlist() {
+                Some(format) => {
+                    self.add_playlist_file(&finder, file, format)?;
+                }
+                None => self.add_file(file)?,
+            }
         }
 
         Ok(None)
     }
 
+    /// Extensions of the files MPD can decode, or a common list when it
+    /// doesn't say
+    fn decoder_suffixes(&mut self) -> Vec<String> {
+        let suffixes: Vec<String> = self
+            .client
+            .decoders()
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|plugin| plugin.suffixes)
+            .collect();
+
+        if suffixes.is_empty() {
+            VALID_EXTENSIONS.iter().map(ToString::to_string).collect()
+        } else {
+            suffixes
+        }
+    }
+
+    fn is_local(&self) -> bool {
+        stream::is_socket(&self.address)
+    }
+
+    /// What MPD knows `file` as: its path in the music directory or,
+    /// when connected locally, a `file://` URI
+    fn uri_for(&self, file: &File) -> eyre::Result<String> {
+        match &file.relative_path {
+            Some(relative_path) => Ok(relative_path.clone()),
+            None if self.is_local() => Ok(format!("file://{}", file.full_path)),
+            None => Err(eyre::eyre!(
+                "{} is outside the music directory \
+                 (connect to MPD's unix socket to add local files)",
+                file.full_path
+            )),
+        }
+    }
+
+    fn push(&mut self, uri: String) -> eyre::Result<()> {
+        let song = mpd::song::Song {
+            file: uri,
+            ..Default::default()
+        };
+
+        self.client
+            .push(&song)
+            .wrap_err(format!("unkown or inalid path: {}", song.file))?;
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, file: &File) -> eyre::Result<()> {
+        let uri = self.uri_for(file)?;
+
+        self.push(uri)
+    }
+
+    fn add_url(&mut self, url: &str) -> eyre::Result<()> {
+        if url.starts_with("file://") && !self.is_local() {
+            return Err(eyre::eyre!(
+                "file:// URIs can only be added over MPD's unix socket: {url}"
+            ));
+        }
+
+        self.push(url.to_string())
+    }
+
+    /// Queues the entries of a playlist file, paths in it being relative to
+    /// the file's directory
+    fn add_playlist_file(
+        &mut self,
+        finder: &Finder,
+        file: &File,
+        format: Format,
+    ) -> eyre::Result<()> {
+        if format == Format::Cue {
+            let uri = self.uri_for(file)?;
+            self.client
+                .load(&uri, ..)
+                .wrap_err(format!("unable to load cue sheet: {uri}"))?;
+
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&file.full_path)
+            .wrap_err(format!("unable to read {}", file.full_path))?;
+        let directory = Path::new(&file.full_path)
+            .parent()
+            .unwrap_or(Path::new("/"));
+
+        for entry in format.entries(&contents) {
+            let path = match playlist_file::file_url_path(&entry.file) {
+                Some(path) => path,
+                None if playlist_file::is_url(&entry.file) => {
+                    self.add_url(&entry.file)?;
+                    continue;
+                }
+                None => entry.file,
+            };
+
+            let entry = finder.locate(&directory.join(path));
+            self.add_file(&entry)?;
+        }
+
+        Ok(())
+    }
+
     pub fn crop(&mut self) -> eyre::Result<Option<String>> {
         // determine current song position
         // remove all songs before current song
         // remove all songs from 1 onwards
+        self.remember("crop")?;
         let status = self.status()?;
         let current_position = status.position;
         let length = status.queue_count;
@@ -117,6 +357,7 @@ impl Client {
         &mut self,
         position: Option<u32>,
     ) -> eyre::Result<Option<String>> {
+        self.remember("del")?;
         let position = match position {
             Some(position) => position,
             None => self.status()?.position,
@@ -238,7 +479,7 @@ impl Client {
     pub fn seek(&mut self, position: &str) -> eyre::Result<Option<String>> {
         let current_status = self.status()?;
 
-        // valid position syntax: [+-][HH:MM:SS]|<0-100>%
+        // valid position syntax: [+-]<time>|<0-100>%
         let place = if position.contains('%') {
             let position = position.replace('%', "");
 
@@ -251,57 +492,91 @@ impl Client {
                 ));
             }
 
-            let length = current_status.track_length.as_secs;
-            let percent = i64::from(percent);
+            let length = current_status.track_length.as_millis();
 
-            length * percent / 100
-        } else if position.contains('+') || position.contains('-') {
-            current_status.elapsed.compute_offset(position)
+            Time::from_millis(length * i64::from(percent) / 100)
+        } else if position.starts_with(['+', '-']) {
+            current_status.elapsed + position.parse::<Time>()?
         } else {
-            time::Time::from(position.to_string()).as_secs
+            position.parse::<Time>()?
         };
 
         let position = self.status()?.position;
 
-        self.client.seek(position, place)?;
+        self.client.seek(position, place.to_duration())?;
 
         self.stats()
     }
 
+    /// Seeks the current song to an absolute position
+    pub fn seek_to(&mut self, time: Time) -> eyre::Result<()> {
+        let position = self.status()?.position;
+
+        self.client
+            .seek(position, time.max(Time::ZERO).to_duration())?;
+
+        Ok(())
+    }
+
     pub fn seekthrough(
         &mut self,
         position: &str,
     ) -> eyre::Result<Option<String>> {
         let mut direction = Direction::Forward;
 
-        // valid position syntax: [+-][HH:MM:SS]
-        let mut place = if position.contains('%') {
+        // valid position syntax: [+-]<time>
+        let offset = if position.contains('%') {
             return Err(eyre::eyre!(
                 "seekthrough does not support percentage based seeking"
             ));
         } else {
-            // if `-` present then back otherwise assume forward
-            if position.contains('-') {
-                direction = Direction::Reverse;
-            }
-            time::Time::from(position.to_string()).as_secs
+            position.parse::<Time>()?
         };
 
+        // if negative then back otherwise assume forward
+        if offset.is_negative() {
+            direction = Direction::Reverse;
+        }
+        let mut place = offset.as_millis().abs();
+
         let queue = self.client.queue()?;
         let start = usize::try_from(self.status()?.position)?;
-        let mut elapsed = self.status()?.elapsed.as_secs;
+        let mut elapsed = self.status()?.elapsed.as_millis();
+
+        // the songs are cycled through, which only ends if they have a length
+        let mut total = 0;
+        for song in &queue {
+            let duration = song.duration.ok_or_else(|| {
+                eyre::eyre!(
+                    "unable to seek through {}: unknown length",
+                    song.file
+                )
+            })?;
+            total += Time::from(duration).as_millis();
+        }
+        if total == 0 {
+            return Err(eyre::eyre!("nothing to seek through"));
+        }
+
+        let id = |song: &mpd::Song| {
+            song.place
+                .map(|place| place.id)
+                .ok_or_else(|| eyre::eyre!("{} is not queued", song.file))
+        };
 
         match direction {
             Direction::Forward => {
                 for song in queue.iter().cycle().skip(start) {
                     let current_song_duration =
-                        i64::try_from(song.duration.unwrap().as_secs())?;
+                        Time::from(song.duration.unwrap_or_default())
+                            .as_millis();
                     let remainder = current_song_duration - elapsed - place;
 
                     // seek position fits the current song
                     if remainder >= 0 {
-                        let position = song.place.unwrap().id;
-                        self.client.seek(position, elapsed + place)?;
+                        let position = id(song)?;
+                        let target = Time::from_millis(elapsed + place);
+                        self.client.seek(position, target.to_duration())?;
                         break;
                     }
 
@@ -311,11 +586,15 @@ impl Client {
             }
             Direction::Reverse => {
                 // queue is reversed so we need to start from the end
-                let start = queue.len() - start - 1;
+                let start = queue
+                    .len()
+                    .checked_sub(start + 1)
+                    .ok_or_else(|| eyre::eyre!("no current song"))?;
 
                 for song in queue.iter().rev().cycle().skip(start) {
                     let current_song_duration =
-                        i64::try_from(song.duration.unwrap().as_secs())?;
+                        Time::from(song.duration.unwrap_or_default())
+                            .as_millis();
 
                     let remainder = if elapsed > 0 {
                         elapsed - place
@@ -325,8 +604,9 @@ impl Client {
 
                     // seek position fits the current song
                     if remainder >= 0 {
-                        let position = song.place.unwrap().id;
-                        self.client.seek(position, remainder)?;
+                        let position = id(song)?;
+                        let target = Time::from_millis(remainder);
+                        self.client.seek(position, target.to_duration())?;
                         break;
                     }
 
@@ -344,6 +624,7 @@ impl Client {
     //
 
     pub fn clear(&mut self) -> eyre::Result<Option<String>> {
+        self.remember("clear")?;
         self.client.clear()?;
 
         self.current_status()
@@ -464,16 +745,309 @@ impl Client {
     }
 
     pub fn shuffle(&mut self) -> eyre::Result<Option<String>> {
+        self.remember("shuffle")?;
         self.client.shuffle(..)?;
 
         self.current_status()
     }
 
+    /// Removes the songs repeating another one from the queue
+    pub fn dedupe(
+        &mut self,
+        by: DedupeBy,
+        keep: Keep,
+    ) -> eyre::Result<Option<String>> {
+        self.remember("dedupe")?;
+        let songs = self.client.queue()?;
+        let repeated = queue::duplicates(&songs, by, keep);
+
+        if !repeated.is_empty() {
+            let lines: Vec<String> = repeated
+                .iter()
+                .map(|position| format!("delete {position}"))
+                .collect();
+            self.raw_list(&lines)?;
+        }
+
+        let mut removed: Vec<String> = repeated
+            .into_iter()
+            .map(|position| songs[position].file.clone())
+            .collect();
+        removed.reverse();
+
+        self.render(&Listing::from(removed))
+    }
+
+    /// Orders the queue by the given tags, moving songs in one command list
+    pub fn sort_queue(
+        &mut self,
+        tags: &[String],
+        reverse: bool,
+    ) -> eyre::Result<Option<String>> {
+        self.remember("sort-queue")?;
+        let songs = self.client.queue()?;
+        let order = queue::order(&songs, tags, reverse);
+        let lines: Vec<String> = queue::moves(&order)
+            .into_iter()
+            .map(|(from, to)| format!("move {from} {to}"))
+            .collect();
+
+        if !lines.is_empty() {
+            self.raw_list(&lines)?;
+        }
+
+        self.current_status()
+    }
+
+    /// The queue and where playback is in it
+    fn queue_state(&mut self) -> eyre::Result<QueueState> {
+        let status = self.client.status()?;
+        let songs = self
+            .client
+            .queue()?
+            .into_iter()
+            .map(|song| Queued {
+                priority: song.place.map_or(0, |place| place.prio),
+                file: song.file,
+            })
+            .collect();
+
+        Ok(QueueState {
+            songs,
+            position: status.song.map(|place| place.pos),
+            elapsed: status
+                .elapsed
+                .map_or(0.0, |elapsed| elapsed.as_secs_f64()),
+            state: crate::status::State::from(status.state).to_string(),
+        })
+    }
+
+    /// Saves the queue to the journal before `command` changes it. A
+    /// journal that cannot be written is only warned about, the command
+    /// going ahead without its undo.
+    fn remember(&mut self, command: &str) -> eyre::Result<()> {
+        let Some(path) = self.journal.clone() else {
+            return Ok(());
+        };
+
+        let snapshot = journal::Snapshot {
+            taken_at: chrono::Utc::now().timestamp(),
+            command: command.to_string(),
+            queue: self.queue_state()?,
+        };
+
+        if let Err(e) = journal::append(&path, &snapshot) {
+            eprintln!(
+                "warning: unable to write the undo journal {}: {e}",
+                path.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Brings back the queue as it was before the `number`th latest
+    /// destructive command, 1 being the latest. The queue being replaced
+    /// is journaled too, so an undo can be undone.
+    pub fn undo(&mut self, number: usize) -> eyre::Result<Option<String>> {
+        let path = self
+            .journal
+            .clone()
+            .ok_or_else(|| eyre::eyre!("the undo journal is disabled"))?;
+        let snapshots = journal::load(&path)?;
+        let queue = number
+            .checked_sub(1)
+            .and_then(|back| snapshots.into_iter().rev().nth(back))
+            .ok_or_else(|| eyre::eyre!("nothing to undo"))?
+            .queue;
+
+        self.remember("undo")?;
+
+        let mut lines = queue.queue_lines();
+        lines.extend(queue.playback_lines());
+        self.raw_list(&lines)?;
+
+        self.current_status()
+    }
+
+    /// The queue, playback, modes, volume and outputs, to be restored later
+    pub fn session(&mut self) -> eyre::Result<Session> {
+        let queue = self.queue_state()?;
+        let status: HashMap<String, String> =
+            self.raw("status")?.into_iter().collect();
+        let mode = |name: &str| {
+            status.get(name).cloned().unwrap_or_else(|| "0".to_string())
+        };
+
+        Ok(Session {
+            random: mode("random"),
+            repeat: mode("repeat"),
+            single: mode("single"),
+            consume: mode("consume"),
+            // -1 or missing without a mixer
+            volume: status.get("volume").and_then(|volume| volume.parse().ok()),
+            crossfade: status
+                .get("xfade")
+                .and_then(|xfade| xfade.parse().ok())
+                .unwrap_or(0),
+            outputs: self
+                .client
+                .outputs()?
+                .into_iter()
+                .filter(|output| output.enabled)
+                .map(|output| output.name)
+                .collect(),
+            queue,
+        })
+    }
+
+    /// Puts everything back as captured by `session`, in one command list.
+    /// The queue being replaced can be brought back with `undo`.
+    pub fn restore(&mut self, session: &Session) -> eyre::Result<()> {
+        self.remember("session restore")?;
+
+        let lines = self.session_lines(session, true)?;
+        self.raw_list(&lines)?;
+
+        Ok(())
+    }
+
+    /// Plays what another server was playing, as captured by `session`,
+    /// from the same point: its queue, modes and volume but not its
+    /// outputs. Songs missing from this server's library are left out.
+    pub fn take_over(&mut self, session: &Session) -> eyre::Result<Handoff> {
+        let missing = self.missing(&session.queue.songs, &[])?;
+
+        let mut session = session.clone();
+        session
+            .queue
+            .retain(|file| !missing.iter().any(|missing| missing == file));
+
+        self.remember("handoff")?;
+        let lines = self.session_lines(&session, false)?;
+        self.raw_list(&lines)?;
+
+        Ok(Handoff {
+            to: self.address.clone(),
+            queued: session.queue.songs.len(),
+            missing,
+        })
+    }
+
+    /// Follows another server, whose state is captured by `session`: see
+    /// `QueueState::mirror_lines`. Songs missing from this server's library
+    /// are left out. The commands sent are returned, none when already in
+    /// line.
+    pub fn mirror(
+        &mut self,
+        session: &Session,
+        drift: f64,
+    ) -> eyre::Result<Vec<String>> {
+        let current = self.queue_state()?;
+        let missing = self.missing(&session.queue.songs, &current.songs)?;
+
+        let mut wanted = session.queue.clone();
+        wanted.retain(|file| !missing.iter().any(|missing| missing == file));
+
+        let lines = wanted.mirror_lines(&current, drift);
+        if !lines.is_empty() {
+            self.raw_list(&lines)?;
+        }
+
+        Ok(lines)
+    }
+
+    /// Files of `songs` not in the library, URLs and the files of `known`
+    /// being taken as there
+    fn missing(
+        &mut self,
+        songs: &[Queued],
+        known: &[Queued],
+    ) -> eyre::Result<Vec<String>> {
+        let unknown: Vec<&String> = songs
+            .iter()
+            .map(|song| &song.file)
+            .filter(|file| {
+                !playlist_file::is_url(file)
+                    && !known.iter().any(|known| &known.file == *file)
+            })
+            .collect();
+        if unknown.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let library: HashSet<String> =
+            self.files_for(None)?.into_iter().collect();
+        let mut missing = Vec::new();
+        for file in unknown {
+            if !library.contains(file) && !missing.contains(file) {
+                missing.push(file.clone());
+            }
+        }
+
+        Ok(missing)
+    }
+
+    /// The command list bringing back `session`, with its outputs if
+    /// `outputs`
+    fn session_lines(
+        &mut self,
+        session: &Session,
+        outputs: bool,
+    ) -> eyre::Result<Vec<String>> {
+        let mut lines = session.queue.queue_lines();
+        for (name, value) in [
+            ("random", &session.random),
+            ("repeat", &session.repeat),
+            ("single", &session.single),
+            ("consume", &session.consume),
+        ] {
+            lines.push(format!("{name} {}", raw::quote(value)));
+        }
+        lines.push(format!("crossfade {}", session.crossfade));
+        if let Some(volume) = session.volume {
+            lines.push(format!("setvol {volume}"));
+        }
+        if outputs {
+            for output in self.client.outputs()? {
+                let command = if session.outputs.contains(&output.name) {
+                    "enableoutput"
+                } else {
+                    "disableoutput"
+                };
+                lines.push(format!("{command} {}", output.id));
+            }
+        }
+        lines.extend(session.queue.playback_lines());
+
+        Ok(lines)
+    }
+
+    /// The journaled queues, latest first
+    pub fn undo_list(&mut self) -> eyre::Result<Option<String>> {
+        let snapshots = match &self.journal {
+            Some(path) => journal::load(path)?,
+            None => vec![],
+        };
+
+        self.render(&Summaries::from(snapshots))
+    }
+
+    /// Names of the stored playlists
+    pub fn playlist_names(&mut self) -> eyre::Result<Vec<String>> {
+        Ok(self
+            .client
+            .playlists()?
+            .into_iter()
+            .map(|p| p.name)
+            .collect())
+    }
+
     pub fn lsplaylists(&mut self) -> eyre::Result<Option<String>> {
-        let playlists = self.client.playlists()?;
-        let playlists: Vec<Playlist> = playlists
+        let playlists: Vec<Playlist> = self
+            .playlist_names()?
             .into_iter()
-            .map(|p| Playlist::from(p.name))
+            .map(Playlist::from)
             .collect();
         let playlists = Playlists { playlists };
 
@@ -491,6 +1065,7 @@ impl Client {
         name: &String,
         range: Option<String>,
     ) -> eyre::Result<Option<String>> {
+        self.remember("load")?;
         match range {
             Some(range_str) => {
                 let range_or_index = range::Parser::new(&range_str)?;
@@ -514,10 +1089,15 @@ impl Client {
         &mut self,
         file: Option<&str>,
     ) -> Result<Vec<String>, eyre::Error> {
-        let all_files = Listing::from(self.client.listall()?);
+        // without a fresh cache, the file names alone are far lighter to
+        // ask for than the whole `library`
+        let songs = match self.fresh_library()? {
+            Some(songs) => songs,
+            None => self.client.listall()?,
+        };
+        let all_files = Listing::from(songs);
 
         let files = if let Some(ref file) = file {
-            // TODO: this is inefficient but it's the only way I see at the moment
             all_files
                 .listing
                 .iter()
@@ -536,7 +1116,7 @@ impl Client {
 
         for file in &files {
             let song = mpd::song::Song {
-                file: file.to_string(),
+                file: file.clone(),
                 ..Default::default()
             };
 
@@ -570,21 +1150,36 @@ impl Client {
             self.client.priority(position_or_range.range, priority)?;
         } else {
             self.client.priority(position_or_range.index, priority)?;
-        };
+        }
 
         Ok(None)
     }
 
+    /// Songs in the named playlist or, if `None`, in the queue
+    pub fn songs(
+        &mut self,
+        name: Option<&str>,
+    ) -> eyre::Result<Vec<mpd::Song>> {
+        let songs = match name {
+            Some(name) => self.client.playlist(name)?,
+            None => self.client.queue()?,
+        };
+
+        Ok(songs)
+    }
+
+    /// Removes the songs at the given queue positions
+    pub fn delete(&mut self, positions: Range<u32>) -> eyre::Result<()> {
+        Ok(self.client.delete(positions)?)
+    }
+
     pub fn playlist(
         &mut self,
-        name: Option<String>,
+        name: Option<&str>,
     ) -> eyre::Result<Option<String>> {
         // if given a name list songs in that playlist
         // if `None` list songs in current playlist
-        let songs = match name {
-            Some(name) => self.client.playlist(&name)?,
-            None => self.client.queue()?,
-        };
+        let songs = self.songs(name)?;
 
         let songs: Vec<Current> = songs
             .into_iter()
@@ -601,6 +1196,137 @@ impl Client {
         Ok(Some(response))
     }
 
+    /// A stored playlist (or, if `None`, the queue) as a playlist file
+    pub fn export(
+        &mut self,
+        name: Option<&str>,
+        format: ExportFormat,
+    ) -> eyre::Result<Option<String>> {
+        let entries: Vec<Entry> =
+            self.songs(name)?.iter().map(Entry::from).collect();
+
+        Ok(Some(playlist_file::write(format, &entries)?))
+    }
+
+    /// Creates a stored playlist from a playlist file, named after the file
+    /// unless `name` is given. Entries are looked up in the library by path
+    /// and, for files listed on another machine, by artist and title;
+    /// those not found are reported.
+    pub fn import(
+        &mut self,
+        path: &str,
+        name: Option<&str>,
+    ) -> eyre::Result<Option<String>> {
+        let path = Path::new(path);
+        let entries = playlist_file::read(path)?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| eyre::eyre!("invalid playlist file name"))?
+                .to_string(),
+        };
+
+        if self.playlist_names()?.contains(&name) {
+            return Err(eyre::eyre!("Playlist already exists: {name}"));
+        }
+
+        // only known when connected over the unix socket
+        let music_dir = self.client.music_directory().ok();
+        let mut imported = Imported {
+            playlist: name,
+            added: 0,
+            unresolved: vec![],
+        };
+
+        for entry in entries {
+            match self.resolve(&entry, music_dir.as_deref())? {
+                Some(uri) => {
+                    let song = mpd::Song {
+                        file: uri,
+                        ..Default::default()
+                    };
+                    self.client.pl_push(imported.playlist.as_str(), &song)?;
+                    imported.added += 1;
+                }
+                None => imported.unresolved.push(entry.file),
+            }
+        }
+
+        self.render(&imported)
+    }
+
+    /// The library URI of a playlist entry: URLs are kept, paths are tried
+    /// relative to the music directory and then with leading directories
+    /// dropped, before falling back to the tags
+    fn resolve(
+        &mut self,
+        entry: &Entry,
+        music_dir: Option<&str>,
+    ) -> eyre::Result<Option<String>> {
+        if playlist_file::is_url(&entry.file)
+            && !entry.file.starts_with("file://")
+        {
+            return Ok(Some(entry.file.clone()));
+        }
+
+        let path = playlist_file::file_url_path(&entry.file)
+            .unwrap_or_else(|| entry.file.clone());
+        let path = Path::new(&path);
+        let relative = music_dir
+            .and_then(|music_dir| path.strip_prefix(music_dir).ok())
+            .unwrap_or(path);
+
+        let components: Vec<&str> = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect();
+        for start in 0..components.len() {
+            let uri = components[start..].join("/");
+            let mut query = mpd::Query::new();
+            query.and(mpd::Term::File, uri.as_str());
+
+            if !self.client.find(&query, None)?.is_empty() {
+                return Ok(Some(uri));
+            }
+        }
+
+        self.find_by_tags(entry)
+    }
+
+    /// The song with the entry's artist and title, the closest in length
+    /// when the entry has one
+    fn find_by_tags(&mut self, entry: &Entry) -> eyre::Result<Option<String>> {
+        const TOLERANCE: u64 = 3;
+
+        let (Some(artist), Some(title)) = (&entry.artist, &entry.title) else {
+            return Ok(None);
+        };
+        let mut query = mpd::Query::new();
+        query
+            .and(mpd::Term::Tag("artist".into()), artist.as_str())
+            .and(mpd::Term::Tag("title".into()), title.as_str());
+        let songs = self.client.find(&query, None)?;
+
+        let Some(expected) = entry.duration else {
+            return Ok(songs.into_iter().next().map(|song| song.file));
+        };
+
+        Ok(songs
+            .into_iter()
+            .filter_map(|song| {
+                let length = song.duration?.as_secs();
+                Some((length.abs_diff(expected), song.file))
+            })
+            .filter(|(difference, _)| *difference <= TOLERANCE)
+            .min()
+            .map(|(_, file)| file))
+    }
+
     pub fn listall(
         &mut self,
         file: Option<&str>,
@@ -616,10 +1342,11 @@ impl Client {
         Ok(Some(response))
     }
 
-    pub fn ls(
+    /// Paths of the entries of a directory (defaults to `music_directory`)
+    pub fn directory_listing(
         &mut self,
         directory: Option<&str>,
-    ) -> eyre::Result<Option<String>> {
+    ) -> eyre::Result<Vec<String>> {
         let directory = directory.unwrap_or("");
         let listing = self.client.listfiles(directory)?;
         let filter_for = if let Some(entry) = listing.first() {
@@ -628,20 +1355,24 @@ impl Client {
             "directory"
         };
 
-        let results = Listing::from(
-            listing
-                .clone()
-                .into_iter()
-                .filter(|(key, _)| key == filter_for)
-                .map(|(_, value)| {
-                    PathBuf::from(&directory)
-                        .join(value)
-                        .to_str()
-                        .unwrap()
-                        .to_string()
-                })
-                .collect::<Vec<String>>(),
-        );
+        Ok(listing
+            .iter()
+            .filter(|(key, _)| key == filter_for)
+            .map(|(_, value)| {
+                PathBuf::from(&directory)
+                    .join(value)
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect())
+    }
+
+    pub fn ls(
+        &mut self,
+        directory: Option<&str>,
+    ) -> eyre::Result<Option<String>> {
+        let results = Listing::from(self.directory_listing(directory)?);
 
         let response = match self.format {
             OutputFormat::Json => serde_json::to_string(&results)?,
@@ -694,8 +1425,21 @@ impl Client {
         self.current_status()
     }
 
-    // TODO: better name or abstraction?
-    pub fn _search(
+    /// Stops playback once the current song ends. Uses the `oneshot` single
+    /// mode where the server supports it (MPD 0.21+) and returns `true`,
+    /// otherwise enables single mode, which the caller has to turn off again.
+    pub fn stop_after_current(&mut self) -> eyre::Result<bool> {
+        if self.client.version < mpd::Version(0, 21, 0) {
+            self.client.single(true)?;
+            return Ok(false);
+        }
+
+        self.raw("single \"oneshot\"")?;
+
+        Ok(true)
+    }
+
+    pub fn search_songs(
         &mut self,
         tag: &str,
         query: &str,
@@ -707,8 +1451,7 @@ impl Client {
         Ok(self.client.search(songs, None)?)
     }
 
-    // TODO: better name or abstraction?
-    pub fn _find(
+    pub fn find_songs(
         &mut self,
         tag: &str,
         query: &str,
@@ -725,7 +1468,7 @@ impl Client {
         tag: &str,
         query: &str,
     ) -> eyre::Result<Option<String>> {
-        let songs = self._search(tag, query)?;
+        let songs = self.search_songs(tag, query)?;
         let files = Listing::from(songs);
 
         let response = match self.format {
@@ -742,7 +1485,7 @@ impl Client {
         tag: &str,
         query: &str,
     ) -> eyre::Result<Option<String>> {
-        let songs = self._search(tag, query)?;
+        let songs = self.search_songs(tag, query)?;
 
         for song in songs {
             self.client
@@ -753,12 +1496,376 @@ impl Client {
         Ok(None)
     }
 
+    //
+    // random picking commands
+    //
+
+    /// Queues every song of a random album, among those with songs
+    /// matching `filter`, in track order
+    pub fn random_album(
+        &mut self,
+        filter: Option<&str>,
+        replace: bool,
+        play: bool,
+    ) -> eyre::Result<Option<String>> {
+        let mut albums = Vec::new();
+        let mut artist = String::new();
+        for (key, value) in self.raw(&list_command("album", filter, true))? {
+            if key.eq_ignore_ascii_case("AlbumArtist") {
+                artist = value;
+            } else if key.eq_ignore_ascii_case("Album") && !value.is_empty() {
+                albums.push((artist.clone(), value));
+            }
+        }
+
+        let (artist, album) = albums
+            .get(random::below(albums.len()))
+            .ok_or_else(|| eyre::eyre!("no albums to pick from"))?
+            .clone();
+        let expression = all_of(
+            vec![
+                tag_filter("album", &album),
+                tag_filter("albumartist", &artist),
+            ],
+            filter,
+        );
+        let mut songs = self.filter(Some(&expression))?;
+        songs.sort_by(song::track_order);
+
+        let picked = if artist.is_empty() {
+            album
+        } else {
+            format!("{artist} - {album}")
+        };
+        self.queue_picked(picked, &songs, replace, play)
+    }
+
+    /// Queues the songs of a random album artist, among those with songs
+    /// matching `filter`, album by album
+    pub fn random_artist(
+        &mut self,
+        filter: Option<&str>,
+        replace: bool,
+        play: bool,
+    ) -> eyre::Result<Option<String>> {
+        let artists: Vec<String> = self
+            .raw(&list_command("albumartist", filter, false))?
+            .into_iter()
+            .map(|(_, value)| value)
+            .filter(|artist| !artist.is_empty())
+            .collect();
+
+        let artist = artists
+            .get(random::below(artists.len()))
+            .ok_or_else(|| eyre::eyre!("no artists to pick from"))?
+            .clone();
+        let expression =
+            all_of(vec![tag_filter("albumartist", &artist)], filter);
+        let mut songs = self.filter(Some(&expression))?;
+        songs.sort_by(song::discography_order);
+
+        self.queue_picked(artist, &songs, replace, play)
+    }
+
+    /// Queues `count` random songs among those matching `filter`
+    pub fn random_songs(
+        &mut self,
+        count: usize,
+        filter: Option<&str>,
+        replace: bool,
+        play: bool,
+    ) -> eyre::Result<Option<String>> {
+        let mut songs = self.filter(filter)?;
+        if songs.is_empty() {
+            return Err(eyre::eyre!("no songs to pick from"));
+        }
+        random::shuffle(&mut songs);
+        songs.truncate(count);
+
+        self.queue_picked(
+            format!("{} songs", songs.len()),
+            &songs,
+            replace,
+            play,
+        )
+    }
+
+    fn queue_picked(
+        &mut self,
+        picked: String,
+        songs: &[mpd::Song],
+        replace: bool,
+        play: bool,
+    ) -> eyre::Result<Option<String>> {
+        if replace {
+            self.remember("random")?;
+            self.client.clear()?;
+        }
+        let start = self.client.status()?.queue_len;
+
+        self.enqueue(songs)?;
+        if play && !songs.is_empty() {
+            self.client.switch(start)?;
+        }
+
+        self.render(&Picked {
+            picked,
+            listing: songs.iter().map(|song| song.file.clone()).collect(),
+        })
+    }
+
+    /// Every song in the database with its tags, from the cache when it
+    /// is as recent as the server's database
+    pub fn library(&mut self) -> eyre::Result<Vec<mpd::Song>> {
+        let Some(path) = self.cache.clone() else {
+            return self.fetch_library();
+        };
+        let db_update = self.db_update()?;
+
+        let snapshot = match Snapshot::load(&path) {
+            Some(cached) if cached.db_update == db_update => {
+                return Ok(cached.songs);
+            }
+            cached => self.snapshot(cached, db_update)?,
+        };
+        // without a writable cache directory things are only slower
+        let _ = snapshot.save(&path);
+
+        Ok(snapshot.songs)
+    }
+
+    /// Songs matching an MPD filter expression (eg. `(genre == "Jazz")`),
+    /// or the whole library without one
+    pub fn filter(
+        &mut self,
+        expression: Option<&str>,
+    ) -> eyre::Result<Vec<mpd::Song>> {
+        match expression {
+            Some(expression) => Ok(song::from_pairs(
+                self.raw(&format!("find {}", raw::quote(expression)))?,
+            )),
+            None => self.library(),
+        }
+    }
+
+    /// The value of the sticker `name` of every song that has one, by file
+    pub fn stickers(
+        &mut self,
+        name: &str,
+    ) -> eyre::Result<HashMap<String, String>> {
+        let pairs = self
+            .raw(&format!("sticker find \"song\" \"\" {}", raw::quote(name)))?;
+        let mut stickers = HashMap::new();
+        let mut file = None;
+
+        for (key, value) in pairs {
+            match key.as_str() {
+                "file" => file = Some(value),
+                "sticker" => {
+                    let value = value
+                        .strip_prefix(name)
+                        .and_then(|value| value.strip_prefix('='));
+                    if let (Some(file), Some(value)) = (file.take(), value) {
+                        stickers.insert(file, value.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(stickers)
+    }
+
+    /// Appends songs to the queue
+    pub fn enqueue(&mut self, songs: &[mpd::Song]) -> eyre::Result<()> {
+        for song in songs {
+            self.push(song.file.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores songs as a new playlist
+    pub fn save_songs(
+        &mut self,
+        name: &str,
+        songs: &[mpd::Song],
+    ) -> eyre::Result<()> {
+        if self
+            .playlist_names()?
+            .iter()
+            .any(|playlist| playlist == name)
+        {
+            return Err(eyre::eyre!("Playlist already exists: {name}"));
+        }
+
+        for song in songs {
+            self.client.pl_push(name, song)?;
+        }
+
+        Ok(())
+    }
+
+    /// The cached library, only when it is as recent as the server's
+    /// database
+    fn fresh_library(&mut self) -> eyre::Result<Option<Vec<mpd::Song>>> {
+        let Some(path) = self.cache.clone() else {
+            return Ok(None);
+        };
+        let db_update = self.db_update()?;
+
+        Ok(Snapshot::load(&path)
+            .filter(|cached| cached.db_update == db_update)
+            .map(|cached| cached.songs))
+    }
+
+    fn db_update(&mut self) -> eyre::Result<u64> {
+        Ok(self.client.stats()?.db_update.as_secs())
+    }
+
+    fn fetch_library(&mut self) -> eyre::Result<Vec<mpd::Song>> {
+        Ok(song::from_pairs(self.raw("listallinfo")?))
+    }
+
+    /// Lists the library again. With an older snapshot only the songs
+    /// modified since are fetched, unless too many files are new to it.
+    fn snapshot(
+        &mut self,
+        cached: Option<Snapshot>,
+        db_update: u64,
+    ) -> eyre::Result<Snapshot> {
+        const MAX_LOOKUPS: usize = 100;
+
+        let songs = match cached {
+            None => self.fetch_library()?,
+            Some(snapshot) => {
+                let since = raw::quote(&snapshot.db_update.to_string());
+                let modified = song::from_pairs(
+                    self.raw(&format!("find modified-since {since}"))?,
+                );
+                let mut known: HashMap<String, mpd::Song> = snapshot
+                    .songs
+                    .into_iter()
+                    .chain(modified)
+                    .map(|song| (song.file.clone(), song))
+                    .collect();
+
+                let files: Vec<String> = self
+                    .client
+                    .listall()?
+                    .into_iter()
+                    .map(|song| song.file)
+                    .collect();
+                let missing = files
+                    .iter()
+                    .filter(|file| !known.contains_key(*file))
+                    .count();
+
+                if missing > MAX_LOOKUPS {
+                    self.fetch_library()?
+                } else {
+                    let mut songs = Vec::with_capacity(files.len());
+                    for file in files {
+                        if let Some(song) = known.remove(&file) {
+                            songs.push(song);
+                        } else {
+                            let mut query = mpd::Query::new();
+                            query.and(mpd::Term::File, file.as_str());
+                            songs.extend(self.client.find(&query, None)?);
+                        }
+                    }
+                    songs
+                }
+            }
+        };
+
+        Ok(Snapshot { db_update, songs })
+    }
+
+    /// Brings the library cache up to date, from scratch if `full`
+    pub fn cache_refresh(
+        &mut self,
+        full: bool,
+    ) -> eyre::Result<Option<String>> {
+        if let Some(path) = self.cache.clone() {
+            let cached = if full { None } else { Snapshot::load(&path) };
+            let db_update = self.db_update()?;
+            self.snapshot(cached, db_update)?
+                .save(&path)
+                .wrap_err(format!(
+                    "unable to write the cache: {}",
+                    path.display()
+                ))?;
+        }
+
+        self.cache_status()
+    }
+
+    pub fn cache_clear(&mut self) -> eyre::Result<Option<String>> {
+        if let Some(path) = &self.cache {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        self.cache_status()
+    }
+
+    pub fn cache_status(&mut self) -> eyre::Result<Option<String>> {
+        let db_update = self.db_update()?;
+        let snapshot = self.cache.as_deref().and_then(Snapshot::load);
+        let status = cache::Status {
+            path: self.cache.clone(),
+            songs: snapshot.as_ref().map_or(0, |snapshot| snapshot.songs.len()),
+            cached: snapshot.as_ref().map(|snapshot| snapshot.db_update),
+            db_update,
+            fresh: snapshot
+                .is_some_and(|snapshot| snapshot.db_update == db_update),
+        };
+
+        self.render(&status)
+    }
+
+    /// Searches the library client-side, forgiving typos and accents (or
+    /// matching a regex), and lists the best `limit` hits, optionally
+    /// queueing them too
+    pub fn fuzzy(
+        &mut self,
+        query: &str,
+        field: Field,
+        regex: bool,
+        limit: usize,
+        enqueue: Option<Enqueue>,
+    ) -> eyre::Result<Option<String>> {
+        let matcher = Matcher::new(query, regex)?;
+        let mut hits = fuzzy::rank(self.library()?, field, &matcher);
+        hits.truncate(limit);
+
+        match enqueue {
+            Some(Enqueue::Add) => {
+                for song in &hits {
+                    self.client.push(song)?;
+                }
+            }
+            Some(Enqueue::Insert) => {
+                let next =
+                    self.client.status()?.song.map_or(0, |place| place.pos + 1);
+                for (offset, song) in hits.iter().enumerate() {
+                    self.client.insert(song, next as usize + offset)?;
+                }
+            }
+            None => {}
+        }
+
+        self.render(&Listing::from(hits))
+    }
+
     pub fn find(
         &mut self,
         tag: &str,
         query: &str,
     ) -> eyre::Result<Option<String>> {
-        let songs = self._find(tag, query)?;
+        let songs = self.find_songs(tag, query)?;
         let files = Listing::from(songs);
 
         let response = match self.format {
@@ -775,7 +1882,7 @@ impl Client {
         tag: &str,
         query: &str,
     ) -> eyre::Result<Option<String>> {
-        let songs = self._find(tag, query)?;
+        let songs = self.find_songs(tag, query)?;
 
         for song in songs {
             self.client
@@ -786,12 +1893,16 @@ impl Client {
         Ok(None)
     }
 
-    pub fn list(&mut self, tag: &str) -> eyre::Result<Option<String>> {
+    /// All values of the given tag type (eg. albums)
+    pub fn tag_values(&mut self, tag: &str) -> eyre::Result<Vec<String>> {
         let term = mpd::Term::Tag(tag.into());
         let query = mpd::Query::new();
 
-        let results = self.client.list(&term, &query)?;
-        let files = Listing::from(results);
+        Ok(self.client.list(&term, &query)?)
+    }
+
+    pub fn list(&mut self, tag: &str) -> eyre::Result<Option<String>> {
+        let files = Listing::from(self.tag_values(tag)?);
 
         let response = match self.format {
             OutputFormat::Json => serde_json::to_string(&files)?,
@@ -884,31 +1995,120 @@ impl Client {
     // volume related commands
     //
 
+    /// The current level, `None` when no output has a mixer. Read with
+    /// `getvol` where the server supports it (MPD 0.23+).
+    pub fn volume_level(&mut self) -> eyre::Result<Option<u8>> {
+        let level = if self.client.version >= mpd::Version(0, 23, 0) {
+            self.raw("getvol")?
+                .into_iter()
+                .find(|(key, _)| key == "volume")
+                .and_then(|(_, value)| value.parse::<i8>().ok())
+        } else {
+            Some(self.client.status()?.volume)
+        };
+
+        Ok(level.and_then(|level| u8::try_from(level).ok()))
+    }
+
+    pub fn volume(&mut self) -> eyre::Result<Option<String>> {
+        let volume = self.volume_level()?;
+        let muted = volume == Some(0)
+            && volume::muted_level(&self.mute_file()).is_some();
+
+        self.render(&Volume {
+            volume,
+            muted: OnOff::from(muted),
+        })
+    }
+
     pub fn set_volume(&mut self, input: &str) -> eyre::Result<Option<String>> {
-        let current = self.client.status()?.volume;
+        let change: volume::Change = input.parse()?;
+        let current = self.mixer_level()?;
 
-        let target = match input {
-            matched if matched.starts_with('+') => {
-                if let Ok(volume) = matched[1..].parse::<i8>() {
-                    current.checked_add(volume).unwrap_or(100).min(100)
-                } else {
-                    panic!("Invalid volume increment, must be between 1-100")
-                }
-            }
-            matched if matched.starts_with('-') => {
-                if let Ok(volume) = matched[1..].parse::<i8>() {
-                    current.checked_sub(volume).unwrap_or(100).max(0)
-                } else {
-                    panic!("Invalid volume increment, must be between 1-100")
-                }
-            }
-            _ => input.parse::<i8>().unwrap_or(0),
-        };
+        self.client.volume(i8::try_from(change.apply(current))?)?;
 
-        self.client
-            .volume(target)
-            .map(|()| None)
-            .map_err(eyre::Report::from)
+        self.volume()
+    }
+
+    /// Like `set_volume` but moves one percent at a time over `over`
+    pub fn ramp_volume(
+        &mut self,
+        input: &str,
+        over: Duration,
+    ) -> eyre::Result<Option<String>> {
+        let change: volume::Change = input.parse()?;
+        let current = self.mixer_level()?;
+        let target = change.apply(current);
+        let steps = u32::from(current.abs_diff(target));
+
+        for step in 1..=steps {
+            thread::sleep(over / steps);
+            let level =
+                volume::ramp(current, target, over * step / steps, over);
+            self.client.volume(i8::try_from(level)?)?;
+        }
+
+        self.volume()
+    }
+
+    /// Silences the output, remembering the level for `unmute`
+    pub fn mute(&mut self) -> eyre::Result<Option<String>> {
+        let current = self.mixer_level()?;
+
+        // muting twice must not forget the level to go back to
+        if current > 0 {
+            volume::remember(&self.mute_file(), current)?;
+        }
+        self.client.volume(0)?;
+
+        self.volume()
+    }
+
+    pub fn unmute(&mut self) -> eyre::Result<Option<String>> {
+        let file = self.mute_file();
+        let level = volume::muted_level(&file)
+            .ok_or_else(|| eyre::eyre!("volume is not muted"))?;
+
+        self.mixer_level()?;
+        self.client.volume(i8::try_from(level)?)?;
+        volume::forget(&file)?;
+
+        self.volume()
+    }
+
+    pub fn toggle_mute(&mut self) -> eyre::Result<Option<String>> {
+        let muted = self.mixer_level()? == 0
+            && volume::muted_level(&self.mute_file()).is_some();
+
+        if muted {
+            self.unmute()
+        } else {
+            self.mute()
+        }
+    }
+
+    fn mixer_level(&mut self) -> eyre::Result<u8> {
+        self.volume_level()?.ok_or_else(|| {
+            eyre::eyre!("no mixer: the volume cannot be changed")
+        })
+    }
+
+    fn mute_file(&self) -> PathBuf {
+        volume::mute_file(&self.address)
+    }
+
+    //
+    // event related commands
+    //
+
+    /// Blocks until one of the given subsystems changes (any if empty)
+    pub fn wait(&mut self, events: &[Event]) -> eyre::Result<Vec<Event>> {
+        let subsystems: Vec<mpd::Subsystem> =
+            events.iter().filter_map(|e| e.subsystem()).collect();
+
+        let changed = self.client.wait(&subsystems)?;
+
+        Ok(changed.into_iter().map(Event::from).collect())
     }
 
     //
@@ -946,7 +2146,7 @@ impl Client {
             None => 0,
         };
 
-        let time = crate::time::Track::from(status.time);
+        let time = Track::from(&status);
 
         let file_path =
             self.client.currentsong()?.map(|song| song.file.clone());
@@ -961,6 +2161,8 @@ impl Client {
             queue_count: status.queue_len,
             elapsed: time.elapsed,
             track_length: time.total,
+            elapsed_secs: time.elapsed.as_secs_f64(),
+            track_length_secs: time.total.as_secs_f64(),
             repeat: OnOff::from(status.repeat),
             random: OnOff::from(status.random),
             single: OnOff::from(status.single),
@@ -969,6 +2171,85 @@ impl Client {
         })
     }
 
+    /// The song currently playing (or paused), if any
+    pub fn current_song(&mut self) -> eyre::Result<Option<mpd::Song>> {
+        Ok(self.client.currentsong()?)
+    }
+
+    /// Lyrics of `uri` (the current song by default) from the first of: an
+    /// `.lrc` or `.txt` file next to it (MPD only tells clients on its
+    /// Unix socket where that is), its lyrics tags, or an `Artist - Title`
+    /// file in `directory`. With `synced`, only synced lyrics will do.
+    pub fn lyrics(
+        &mut self,
+        uri: Option<&str>,
+        directory: Option<&Path>,
+        synced: bool,
+    ) -> eyre::Result<Lyrics> {
+        let song = match uri {
+            Some(uri) => {
+                let mut query = mpd::Query::new();
+                query.and(mpd::Term::File, uri);
+                self.client
+                    .find(&query, None)?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| {
+                        eyre::eyre!("not found in the mpd database: {uri}")
+                    })?
+            }
+            None => self
+                .client
+                .currentsong()?
+                .ok_or_else(|| eyre::eyre!("no song is playing"))?,
+        };
+
+        let mut found = Vec::new();
+        if let Ok(music_dir) = self.client.music_directory() {
+            let paths = lyrics::beside(Path::new(&music_dir), &song.file);
+            found.extend(lyrics::read(&song.file, &paths));
+        }
+        let comments = self
+            .raw(&format!("readcomments {}", raw::quote(&song.file)))
+            .unwrap_or_default();
+        for name in lyrics::TAGS {
+            let text: Vec<&str> = comments
+                .iter()
+                .filter(|(tag, _)| tag.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+                .collect();
+            if !text.is_empty() {
+                found.push(Lyrics::parse(&song.file, name, &text.join("\n")));
+            }
+        }
+        if let (Some(directory), Some(artist), Some(title)) = (
+            directory,
+            song::tag(&song, "artist"),
+            song::tag(&song, "title"),
+        ) {
+            let paths = lyrics::in_directory(directory, artist, title);
+            found.extend(lyrics::read(&song.file, &paths));
+        }
+
+        found
+            .into_iter()
+            .find(|lyrics| lyrics.synced || !synced)
+            .ok_or_else(|| {
+                let kind = if synced { "synced lyrics" } else { "lyrics" };
+                eyre::eyre!("no {kind} found for {}", song.file)
+            })
+    }
+
+    /// Raw bytes of the cover art MPD finds for the given song
+    pub fn albumart(&mut self, uri: &str) -> eyre::Result<Vec<u8>> {
+        let song = mpd::Song {
+            file: uri.to_string(),
+            ..Default::default()
+        };
+
+        Ok(self.client.albumart(&song)?)
+    }
+
     pub fn current_status(&mut self) -> eyre::Result<Option<String>> {
         let status = self.status()?;
         let response = match self.format {
@@ -979,4 +2260,265 @@ impl Client {
 
         Ok(Some(response))
     }
+
+    /// Sends a command `mpd::Client` has no method for over a separate
+    /// connection
+    fn raw(&mut self, line: &str) -> eyre::Result<Vec<(String, String)>> {
+        raw::Connection::open(&self.address)?.command(line)
+    }
+
+    fn raw_list(
+        &mut self,
+        lines: &[String],
+    ) -> eyre::Result<Vec<(String, String)>> {
+        raw::Connection::open(&self.address)?.command_list(lines)
+    }
+
+    /// Formats a value computed outside the client the same way the
+    /// client's own commands are formatted
+    pub fn render<T: Serialize + fmt::Display>(
+        &self,
+        value: &T,
+    ) -> eyre::Result<Option<String>> {
+        let response = match self.format {
+            OutputFormat::Json => serde_json::to_string(value)?,
+            OutputFormat::Text => value.to_string(),
+            OutputFormat::None => NO_OUT,
+        };
+
+        Ok(Some(response))
+    }
+}
+
+/// `list <tag> [filter]`, grouped by album artist if `grouped`
+fn list_command(tag: &str, filter: Option<&str>, grouped: bool) -> String {
+    let mut command = format!("list {tag}");
+    if let Some(filter) = filter {
+        command.push(' ');
+        command.push_str(&raw::quote(filter));
+    }
+    if grouped {
+        command.push_str(" group albumartist");
+    }
+
+    command
+}
+
+/// An MPD filter expression matching songs whose `tag` is `value`
+fn tag_filter(tag: &str, value: &str) -> String {
+    format!("({tag} == {})", raw::quote(value))
+}
+
+/// An MPD filter expression matching all of `expressions` and `filter`
+fn all_of(mut expressions: Vec<String>, filter: Option<&str>) -> String {
+    expressions.extend(filter.map(String::from));
+
+    if expressions.len() == 1 {
+        expressions.remove(0)
+    } else {
+        format!("({})", expressions.join(" AND "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{parse_line, FakeServer};
+
+    #[test]
+    fn test_new_reports_an_unreachable_server() {
+        let dir = tempdir::TempDir::new("client").unwrap();
+        let socket = dir.path().join("socket");
+
+        let error = super::Client::new(socket.to_str().unwrap(), "6600", None)
+            .err()
+            .unwrap();
+
+        assert_eq!(error.to_string(), "Error connecting to mpd server");
+    }
+
+    #[test]
+    fn test_add_resolves_paths_on_the_server() {
+        let server = FakeServer::start(|line| match parse_line(line) {
+            (command, args) if command == "lsinfo" && args[0] == "Nope" => {
+                Err("No such directory".to_string())
+            }
+            _ => Ok(String::new()),
+        });
+        let mut client = server.client();
+
+        client.add("Artist/Album/").unwrap();
+        client.add("/").unwrap();
+        // not walked on this machine, which may not be the server's
+        client.add("/Artist/Album").unwrap();
+        client.add("sets/friday.m3u").unwrap();
+        let error = client.add("Nope").unwrap_err();
+        assert_eq!(error.to_string(), "not found in the mpd database: Nope");
+
+        let commands = server.commands();
+        for expected in [
+            r#"lsinfo "Artist/Album""#,
+            r#"add "Artist/Album""#,
+            r#"add "/""#,
+            r#"load "sets/friday.m3u" "0:""#,
+        ] {
+            assert!(commands.contains(&expected.to_string()), "{expected}");
+        }
+        assert!(!commands.contains(&r#"add "Nope""#.to_string()));
+        assert!(!commands.contains(&"config".to_string()));
+    }
+
+    #[test]
+    fn test_random_album_queues_tracks_in_order() {
+        let server = FakeServer::start(|line| {
+            let body = match parse_line(line) {
+                (command, args) if command == "list" => {
+                    assert_eq!(
+                        args,
+                        [
+                            "album",
+                            "(genre == \"Jazz\")",
+                            "group",
+                            "albumartist"
+                        ]
+                    );
+                    "AlbumArtist: Miles Davis\nAlbum: Kind of Blue\n"
+                }
+                (command, args) if command == "find" => {
+                    assert_eq!(
+                        args,
+                        [concat!(
+                            r#"((album == "Kind of Blue") AND "#,
+                            r#"(albumartist == "Miles Davis") AND "#,
+                            r#"(genre == "Jazz"))"#
+                        )]
+                    );
+                    "file: 2.flac\nTrack: 2\nfile: 1.flac\nTrack: 1\n"
+                }
+                (command, _) if command == "addid" => "Id: 1\n",
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+
+        let picked = client
+            .random_album(Some(r#"(genre == "Jazz")"#), true, true)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            picked,
+            r#"{"picked":"Miles Davis - Kind of Blue","listing":["1.flac","2.flac"]}"#
+        );
+        let commands: Vec<(String, Vec<String>)> = server
+            .commands()
+            .iter()
+            .map(|line| parse_line(line))
+            .collect();
+        let position = |command: &str, argument: &str| {
+            commands
+                .iter()
+                .position(|(name, args)| {
+                    name == command
+                        && args.first().map(String::as_str) == Some(argument)
+                })
+                .unwrap()
+        };
+        assert!(commands.iter().any(|(name, _)| name == "clear"));
+        assert!(position("addid", "1.flac") < position("addid", "2.flac"));
+        assert!(position("addid", "2.flac") < position("play", "0"));
+    }
+
+    #[test]
+    fn test_seekthrough_crosses_songs_and_checks_the_queue() {
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                "status" => "state: play\nsong: 0\nsongid: 1\nelapsed: 50\n",
+                "playlistinfo" => concat!(
+                    "file: a.flac\nTime: 100\nPos: 0\nId: 1\n",
+                    "file: b.flac\nTime: 100\nPos: 1\nId: 2\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+
+        client.seekthrough("+80").unwrap();
+        client.seekthrough("-80").unwrap();
+        let commands = server.commands();
+        assert!(commands.contains(&r#"seekid "2" "30""#.to_string()));
+        assert!(commands.contains(&r#"seekid "2" "70""#.to_string()));
+
+        // the current song is past the end of a queue changed meanwhile
+        let error = |queue: &'static str| {
+            let server = FakeServer::start(move |line| {
+                let body = match line {
+                    "status" => "state: play\nsong: 3\nsongid: 4\n",
+                    "playlistinfo" => queue,
+                    _ => "",
+                };
+
+                Ok(body.to_string())
+            });
+
+            server.client().seekthrough("-10").unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            error("file: a.flac\nTime: 100\nPos: 0\nId: 1\n"),
+            "no current song"
+        );
+        assert_eq!(
+            error("file: a.flac\nPos: 0\nId: 1\n"),
+            "unable to seek through a.flac: unknown length"
+        );
+    }
+
+    #[test]
+    fn test_random_replace_can_be_undone() {
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                "listallinfo" => "file: new.flac\n",
+                "playlistinfo" => "file: old.flac\nPos: 0\nId: 1\n",
+                _ if line.starts_with("addid") => "Id: 2\n",
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let dir = tempdir::TempDir::new("journal").unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let mut client = server.client();
+        client.set_journal(Some(path));
+
+        client.random_songs(1, None, true, false).unwrap();
+        client.undo(1).unwrap();
+
+        let commands = server.commands();
+        let restore =
+            commands.iter().rposition(|line| line == "clear").unwrap();
+        assert!(commands.iter().take(restore).any(|line| line == "clear"));
+        assert_eq!(commands[restore + 1], r#"add "old.flac""#);
+    }
+
+    #[test]
+    fn test_random_songs_needs_songs() {
+        let server = FakeServer::start(|line| match line {
+            "listallinfo" => Ok("file: a.flac\nfile: b.flac\n".to_string()),
+            _ if line.starts_with("addid") => Ok("Id: 1\n".to_string()),
+            _ => Ok(String::new()),
+        });
+        let mut client = server.client();
+
+        let picked = client.random_songs(5, None, false, false).unwrap();
+        assert!(picked.unwrap().starts_with(r#"{"picked":"2 songs""#));
+
+        let error = client
+            .random_songs(5, Some("(genre == \"None\")"), false, false)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "no songs to pick from");
+    }
 }
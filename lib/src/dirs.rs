@@ -0,0 +1,41 @@
+//! Locations for files kept between runs, following the XDG base directory
+//! specification with `$HOME` based fallbacks.
+use std::env;
+use std::path::PathBuf;
+
+const APP: &str = "mp-cli";
+
+fn base(variable: &str, fallback: &str) -> PathBuf {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback))
+        })
+        .unwrap_or_else(env::temp_dir)
+        .join(APP)
+}
+
+/// `$XDG_CONFIG_HOME/mp-cli`
+#[must_use]
+pub fn config_dir() -> PathBuf {
+    base("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_CACHE_HOME/mp-cli`
+#[must_use]
+pub fn cache_dir() -> PathBuf {
+    base("XDG_CACHE_HOME", ".cache")
+}
+
+/// `$XDG_STATE_HOME/mp-cli`
+#[must_use]
+pub fn state_dir() -> PathBuf {
+    base("XDG_STATE_HOME", ".local/state")
+}
+
+/// `$XDG_DATA_HOME/mp-cli`
+#[must_use]
+pub fn data_dir() -> PathBuf {
+    base("XDG_DATA_HOME", ".local/share")
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// A subsystem reported as changed by MPD's `idle` command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all(serialize = "snake_case"))]
+pub enum Event {
+    Database,
+    Update,
+    StoredPlaylist,
+    Playlist,
+    Player,
+    Mixer,
+    Output,
+    Options,
+    Sticker,
+    Message,
+    Other,
+}
+
+impl From<mpd::Subsystem> for Event {
+    fn from(subsystem: mpd::Subsystem) -> Self {
+        match subsystem {
+            mpd::Subsystem::Database => Event::Database,
+            mpd::Subsystem::Update => Event::Update,
+            mpd::Subsystem::Playlist => Event::StoredPlaylist,
+            mpd::Subsystem::Queue => Event::Playlist,
+            mpd::Subsystem::Player => Event::Player,
+            mpd::Subsystem::Mixer => Event::Mixer,
+            mpd::Subsystem::Output => Event::Output,
+            mpd::Subsystem::Options => Event::Options,
+            mpd::Subsystem::Sticker => Event::Sticker,
+            mpd::Subsystem::Message => Event::Message,
+            _ => Event::Other,
+        }
+    }
+}
+
+impl Event {
+    pub(crate) fn subsystem(self) -> Option<mpd::Subsystem> {
+        match self {
+            Event::Database => Some(mpd::Subsystem::Database),
+            Event::Update => Some(mpd::Subsystem::Update),
+            Event::StoredPlaylist => Some(mpd::Subsystem::Playlist),
+            Event::Playlist => Some(mpd::Subsystem::Queue),
+            Event::Player => Some(mpd::Subsystem::Player),
+            Event::Mixer => Some(mpd::Subsystem::Mixer),
+            Event::Output => Some(mpd::Subsystem::Output),
+            Event::Options => Some(mpd::Subsystem::Options),
+            Event::Sticker => Some(mpd::Subsystem::Sticker),
+            Event::Message => Some(mpd::Subsystem::Message),
+            Event::Other => None,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let event = match self {
+            Event::Database => "database",
+            Event::Update => "update",
+            Event::StoredPlaylist => "stored_playlist",
+            Event::Playlist => "playlist",
+            Event::Player => "player",
+            Event::Mixer => "mixer",
+            Event::Output => "output",
+            Event::Options => "options",
+            Event::Sticker => "sticker",
+            Event::Message => "message",
+            Event::Other => "other",
+        };
+        write!(f, "{event}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_round_trip() {
+        let events = [
+            Event::Database,
+            Event::StoredPlaylist,
+            Event::Playlist,
+            Event::Player,
+            Event::Mixer,
+        ];
+
+        for event in events {
+            let subsystem = event.subsystem().unwrap();
+            assert_eq!(Event::from(subsystem), event);
+        }
+
+        assert!(Event::Other.subsystem().is_none());
+    }
+
+    #[test]
+    fn test_event_display() {
+        assert_eq!(Event::Playlist.to_string(), "playlist");
+        assert_eq!(Event::StoredPlaylist.to_string(), "stored_playlist");
+    }
+}
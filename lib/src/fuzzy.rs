@@ -0,0 +1,293 @@
+//! Client-side search over the whole library that forgives typos, accents
+//! and case (`Björk`, `bjork` and `bjrok` all find Björk), or matches a
+//! regular expression.
+use eyre::eyre;
+use regex::{Regex, RegexBuilder};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// The tags a search looks at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Artist,
+    Album,
+    Title,
+    /// Artist, album, title and file name
+    Any,
+}
+
+/// Where the hits of a search go besides being listed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Enqueue {
+    /// At the end of the queue
+    Add,
+    /// After the current song
+    Insert,
+}
+
+pub(crate) enum Matcher {
+    Fuzzy(Vec<String>),
+    Regex(Regex),
+}
+
+impl Matcher {
+    pub(crate) fn new(query: &str, regex: bool) -> eyre::Result<Self> {
+        if regex {
+            let regex = RegexBuilder::new(query)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| eyre!("invalid regex: {e}"))?;
+
+            return Ok(Matcher::Regex(regex));
+        }
+
+        let words = words(query);
+        if words.is_empty() {
+            return Err(eyre!("empty search query"));
+        }
+
+        Ok(Matcher::Fuzzy(words))
+    }
+
+    /// How well `texts` match, `None` when they don't. Every query word has
+    /// to match one of the words of `texts`; fewer words score higher.
+    fn score(&self, texts: &[&str]) -> Option<u32> {
+        match self {
+            Matcher::Regex(regex) => texts
+                .iter()
+                .any(|text| {
+                    regex.is_match(text) || regex.is_match(&normalize(text))
+                })
+                .then_some(0),
+            Matcher::Fuzzy(query) => {
+                let candidates: Vec<String> =
+                    texts.iter().flat_map(|text| words(text)).collect();
+                let total = query.iter().try_fold(0, |total, word| {
+                    let best = candidates
+                        .iter()
+                        .filter_map(|candidate| word_score(word, candidate))
+                        .max()?;
+                    Some(total + best)
+                })?;
+                let extra = u32::try_from(candidates.len()).unwrap_or(u32::MAX);
+
+                Some((total * 10).saturating_sub(extra))
+            }
+        }
+    }
+}
+
+/// Lowercase and without diacritics
+fn normalize(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn words(text: &str) -> Vec<String> {
+    normalize(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Exact, prefix and substring matches first, then matches within a few
+/// typos of the word or of its beginning
+fn word_score(query: &str, word: &str) -> Option<u32> {
+    if word == query {
+        return Some(100);
+    }
+    if word.starts_with(query) {
+        return Some(80);
+    }
+    if word.contains(query) {
+        return Some(60);
+    }
+
+    let length = query.chars().count();
+    let allowed = match length {
+        0..=3 => return None,
+        4..=6 => 1,
+        _ => 2,
+    };
+    let prefix: String = word.chars().take(length).collect();
+    let distance =
+        edit_distance(query, word).min(edit_distance(query, &prefix));
+
+    (distance <= allowed).then(|| 50 - 10 * distance)
+}
+
+/// Insertions, deletions, substitutions and swaps of adjacent characters
+/// needed to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> u32 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = u32::try_from(i).unwrap_or(u32::MAX);
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = u32::try_from(j).unwrap_or(u32::MAX);
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = u32::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+fn tag<'a>(song: &'a mpd::Song, name: &str) -> Option<&'a str> {
+    song.tags
+        .iter()
+        .find(|(tag, _)| tag == name)
+        .map(|(_, value)| value.as_str())
+}
+
+fn texts(song: &mpd::Song, field: Field) -> Vec<&str> {
+    let artist = [song.artist.as_deref(), tag(song, "AlbumArtist")];
+    let album = [tag(song, "Album")];
+    let title = [song.title.as_deref().or(song.name.as_deref())];
+
+    let texts: Vec<Option<&str>> = match field {
+        Field::Artist => artist.to_vec(),
+        Field::Album => album.to_vec(),
+        Field::Title => title.to_vec(),
+        Field::Any => {
+            let file = song.file.rsplit('/').next();
+            [&artist[..], &album, &title, &[file]].concat()
+        }
+    };
+
+    texts.into_iter().flatten().collect()
+}
+
+/// The matching songs, best first and otherwise in library order
+pub(crate) fn rank(
+    songs: Vec<mpd::Song>,
+    field: Field,
+    matcher: &Matcher,
+) -> Vec<mpd::Song> {
+    let mut scored: Vec<(u32, mpd::Song)> = songs
+        .into_iter()
+        .filter_map(|song| {
+            let score = matcher.score(&texts(&song, field))?;
+            Some((score, song))
+        })
+        .collect();
+    scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    scored.into_iter().map(|(_, song)| song).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeServer;
+
+    fn song(file: &str, artist: &str, title: &str) -> mpd::Song {
+        mpd::Song {
+            file: file.to_string(),
+            artist: Some(artist.to_string()),
+            title: Some(title.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn files(songs: &[mpd::Song]) -> Vec<&str> {
+        songs.iter().map(|song| song.file.as_str()).collect()
+    }
+
+    fn library() -> Vec<mpd::Song> {
+        vec![
+            song("a.flac", "Sigur Rós", "Svefn-g-englar"),
+            song("b.flac", "Björk", "Hyperballad"),
+            song("c.flac", "Björk Guðmundsdóttir Trio", "Ruby Baby"),
+            song("d.flac", "Boards of Canada", "Roygbiv"),
+        ]
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("Sigur Rós"), "sigur ros");
+        assert_eq!(normalize("BJÖRK"), "bjork");
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("bjork", "bjork"), 0);
+        assert_eq!(edit_distance("bjrok", "bjork"), 1);
+        assert_eq!(edit_distance("sigr", "sigur"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_fuzzy_rank() {
+        let rank = |query: &str, field: Field| {
+            let matcher = Matcher::new(query, false).unwrap();
+            files(&rank(library(), field, &matcher))
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(rank("bjork", Field::Artist), ["b.flac", "c.flac"]);
+        assert_eq!(rank("Bjrok", Field::Artist), ["b.flac", "c.flac"]);
+        assert_eq!(rank("sigur ros", Field::Any), ["a.flac"]);
+        assert_eq!(rank("hyperbalad", Field::Title), ["b.flac"]);
+        assert!(rank("bjork", Field::Title).is_empty());
+    }
+
+    #[test]
+    fn test_regex() {
+        let matcher = Matcher::new("^bj.rk$", true).unwrap();
+        assert_eq!(
+            files(&rank(library(), Field::Artist, &matcher)),
+            ["b.flac"]
+        );
+
+        assert!(Matcher::new("(", true).is_err());
+        assert!(Matcher::new(" - ", false).is_err());
+    }
+
+    #[test]
+    fn test_fuzzy_inserts_top_hits_after_current_song() {
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                "listallinfo" => concat!(
+                    "directory: Björk\n",
+                    "file: Björk/Hyperballad.flac\nArtist: Björk\n",
+                    "file: Björk/Joga.flac\nArtist: Björk\n",
+                    "file: Other/x.flac\nArtist: Other\n",
+                ),
+                "status" => "state: play\nsong: 3\nsongid: 7\n",
+                _ if line.starts_with("addid") => "Id: 9\n",
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+
+        let listing = client
+            .fuzzy("bjork", Field::Artist, false, 1, Some(Enqueue::Insert))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(listing, r#"{"listing":["Björk/Hyperballad.flac"]}"#);
+        assert!(server
+            .commands()
+            .contains(&r#"addid "Björk/Hyperballad.flac" "4""#.to_string()));
+    }
+}
@@ -0,0 +1,398 @@
+//! The queue as it was before each command that changes it for good, kept
+//! as JSON lines in `$XDG_STATE_HOME/mp-cli` so that `undo` can bring it
+//! back.
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::{dirs, raw};
+
+/// Snapshots kept, older ones are dropped
+const MAX_SNAPSHOTS: usize = 50;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Queued {
+    pub(crate) file: String,
+    #[serde(default)]
+    pub(crate) priority: u8,
+}
+
+/// The queue and where playback is in it
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct QueueState {
+    pub(crate) songs: Vec<Queued>,
+    /// Position of the current song, if any
+    pub(crate) position: Option<u32>,
+    /// Seconds into the current song
+    pub(crate) elapsed: f64,
+    /// `play`, `pause` or `stop`
+    pub(crate) state: String,
+}
+
+impl QueueState {
+    /// Commands replacing the queue with these songs
+    pub(crate) fn queue_lines(&self) -> Vec<String> {
+        let mut lines = vec!["clear".to_string()];
+
+        for song in &self.songs {
+            lines.push(format!("add {}", raw::quote(&song.file)));
+        }
+        for (position, song) in self.songs.iter().enumerate() {
+            if song.priority > 0 {
+                lines.push(format!("prio {} {position}", song.priority));
+            }
+        }
+
+        lines
+    }
+
+    /// Leaves out the songs whose file `keep` rejects, playback moving on
+    /// to the next song kept when the current one goes
+    pub(crate) fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        let current = self.position.map(|position| position as usize);
+        let mut position = None;
+        let mut songs = Vec::new();
+
+        for (index, song) in
+            std::mem::take(&mut self.songs).into_iter().enumerate()
+        {
+            if !keep(&song.file) {
+                continue;
+            }
+            if position.is_none() && current.is_some_and(|c| index >= c) {
+                if current != Some(index) {
+                    self.elapsed = 0.0;
+                }
+                position = u32::try_from(songs.len()).ok();
+            }
+            songs.push(song);
+        }
+
+        self.songs = songs;
+        self.position = position;
+    }
+
+    /// Commands bringing a server whose queue is `current` in line with
+    /// this one: the queue when the files differ, then the state, current
+    /// song and, when more than `drift` seconds apart, the elapsed time
+    pub(crate) fn mirror_lines(
+        &self,
+        current: &QueueState,
+        drift: f64,
+    ) -> Vec<String> {
+        let same_files = self
+            .songs
+            .iter()
+            .map(|song| &song.file)
+            .eq(current.songs.iter().map(|song| &song.file));
+        let (mut lines, position, state, elapsed) = if same_files {
+            (
+                vec![],
+                current.position,
+                current.state.as_str(),
+                current.elapsed,
+            )
+        } else {
+            (self.queue_lines(), None, "stop", 0.0)
+        };
+
+        match (self.state.as_str(), self.position) {
+            ("play" | "pause", Some(wanted)) => {
+                let seek = position != Some(wanted)
+                    || state == "stop"
+                    || (elapsed - self.elapsed).abs() > drift;
+                if seek {
+                    lines.push(format!("seek {wanted} {}", self.elapsed));
+                }
+                if self.state == "pause" && (seek || state != "pause") {
+                    lines.push("pause 1".to_string());
+                } else if self.state == "play" && !seek && state == "pause" {
+                    lines.push("pause 0".to_string());
+                }
+            }
+            _ if state != "stop" => lines.push("stop".to_string()),
+            _ => (),
+        }
+
+        lines
+    }
+
+    /// Commands going back to the current song and position, to be sent
+    /// after `queue_lines`
+    pub(crate) fn playback_lines(&self) -> Vec<String> {
+        let Some(position) = self.position else {
+            return vec![];
+        };
+        let seek = format!("seek {position} {}", self.elapsed);
+
+        match self.state.as_str() {
+            "play" => vec![seek],
+            "pause" => vec![seek, "pause 1".to_string()],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Snapshot {
+    /// Unix timestamp of when the snapshot was taken
+    pub(crate) taken_at: i64,
+    /// The command about to change the queue
+    pub(crate) command: String,
+    #[serde(flatten)]
+    pub(crate) queue: QueueState,
+}
+
+/// Where the queue of the server at `address` is journaled
+pub(crate) fn path(address: &str) -> PathBuf {
+    let name = address.replace([':', '/'], "_");
+
+    dirs::state_dir().join(format!("journal-{name}.jsonl"))
+}
+
+/// The snapshots, oldest first
+pub(crate) fn load(path: &Path) -> eyre::Result<Vec<Snapshot>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Appends a snapshot, dropping the oldest beyond `MAX_SNAPSHOTS`
+pub(crate) fn append(path: &Path, snapshot: &Snapshot) -> eyre::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut snapshots = load(path)?;
+    if snapshots.len() >= MAX_SNAPSHOTS {
+        snapshots.drain(..=snapshots.len() - MAX_SNAPSHOTS);
+        let lines: Vec<String> = snapshots
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<_, _>>()?;
+        fs::write(path, lines.join("\n") + "\n")?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(snapshot)?)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    /// What `undo` takes to restore the snapshot, 1 being the latest
+    pub number: usize,
+    pub taken_at: i64,
+    pub command: String,
+    pub songs: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summaries(pub Vec<Summary>);
+
+impl From<Vec<Snapshot>> for Summaries {
+    fn from(snapshots: Vec<Snapshot>) -> Self {
+        Summaries(
+            snapshots
+                .into_iter()
+                .rev()
+                .enumerate()
+                .map(|(index, snapshot)| Summary {
+                    number: index + 1,
+                    taken_at: snapshot.taken_at,
+                    command: snapshot.command,
+                    songs: snapshot.queue.songs.len(),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for Summaries {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = self
+            .0
+            .iter()
+            .map(|summary| {
+                let taken_at = Local
+                    .timestamp_opt(summary.taken_at, 0)
+                    .single()
+                    .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default();
+                format!(
+                    "{} {taken_at} {} ({} songs)",
+                    summary.number, summary.command, summary.songs
+                )
+            })
+            .collect();
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeServer;
+
+    fn snapshot(command: &str) -> Snapshot {
+        Snapshot {
+            taken_at: 0,
+            command: command.to_string(),
+            queue: QueueState {
+                songs: vec![],
+                position: None,
+                elapsed: 0.0,
+                state: "stop".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_append_drops_old_snapshots() {
+        let dir = tempdir::TempDir::new("journal").unwrap();
+        let path = dir.path().join("journal.jsonl");
+
+        for index in 0..=MAX_SNAPSHOTS {
+            append(&path, &snapshot(&index.to_string())).unwrap();
+        }
+
+        let snapshots = load(&path).unwrap();
+        assert_eq!(snapshots.len(), MAX_SNAPSHOTS);
+        assert_eq!(snapshots[0].command, "1");
+        assert_eq!(
+            Summaries::from(snapshots).0[0].command,
+            MAX_SNAPSHOTS.to_string()
+        );
+    }
+
+    #[test]
+    fn test_retain_moves_playback_to_the_next_song_kept() {
+        let queued = |file: &str| Queued {
+            file: file.to_string(),
+            priority: 0,
+        };
+        let mut queue = QueueState {
+            songs: vec![queued("a"), queued("b"), queued("c")],
+            position: Some(1),
+            elapsed: 30.0,
+            state: "play".to_string(),
+        };
+
+        queue.retain(|file| file != "a");
+        assert_eq!((queue.position, queue.elapsed), (Some(0), 30.0));
+
+        queue.retain(|file| file != "b");
+        assert_eq!(queue.songs, [queued("c")]);
+        assert_eq!((queue.position, queue.elapsed), (Some(0), 0.0));
+
+        queue.retain(|_| false);
+        assert_eq!(queue.position, None);
+    }
+
+    #[test]
+    fn test_mirror_lines() {
+        let queue =
+            |files: &[&str], position, elapsed, state: &str| QueueState {
+                songs: files
+                    .iter()
+                    .map(|file| Queued {
+                        file: (*file).to_string(),
+                        priority: 0,
+                    })
+                    .collect(),
+                position,
+                elapsed,
+                state: state.to_string(),
+            };
+        let source = queue(&["a", "b"], Some(1), 30.0, "play");
+
+        let in_line = queue(&["a", "b"], Some(1), 31.5, "play");
+        assert!(source.mirror_lines(&in_line, 2.0).is_empty());
+        assert_eq!(source.mirror_lines(&in_line, 1.0), ["seek 1 30"]);
+
+        let paused = queue(&["a", "b"], Some(1), 30.0, "pause");
+        assert_eq!(source.mirror_lines(&paused, 2.0), ["pause 0"]);
+
+        let other = queue(&["a"], Some(0), 30.0, "play");
+        assert_eq!(
+            source.mirror_lines(&other, 2.0),
+            ["clear", r#"add "a""#, r#"add "b""#, "seek 1 30"]
+        );
+
+        let stopped = queue(&["a", "b"], None, 0.0, "stop");
+        assert_eq!(stopped.mirror_lines(&in_line, 2.0), ["stop"]);
+    }
+
+    #[test]
+    fn test_undo_restores_the_queue_before_clear() {
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                "status" => concat!(
+                    "state: pause\nsong: 1\nsongid: 2\nplaylistlength: 2\n",
+                    "elapsed: 12.500\n",
+                ),
+                "playlistinfo" => concat!(
+                    "file: a.flac\nPos: 0\nId: 1\n",
+                    "file: b.flac\nPos: 1\nId: 2\nPrio: 5\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let dir = tempdir::TempDir::new("journal").unwrap();
+        let path = dir.path().join("journal.jsonl");
+        let mut client = server.client();
+        client.set_journal(Some(path.clone()));
+
+        client.clear().unwrap();
+        let snapshots = load(&path).unwrap();
+        assert_eq!(snapshots[0].command, "clear");
+        assert_eq!(snapshots[0].queue.songs[1].priority, 5);
+        assert_eq!(snapshots[0].queue.position, Some(1));
+
+        client.undo(1).unwrap();
+        let commands = server.commands();
+        let restore =
+            commands.iter().rposition(|line| line == "clear").unwrap();
+        assert_eq!(
+            commands[restore..restore + 6],
+            [
+                "clear",
+                r#"add "a.flac""#,
+                r#"add "b.flac""#,
+                "prio 5 1",
+                "seek 1 12.5",
+                "pause 1",
+            ]
+        );
+        assert_eq!(load(&path).unwrap()[1].command, "undo");
+        assert!(client.undo(3).is_err());
+    }
+
+    #[test]
+    fn test_unwritable_journal_does_not_block_clear() {
+        let server = FakeServer::start(|_| Ok(String::new()));
+        let dir = tempdir::TempDir::new("journal").unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let mut client = server.client();
+        client.set_journal(Some(file.join("journal.jsonl")));
+
+        client.clear().unwrap();
+
+        assert!(server.commands().contains(&"clear".to_string()));
+    }
+}
@@ -9,16 +9,42 @@ extern crate serde_json;
 
 use serde::Serialize;
 
+mod cache;
 mod client;
+pub mod dirs;
+mod event;
+mod fuzzy;
+mod journal;
+mod lyrics;
+mod playlist_file;
+mod queue;
+pub mod random;
 mod range;
+mod raw;
 mod se;
+mod session;
 mod song;
 mod stats;
 mod status;
+mod stream;
 mod time;
+mod volume;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
 
 pub use client::Client;
-pub use status::State;
+pub use event::Event;
+pub use fuzzy::{Enqueue, Field};
+pub use lyrics::{Line, Lyrics};
+pub use mpd::Song;
+pub use playlist_file::ExportFormat;
+pub use queue::{DedupeBy, Keep};
+pub use session::{Handoff, Session};
+pub use song::{track_order, FinderOptions, Listing, Picked};
+pub use status::{State, Status};
+pub use time::Time;
+pub use volume::{ramp, Volume};
 
 pub enum OutputFormat {
     Text,
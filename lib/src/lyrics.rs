@@ -0,0 +1,232 @@
+//! Lyrics of a song, plain or synced (LRC), from a file next to it, its
+//! tags or a directory of lyrics files.
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::time::Time;
+
+/// Tags lyrics are embedded in, in order of preference
+pub(crate) const TAGS: [&str; 3] = ["LYRICS", "UNSYNCEDLYRICS", "SYNCEDLYRICS"];
+
+/// Extensions of lyrics files, synced first
+const EXTENSIONS: [&str; 2] = ["lrc", "txt"];
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Line {
+    /// When the line is sung, `None` for plain lyrics
+    pub time: Option<Time>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lyrics {
+    /// The song's file
+    pub file: String,
+    /// The lyrics file, or tag, the lyrics were found in
+    pub source: String,
+    pub synced: bool,
+    pub lines: Vec<Line>,
+}
+
+impl Lyrics {
+    /// Reads LRC (`[mm:ss.xx]` timestamped lines, honouring `[offset:]`)
+    /// or, without any timestamp, plain text
+    pub(crate) fn parse(file: &str, source: &str, text: &str) -> Self {
+        let mut offset = 0;
+        let mut synced = Vec::new();
+
+        for line in text.lines() {
+            let mut rest = line.trim();
+            let mut times = Vec::new();
+
+            while let Some((tag, after)) = rest
+                .strip_prefix('[')
+                .and_then(|tagged| tagged.split_once(']'))
+            {
+                if let Some(value) = tag.strip_prefix("offset:") {
+                    offset = value.trim().parse().unwrap_or(0);
+                } else if let Some(time) = tag
+                    .contains(':')
+                    .then_some(tag)
+                    .and_then(|tag| tag.parse::<Time>().ok())
+                {
+                    times.push(time);
+                }
+                rest = after;
+            }
+
+            for time in times {
+                synced.push(Line {
+                    time: Some(time),
+                    text: rest.trim().to_string(),
+                });
+            }
+        }
+
+        if synced.is_empty() {
+            return Lyrics {
+                file: file.to_string(),
+                source: source.to_string(),
+                synced: false,
+                lines: text
+                    .trim()
+                    .lines()
+                    .map(|line| Line {
+                        time: None,
+                        text: line.trim_end().to_string(),
+                    })
+                    .collect(),
+            };
+        }
+
+        // a positive offset shows the lines earlier
+        for line in &mut synced {
+            line.time = line
+                .time
+                .map(|time| time - Time::from_millis(offset))
+                .map(|time| time.max(Time::ZERO));
+        }
+        synced.sort_by_key(|line| line.time);
+
+        Lyrics {
+            file: file.to_string(),
+            source: source.to_string(),
+            synced: true,
+            lines: synced,
+        }
+    }
+
+    /// The line being sung `elapsed` into the song, if the lyrics are
+    /// synced and it has started
+    #[must_use]
+    pub fn line_at(&self, elapsed: Time) -> Option<&Line> {
+        self.lines
+            .iter()
+            .take_while(|line| line.time.is_some_and(|time| time <= elapsed))
+            .last()
+    }
+
+    /// When the line after the one sung at `elapsed` starts
+    #[must_use]
+    pub fn next_time(&self, elapsed: Time) -> Option<Time> {
+        self.lines
+            .iter()
+            .filter_map(|line| line.time)
+            .find(|time| *time > elapsed)
+    }
+}
+
+impl fmt::Display for Lyrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<&str> =
+            self.lines.iter().map(|line| line.text.as_str()).collect();
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Lyrics files next to `file` in the music directory
+pub(crate) fn beside(music_dir: &Path, file: &str) -> Vec<PathBuf> {
+    let song = music_dir.join(file);
+
+    EXTENSIONS
+        .iter()
+        .map(|extension| song.with_extension(extension))
+        .collect()
+}
+
+/// Lyrics files named `Artist - Title` in `directory`
+pub(crate) fn in_directory(
+    directory: &Path,
+    artist: &str,
+    title: &str,
+) -> Vec<PathBuf> {
+    let name = format!("{artist} - {title}").replace('/', "_");
+
+    EXTENSIONS
+        .iter()
+        .map(|extension| directory.join(format!("{name}.{extension}")))
+        .collect()
+}
+
+/// The lyrics in each of `paths` that exists
+pub(crate) fn read(file: &str, paths: &[PathBuf]) -> Vec<Lyrics> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(path).ok()?;
+            Some(Lyrics::parse(file, &path.display().to_string(), &text))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lrc() {
+        let lyrics = Lyrics::parse(
+            "song.flac",
+            "song.lrc",
+            "[ar:Someone]\n[offset:500]\n[00:12.00]Second\n\
+             [00:02.00][00:20.50]First and last\n",
+        );
+
+        assert!(lyrics.synced);
+        assert_eq!(
+            lyrics
+                .lines
+                .iter()
+                .map(|line| line.text.as_str())
+                .collect::<Vec<_>>(),
+            ["First and last", "Second", "First and last"]
+        );
+        assert_eq!(lyrics.lines[0].time, Some(Time::from_millis(1500)));
+
+        let at = |millis| {
+            lyrics
+                .line_at(Time::from_millis(millis))
+                .map(|line| line.text.as_str())
+        };
+        assert_eq!(at(1000), None);
+        assert_eq!(at(11_600), Some("Second"));
+        assert_eq!(
+            lyrics.next_time(Time::from_millis(11_600)),
+            Some(Time::from_millis(20_000))
+        );
+    }
+
+    #[test]
+    fn test_parse_plain_text() {
+        let lyrics = Lyrics::parse("song.flac", "LYRICS", "\nOne\nTwo [x]\n");
+
+        assert!(!lyrics.synced);
+        assert_eq!(lyrics.to_string(), "One\nTwo [x]");
+        assert_eq!(lyrics.line_at(Time::from_secs(5)), None);
+    }
+
+    #[test]
+    fn test_lyrics_files() {
+        assert_eq!(
+            beside(Path::new("/music"), "Artist/Album/01 Song.flac"),
+            [
+                PathBuf::from("/music/Artist/Album/01 Song.lrc"),
+                PathBuf::from("/music/Artist/Album/01 Song.txt"),
+            ]
+        );
+        assert_eq!(
+            in_directory(Path::new("/lyrics"), "AC/DC", "Song")[0],
+            PathBuf::from("/lyrics/AC_DC - Song.lrc")
+        );
+    }
+}
@@ -0,0 +1,602 @@
+//! Playlist files on disk (m3u, pls, xspf and cue sheets): reading the
+//! entries they list, and writing stored playlists or the queue out for
+//! other players.
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::Path;
+
+use eyre::{eyre, WrapErr};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    M3u,
+    Pls,
+    Xspf,
+    /// Left to MPD's cue plugin, which splits the image into tracks
+    Cue,
+}
+
+/// The formats playlists can be exported to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// One path per line, without any extended tag
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+    Json,
+}
+
+/// A song listed in a playlist file, with whatever the file tells about it
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub(crate) struct Entry {
+    /// A path or URL
+    pub(crate) file: String,
+    pub(crate) artist: Option<String>,
+    pub(crate) title: Option<String>,
+    pub(crate) album: Option<String>,
+    /// In whole seconds
+    pub(crate) duration: Option<u64>,
+}
+
+impl Entry {
+    /// `Artist - Title` as in m3u and pls files
+    fn display_title(&self) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{artist} - {title}"),
+            (None, Some(title)) => title.clone(),
+            _ => self.file.clone(),
+        }
+    }
+
+    fn set_display_title(&mut self, display: &str) {
+        match display.split_once(" - ") {
+            Some((artist, title)) => {
+                self.artist = Some(artist.trim().to_string());
+                self.title = Some(title.trim().to_string());
+            }
+            None if !display.trim().is_empty() => {
+                self.title = Some(display.trim().to_string());
+            }
+            None => {}
+        }
+    }
+}
+
+impl From<&mpd::Song> for Entry {
+    fn from(song: &mpd::Song) -> Self {
+        let tag = |name: &str| {
+            song.tags
+                .iter()
+                .find(|(tag, _)| tag == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        Entry {
+            file: song.file.clone(),
+            artist: song.artist.clone().or_else(|| tag("Artist")),
+            title: song.title.clone().or_else(|| song.name.clone()),
+            album: tag("Album"),
+            duration: song.duration.map(|duration| duration.as_secs()),
+        }
+    }
+}
+
+impl Format {
+    pub(crate) fn of(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "m3u" | "m3u8" => Some(Format::M3u),
+            "pls" => Some(Format::Pls),
+            "xspf" => Some(Format::Xspf),
+            "cue" => Some(Format::Cue),
+            _ => None,
+        }
+    }
+
+    /// The songs listed in `contents`, in order
+    pub(crate) fn entries(self, contents: &str) -> Vec<Entry> {
+        let contents = contents.trim_start_matches('\u{feff}');
+
+        match self {
+            Format::M3u => m3u_entries(contents),
+            Format::Pls => pls_entries(contents),
+            Format::Xspf => xspf_entries(contents),
+            Format::Cue => vec![],
+        }
+    }
+}
+
+fn m3u_entries(contents: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut info: Option<Entry> = None;
+
+    for line in contents.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            let (duration, display) =
+                extinf.split_once(',').unwrap_or((extinf, ""));
+            let mut entry = Entry {
+                duration: duration.trim().parse().ok(),
+                ..Default::default()
+            };
+            entry.set_display_title(display);
+            info = Some(entry);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let mut entry = info.take().unwrap_or_default();
+            entry.file = line.to_string();
+            entries.push(entry);
+        }
+    }
+
+    entries
+}
+
+fn pls_entries(contents: &str) -> Vec<Entry> {
+    let mut entries: BTreeMap<u32, Entry> = BTreeMap::new();
+
+    for (key, value) in contents
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_lowercase(), value.trim()))
+    {
+        let split = key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len());
+        let (name, index) = key.split_at(split);
+        let Ok(index) = index.parse::<u32>() else {
+            continue;
+        };
+        let entry = entries.entry(index).or_default();
+
+        match name {
+            "file" => entry.file = value.to_string(),
+            "title" => entry.set_display_title(value),
+            "length" => entry.duration = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    entries
+        .into_values()
+        .filter(|entry| !entry.file.is_empty())
+        .collect()
+}
+
+/// The text of the first `<name>` element in `xml`
+fn element(xml: &str, name: &str) -> Option<String> {
+    let (_, rest) = xml.split_once(&format!("<{name}>"))?;
+    let (text, _) = rest.split_once(&format!("</{name}>"))?;
+
+    Some(unescape(text.trim()))
+}
+
+fn xspf_entries(contents: &str) -> Vec<Entry> {
+    contents
+        .split("<track>")
+        .skip(1)
+        .filter_map(|track| {
+            let track = track.split("</track>").next().unwrap_or(track);
+            let location = element(track, "location")?;
+
+            Some(Entry {
+                // relative locations are URI references too
+                file: if is_url(&location) {
+                    location
+                } else {
+                    percent_decode(&location)
+                },
+                artist: element(track, "creator"),
+                title: element(track, "title"),
+                album: element(track, "album"),
+                duration: element(track, "duration")
+                    .and_then(|millis| millis.parse::<u64>().ok())
+                    .map(|millis| millis / 1000),
+            })
+        })
+        .collect()
+}
+
+/// Reads the entries of a playlist file to import, json files being a list
+/// of entries as written by `write`
+pub(crate) fn read(path: &Path) -> eyre::Result<Vec<Entry>> {
+    let contents = fs::read_to_string(path)
+        .wrap_err(format!("unable to read {}", path.display()))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+
+    if is_json {
+        return serde_json::from_str(&contents)
+            .wrap_err(format!("invalid playlist: {}", path.display()));
+    }
+
+    match Format::of(path) {
+        Some(Format::Cue) | None => Err(eyre!(
+            "unsupported playlist file: {} (expected m3u, m3u8, pls, xspf or json)",
+            path.display()
+        )),
+        Some(format) => Ok(format.entries(&contents)),
+    }
+}
+
+/// A playlist file listing `entries`
+pub(crate) fn write(
+    format: ExportFormat,
+    entries: &[Entry],
+) -> eyre::Result<String> {
+    let mut output = String::new();
+
+    match format {
+        ExportFormat::M3u => {
+            for entry in entries {
+                writeln!(output, "{}", entry.file)?;
+            }
+        }
+        ExportFormat::M3u8 => {
+            writeln!(output, "#EXTM3U")?;
+            for entry in entries {
+                let duration = entry
+                    .duration
+                    .map_or("-1".to_string(), |duration| duration.to_string());
+                writeln!(
+                    output,
+                    "#EXTINF:{duration},{}",
+                    entry.display_title()
+                )?;
+                writeln!(output, "{}", entry.file)?;
+            }
+        }
+        ExportFormat::Pls => {
+            writeln!(output, "[playlist]")?;
+            for (index, entry) in entries.iter().enumerate() {
+                let number = index + 1;
+                let length = entry
+                    .duration
+                    .map_or("-1".to_string(), |duration| duration.to_string());
+                writeln!(output, "File{number}={}", entry.file)?;
+                writeln!(output, "Title{number}={}", entry.display_title())?;
+                writeln!(output, "Length{number}={length}")?;
+            }
+            writeln!(output, "NumberOfEntries={}", entries.len())?;
+            writeln!(output, "Version=2")?;
+        }
+        ExportFormat::Xspf => {
+            writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+            writeln!(
+                output,
+                r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">"#
+            )?;
+            writeln!(output, "  <trackList>")?;
+            for entry in entries {
+                let location = if is_url(&entry.file) {
+                    entry.file.clone()
+                } else {
+                    percent_encode(&entry.file)
+                };
+
+                writeln!(output, "    <track>")?;
+                writeln!(
+                    output,
+                    "      <location>{}</location>",
+                    escape(&location)
+                )?;
+                for (name, value) in [
+                    ("creator", &entry.artist),
+                    ("title", &entry.title),
+                    ("album", &entry.album),
+                ] {
+                    if let Some(value) = value {
+                        writeln!(
+                            output,
+                            "      <{name}>{}</{name}>",
+                            escape(value)
+                        )?;
+                    }
+                }
+                if let Some(duration) = entry.duration {
+                    writeln!(
+                        output,
+                        "      <duration>{}</duration>",
+                        duration * 1000
+                    )?;
+                }
+                writeln!(output, "    </track>")?;
+            }
+            writeln!(output, "  </trackList>")?;
+            writeln!(output, "</playlist>")?;
+        }
+        ExportFormat::Json => {
+            output = serde_json::to_string_pretty(entries)?;
+            output.push('\n');
+        }
+    }
+
+    Ok(output)
+}
+
+/// What `import` did with the entries of a playlist file
+#[derive(Debug, Serialize)]
+pub(crate) struct Imported {
+    pub(crate) playlist: String,
+    pub(crate) added: usize,
+    /// Entries not found in the library, as listed in the file
+    pub(crate) unresolved: Vec<String>,
+}
+
+impl fmt::Display for Imported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "playlist={}\nadded={}\nunresolved={}",
+            self.playlist,
+            self.added,
+            self.unresolved.len()
+        )?;
+        for entry in &self.unresolved {
+            write!(f, "\n{entry}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `entry` is a URL (eg. `http://…` or `file:///…`) rather than a
+/// path
+pub(crate) fn is_url(entry: &str) -> bool {
+    entry.split_once("://").is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    })
+}
+
+/// The local path of a `file://` URL, percent-decoded
+pub(crate) fn file_url_path(url: &str) -> Option<String> {
+    url.strip_prefix("file://").map(percent_decode)
+}
+
+fn percent_decode(encoded: &str) -> String {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        if let Some(decoded) = escaped {
+            bytes.push(decoded);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Escapes everything but unreserved characters and `/`
+fn percent_encode(path: &str) -> String {
+    path.bytes().fold(String::new(), |mut encoded, byte| {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+        encoded
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeServer;
+    use std::fs;
+
+    #[test]
+    fn test_format_of() {
+        assert_eq!(Format::of(Path::new("set.M3U8")), Some(Format::M3u));
+        assert_eq!(Format::of(Path::new("a/b.xspf")), Some(Format::Xspf));
+        assert_eq!(Format::of(Path::new("song.mp3")), None);
+    }
+
+    fn files(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.file.as_str()).collect()
+    }
+
+    #[test]
+    fn test_entries() {
+        let m3u =
+            "\u{feff}#EXTM3U\n#EXTINF:123,A - B\n../a.mp3\n\nhttp://radio/x\n";
+        let entries = Format::M3u.entries(m3u);
+        assert_eq!(files(&entries), ["../a.mp3", "http://radio/x"]);
+        assert_eq!(entries[0].artist.as_deref(), Some("A"));
+        assert_eq!(entries[0].duration, Some(123));
+        assert_eq!(entries[1].title, None);
+
+        let pls = "[playlist]\nFile1=a.flac\nTitle1=A\nfile2 = b.ogg\nNumberOfEntries=2\n";
+        assert_eq!(files(&Format::Pls.entries(pls)), ["a.flac", "b.ogg"]);
+
+        let xspf = concat!(
+            "<playlist><trackList>",
+            "<track><location>file:///music/R%26B/a.mp3</location></track>",
+            "<track><title>x</title><location>b%20&amp;c.mp3</location></track>",
+            "</trackList></playlist>",
+        );
+        assert_eq!(
+            files(&Format::Xspf.entries(xspf)),
+            ["file:///music/R%26B/a.mp3", "b &c.mp3"]
+        );
+    }
+
+    #[test]
+    fn test_written_files_read_back() {
+        let entries = vec![
+            Entry {
+                file: "R&B/01 Song.flac".to_string(),
+                artist: Some("Artist".to_string()),
+                title: Some("Title".to_string()),
+                album: Some("Album".to_string()),
+                duration: Some(215),
+            },
+            Entry {
+                file: "http://radio/stream".to_string(),
+                ..Default::default()
+            },
+        ];
+
+        for (format, extended) in [
+            (Format::M3u, ExportFormat::M3u8),
+            (Format::Pls, ExportFormat::Pls),
+            (Format::Xspf, ExportFormat::Xspf),
+        ] {
+            let written = write(extended, &entries).unwrap();
+            let read = format.entries(&written);
+
+            assert_eq!(files(&read), files(&entries), "{written}");
+            assert_eq!(read[0].artist.as_deref(), Some("Artist"));
+            assert_eq!(read[0].duration, Some(215));
+        }
+
+        let plain = write(ExportFormat::M3u, &entries).unwrap();
+        assert_eq!(plain, "R&B/01 Song.flac\nhttp://radio/stream\n");
+        assert_eq!(files(&Format::M3u.entries(&plain)), files(&entries));
+
+        let json = write(ExportFormat::Json, &entries).unwrap();
+        let read: Vec<Entry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, entries);
+    }
+
+    #[test]
+    fn test_urls() {
+        assert!(is_url("http://example.com/stream"));
+        assert!(is_url("file:///tmp/a.mp3"));
+        assert!(!is_url("music/a.mp3"));
+        assert!(!is_url("weird ://name.mp3"));
+        assert_eq!(
+            file_url_path("file:///tmp/My%20Set/a%2.mp3").unwrap(),
+            "/tmp/My Set/a%2.mp3"
+        );
+        assert_eq!(file_url_path("http://x"), None);
+    }
+
+    #[test]
+    fn test_add_expands_playlist_files_and_urls() {
+        let music = tempdir::TempDir::new("music").unwrap();
+        fs::create_dir_all(music.path().join("sets")).unwrap();
+        fs::write(
+            music.path().join("sets/friday.m3u"),
+            "#EXTM3U\n../a/one.mp3\nhttp://radio/stream\n",
+        )
+        .unwrap();
+        let music_dir = music.path().display().to_string();
+        let server = FakeServer::start(move |line| match line {
+            "config" => Ok(format!("music_directory: {music_dir}\n")),
+            _ if line.starts_with("addid") => Ok("Id: 1\n".to_string()),
+            _ => Ok(String::new()),
+        });
+        let mut client = server.client();
+
+        client.add_local("sets").unwrap();
+        client.add("https://radio/other").unwrap();
+        assert!(client.add("file:///tmp/set/a.mp3").is_err());
+
+        let elsewhere = tempdir::TempDir::new("sets").unwrap();
+        let outside = elsewhere.path().join("a.mp3");
+        fs::write(&outside, "").unwrap();
+        let error = client.add_local(outside.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("outside the music directory"));
+
+        let added: Vec<String> = server
+            .commands()
+            .into_iter()
+            .filter(|line| line.starts_with("addid"))
+            .collect();
+        assert_eq!(
+            added,
+            [
+                r#"addid "a/one.mp3""#,
+                r#"addid "http://radio/stream""#,
+                r#"addid "https://radio/other""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_resolves_paths_and_tags() {
+        let dir = tempdir::TempDir::new("import").unwrap();
+        let file = dir.path().join("friday.m3u8");
+        fs::write(
+            &file,
+            concat!(
+                "#EXTM3U\n",
+                "/home/dj/Music/Artist/Album/01.flac\n",
+                "#EXTINF:200,Artist - Remote\n",
+                "C:/Users/dj/Remote.mp3\n",
+                "#EXTINF:200,Nobody - Nothing\n",
+                "missing.mp3\n",
+                "http://radio/stream\n",
+            ),
+        )
+        .unwrap();
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                r#"find "file" "Artist/Album/01.flac""# => {
+                    "file: Artist/Album/01.flac\n"
+                }
+                r#"find "artist" "Artist" "title" "Remote""# => concat!(
+                    "file: live/Remote.flac\nTime: 320\n",
+                    "file: Artist/Album/02.flac\nTime: 201\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+
+        let imported = client
+            .import(file.to_str().unwrap(), Some("Friday"))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            imported,
+            r#"{"playlist":"Friday","added":3,"unresolved":["missing.mp3"]}"#
+        );
+        let added: Vec<String> = server
+            .commands()
+            .into_iter()
+            .filter(|line| line.starts_with("playlistadd"))
+            .collect();
+        assert_eq!(
+            added,
+            [
+                r#"playlistadd "Friday" "Artist/Album/01.flac""#,
+                r#"playlistadd "Friday" "Artist/Album/02.flac""#,
+                r#"playlistadd "Friday" "http://radio/stream""#,
+            ]
+        );
+    }
+}
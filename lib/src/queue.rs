@@ -0,0 +1,207 @@
+//! Tidying the queue: finding repeated songs and the moves that sort it.
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::song::{natural_cmp, tag};
+
+/// What makes two queued songs the same
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DedupeBy {
+    File,
+    /// Artist and title, ignoring case
+    ArtistTitle,
+    /// `MUSICBRAINZ_TRACKID`, songs without one are never repeats
+    MusicbrainzTrackid,
+}
+
+/// Which of the repeated songs stays in the queue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Keep {
+    First,
+    Last,
+}
+
+fn key(song: &mpd::Song, by: DedupeBy) -> Option<String> {
+    match by {
+        DedupeBy::File => Some(song.file.clone()),
+        DedupeBy::ArtistTitle => {
+            let artist = tag(song, "artist").unwrap_or_default();
+            let title = tag(song, "title")?;
+            Some(format!("{artist}\n{title}").to_lowercase())
+        }
+        DedupeBy::MusicbrainzTrackid => {
+            tag(song, "MUSICBRAINZ_TRACKID").map(String::from)
+        }
+    }
+}
+
+/// Positions of the songs repeating an earlier (or, keeping the last, a
+/// later) one, highest first so that they can be deleted in turn
+pub(crate) fn duplicates(
+    songs: &[mpd::Song],
+    by: DedupeBy,
+    keep: Keep,
+) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    let mut positions: Vec<usize> = (0..songs.len()).collect();
+    if keep == Keep::Last {
+        positions.reverse();
+    }
+
+    let mut repeated: Vec<usize> = positions
+        .into_iter()
+        .filter(|&position| {
+            key(&songs[position], by).is_some_and(|key| !seen.insert(key))
+        })
+        .collect();
+    repeated.sort_unstable_by(|a, b| b.cmp(a));
+
+    repeated
+}
+
+/// The value a song is sorted by for `name`, preferring the sort tags
+/// (eg. `ArtistSort` over `Artist`)
+fn sort_value<'a>(song: &'a mpd::Song, name: &str) -> &'a str {
+    tag(song, &format!("{name}sort"))
+        .or_else(|| tag(song, name))
+        .unwrap_or_default()
+}
+
+/// The queue positions in the order given by the tags, ties keeping their
+/// order
+pub(crate) fn order(
+    songs: &[mpd::Song],
+    tags: &[String],
+    reverse: bool,
+) -> Vec<usize> {
+    let mut positions: Vec<usize> = (0..songs.len()).collect();
+
+    positions.sort_by(|&a, &b| {
+        let ordering = tags.iter().fold(Ordering::Equal, |ordering, name| {
+            ordering.then_with(|| {
+                natural_cmp(
+                    sort_value(&songs[a], name),
+                    sort_value(&songs[b], name),
+                )
+            })
+        });
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+
+    positions
+}
+
+/// `(from, to)` moves that put the songs at `order` in that order
+pub(crate) fn moves(order: &[usize]) -> Vec<(usize, usize)> {
+    let mut current: Vec<usize> = (0..order.len()).collect();
+    let mut moves = Vec::new();
+
+    for (to, wanted) in order.iter().enumerate() {
+        let Some(from) = current.iter().position(|song| song == wanted) else {
+            continue;
+        };
+        if from != to {
+            let song = current.remove(from);
+            current.insert(to, song);
+            moves.push((from, to));
+        }
+    }
+
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{parse_line, FakeServer};
+
+    fn song(file: &str, tags: &[(&str, &str)]) -> mpd::Song {
+        mpd::Song {
+            file: file.to_string(),
+            tags: tags
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let songs = vec![
+            song("a.flac", &[("MUSICBRAINZ_TRACKID", "1")]),
+            song("b.flac", &[]),
+            song("a.flac", &[]),
+            song("c.flac", &[("MUSICBRAINZ_TRACKID", "1")]),
+            song("a.flac", &[]),
+        ];
+
+        assert_eq!(duplicates(&songs, DedupeBy::File, Keep::First), [4, 2]);
+        assert_eq!(duplicates(&songs, DedupeBy::File, Keep::Last), [2, 0]);
+        assert_eq!(
+            duplicates(&songs, DedupeBy::MusicbrainzTrackid, Keep::First),
+            [3]
+        );
+    }
+
+    #[test]
+    fn test_order_prefers_sort_tags_and_numbers() {
+        let songs = vec![
+            song("1", &[("Artist", "The Who"), ("Track", "10")]),
+            song("2", &[("Artist", "Beatles"), ("Track", "2")]),
+            song("3", &[("Artist", "The Beatles"), ("ArtistSort", "Beatles")]),
+            song("4", &[("Artist", "Beatles"), ("Track", "1")]),
+        ];
+        let tags = ["artist".to_string(), "track".to_string()];
+
+        assert_eq!(order(&songs, &tags, false), [2, 3, 1, 0]);
+        assert_eq!(order(&songs, &tags, true), [0, 1, 3, 2]);
+    }
+
+    #[test]
+    fn test_moves_reach_the_order() {
+        let order = [2, 0, 3, 1];
+        let mut queue = vec![0, 1, 2, 3];
+
+        for (from, to) in moves(&order) {
+            let song = queue.remove(from);
+            queue.insert(to, song);
+        }
+
+        assert_eq!(queue, order);
+        assert!(moves(&[0, 1, 2]).is_empty());
+    }
+
+    #[test]
+    fn test_sort_queue_moves_songs() {
+        let server = FakeServer::start(|line| {
+            let body = match line {
+                "playlistinfo" => concat!(
+                    "file: b.flac\nTitle: B\nPos: 0\nId: 1\n",
+                    "file: a.flac\nTitle: A\nPos: 1\nId: 2\n",
+                ),
+                _ => "",
+            };
+
+            Ok(body.to_string())
+        });
+        let mut client = server.client();
+
+        client.sort_queue(&["title".to_string()], false).unwrap();
+
+        let commands = server.commands();
+        let moves: Vec<_> = commands
+            .iter()
+            .map(|line| parse_line(line))
+            .filter(|(command, _)| command == "move")
+            .collect();
+        assert_eq!(
+            moves,
+            [("move".to_string(), vec!["1".to_string(), "0".to_string()])]
+        );
+    }
+}
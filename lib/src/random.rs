@@ -0,0 +1,49 @@
+//! Randomness good enough for picking music, from the randomly keyed hasher
+//! of the standard library rather than a dependency.
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+fn next() -> u64 {
+    // every new `RandomState` is keyed differently
+    RandomState::new().hash_one(())
+}
+
+/// A number below `bound`, 0 when `bound` is 0
+#[must_use]
+pub fn below(bound: usize) -> usize {
+    if bound == 0 {
+        return 0;
+    }
+
+    usize::try_from(next() % bound as u64).unwrap_or(0)
+}
+
+/// Puts `items` in a random order
+pub fn shuffle<T>(items: &mut [T]) {
+    for index in (1..items.len()).rev() {
+        items.swap(index, below(index + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_below() {
+        assert_eq!(below(0), 0);
+        assert_eq!(below(1), 0);
+        assert!((0..100).all(|_| below(3) < 3));
+        assert!((0..100).any(|_| below(2) == 1));
+    }
+
+    #[test]
+    fn test_shuffle_keeps_items() {
+        let mut items: Vec<u32> = (0..50).collect();
+
+        shuffle(&mut items);
+        assert_ne!(items, (0..50).collect::<Vec<_>>());
+        items.sort_unstable();
+        assert_eq!(items, (0..50).collect::<Vec<_>>());
+    }
+}
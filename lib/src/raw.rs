@@ -0,0 +1,131 @@
+//! A bare protocol connection for the few commands `mpd::Client` has no
+//! method for (eg. `single oneshot`).
+use std::io::{BufRead, BufReader, Write};
+
+use eyre::eyre;
+
+use crate::stream::Stream;
+
+/// Quotes a command argument, escaping quotes and backslashes
+pub(crate) fn quote(argument: &str) -> String {
+    format!(
+        "\"{}\"",
+        argument.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+pub(crate) struct Connection {
+    reader: BufReader<Stream>,
+    writer: Stream,
+}
+
+impl Connection {
+    pub(crate) fn open(address: &str) -> eyre::Result<Self> {
+        let stream = Stream::connect(address)?;
+        let mut connection = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        let greeting = connection.line()?;
+        if !greeting.starts_with("OK MPD ") {
+            return Err(eyre!("unexpected greeting: {greeting}"));
+        }
+
+        Ok(connection)
+    }
+
+    /// Sends a single command line and returns the `key: value` pairs of the
+    /// response, or the server's message when it answers with `ACK`.
+    pub(crate) fn command(
+        &mut self,
+        line: &str,
+    ) -> eyre::Result<Vec<(String, String)>> {
+        writeln!(self.writer, "{line}")?;
+
+        self.response()
+    }
+
+    /// Sends command lines as one command list, which MPD runs as a whole
+    /// and stops at the first failing command
+    pub(crate) fn command_list(
+        &mut self,
+        lines: &[String],
+    ) -> eyre::Result<Vec<(String, String)>> {
+        writeln!(self.writer, "command_list_begin")?;
+        for line in lines {
+            writeln!(self.writer, "{line}")?;
+        }
+        writeln!(self.writer, "command_list_end")?;
+
+        self.response()
+    }
+
+    fn response(&mut self) -> eyre::Result<Vec<(String, String)>> {
+        let mut pairs = Vec::new();
+        loop {
+            let response = self.line()?;
+
+            if response == "OK" {
+                return Ok(pairs);
+            }
+
+            if let Some(error) = response.strip_prefix("ACK ") {
+                // ACK [error@command_listNum] {current_command} message_text
+                let message = error
+                    .split_once("} ")
+                    .map_or(error, |(_, message)| message);
+                return Err(eyre!("{message}"));
+            }
+
+            if let Some((key, value)) = response.split_once(": ") {
+                pairs.push((key.to_string(), value.to_string()));
+            } else if let Some((_, value)) = pairs.last_mut() {
+                // a value spanning several lines, eg. lyrics
+                value.push('\n');
+                value.push_str(&response);
+            }
+        }
+    }
+
+    fn line(&mut self) -> eyre::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(eyre!("connection closed by mpd server"));
+        }
+
+        Ok(line.trim_end_matches('\n').to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::FakeServer;
+
+    #[test]
+    fn test_command_pairs_and_errors() {
+        let server = FakeServer::start(|line| match line {
+            "getvol" => Ok("volume: 42\n".to_string()),
+            _ => Err("unknown command".to_string()),
+        });
+        let mut connection =
+            Connection::open(&format!("{}:{}", server.address, server.port))
+                .unwrap();
+
+        assert_eq!(
+            connection.command("getvol").unwrap(),
+            vec![("volume".to_string(), "42".to_string())]
+        );
+        assert_eq!(
+            connection.command("nope").unwrap_err().to_string(),
+            "unknown command"
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("Artist/Album"), r#""Artist/Album""#);
+        assert_eq!(quote(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+    }
+}
@@ -18,9 +18,11 @@ where
     seq.end()
 }
 
+/// A time as `HH:MM:SS`, without the fraction of a second
+#[allow(clippy::trivially_copy_pass_by_ref)]
 pub fn serialize_time<S>(time: &Time, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    serializer.serialize_str(&time.as_string)
+    serializer.serialize_str(&time.truncated().to_string())
 }
@@ -0,0 +1,48 @@
+//! The state of the player as a whole, saved by name to switch between
+//! contexts (eg. work and dinner).
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::journal::QueueState;
+
+/// Everything about the player that a `session restore` brings back
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Session {
+    #[serde(flatten)]
+    pub(crate) queue: QueueState,
+    /// Modes as MPD reports them (`0`, `1`, or `oneshot` for single and
+    /// consume)
+    pub(crate) random: String,
+    pub(crate) repeat: String,
+    pub(crate) single: String,
+    pub(crate) consume: String,
+    /// `None` when the server has no mixer
+    pub(crate) volume: Option<u8>,
+    /// Seconds
+    pub(crate) crossfade: u64,
+    /// Names of the enabled outputs
+    pub(crate) outputs: Vec<String>,
+}
+
+/// What a server took over from another
+#[derive(Debug, Serialize)]
+pub struct Handoff {
+    /// Address of the server now playing
+    pub to: String,
+    /// Songs in its queue
+    pub queued: usize,
+    /// Files left out for not being in its library
+    pub missing: Vec<String>,
+}
+
+impl fmt::Display for Handoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} songs queued", self.to, self.queued)?;
+        for file in &self.missing {
+            write!(f, "\nmissing: {file}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,12 +1,19 @@
-use std::{fmt, fs, path::Path};
-
-use serde::Serialize;
-
-use crate::{se::serialize_playlists, status::Status};
-
-const VALID_EXTENSIONS: &[&str] = &[
-    "mp3", "ogg", "flac", "wav", "aac", "m4a", "wma", "opus", "dffs", "dsf",
-    "ape", "tta",
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{playlist_file::Format, se::serialize_playlists, status::Status};
+
+/// Used when MPD's decoders can't be listed
+pub(crate) const VALID_EXTENSIONS: &[&str] = &[
+    "mp3", "ogg", "flac", "wav", "aac", "m4a", "wma", "opus", "dff", "dsf",
+    "ape", "tta", "wv", "aiff", "alac", "mpc", "mka", "mod",
 ];
 
 #[derive(Serialize)]
@@ -49,6 +56,70 @@ impl fmt::Display for Listing {
     }
 }
 
+/// Songs queued by a random pick, and what was picked
+#[derive(Serialize)]
+pub struct Picked {
+    pub picked: String,
+    pub listing: Vec<String>,
+}
+
+impl fmt::Display for Picked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "picked={}", self.picked)?;
+        for (index, file) in self.listing.iter().enumerate() {
+            writeln!(f, "{index}={file}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The value of a tag, including those `mpd::Song` keeps in fields
+pub(crate) fn tag<'a>(song: &'a mpd::Song, name: &str) -> Option<&'a str> {
+    let field = match name.to_lowercase().as_str() {
+        "file" => Some(song.file.as_str()),
+        "artist" => song.artist.as_deref(),
+        "title" => song.title.as_deref(),
+        "name" => song.name.as_deref(),
+        _ => None,
+    };
+
+    field.or_else(|| {
+        song.tags
+            .iter()
+            .find(|(tag, _)| tag.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    })
+}
+
+/// The leading number of a tag such as `Track: 3/12`, 0 without one
+fn number(song: &mpd::Song, name: &str) -> u32 {
+    tag(song, name)
+        .and_then(|value| {
+            let end = value
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(value.len());
+            value[..end].parse().ok()
+        })
+        .unwrap_or(0)
+}
+
+/// Orders the songs of an album by disc and track number, then by file
+#[must_use]
+pub fn track_order(a: &mpd::Song, b: &mpd::Song) -> Ordering {
+    (number(a, "Disc"), number(a, "Track"))
+        .cmp(&(number(b, "Disc"), number(b, "Track")))
+        .then_with(|| a.file.cmp(&b.file))
+}
+
+/// Orders the songs of an artist by release date and album, then as
+/// within an album
+pub(crate) fn discography_order(a: &mpd::Song, b: &mpd::Song) -> Ordering {
+    (tag(a, "Date"), tag(a, "Album"))
+        .cmp(&(tag(b, "Date"), tag(b, "Album")))
+        .then_with(|| track_order(a, b))
+}
+
 #[derive(Serialize)]
 pub struct TrackList {
     pub songs: Vec<Current>,
@@ -119,13 +190,134 @@ impl fmt::Display for Playlists {
     }
 }
 
+/// Songs from the `key: value` pairs of a listing such as `listallinfo`,
+/// read the way `mpd::Client` reads them; directories and playlists are
+/// skipped
+pub(crate) fn from_pairs(pairs: Vec<(String, String)>) -> Vec<mpd::Song> {
+    let mut songs = Vec::new();
+    let mut current: Option<mpd::Song> = None;
+
+    for (key, value) in pairs {
+        match key.as_str() {
+            "file" => {
+                songs.extend(current.take());
+                current = Some(mpd::Song {
+                    file: value,
+                    ..Default::default()
+                });
+            }
+            "directory" | "playlist" => songs.extend(current.take()),
+            _ => {
+                let Some(song) = current.as_mut() else {
+                    continue;
+                };
+                match key.as_str() {
+                    "Title" => song.title = Some(value),
+                    "Artist" => song.artist = Some(value),
+                    "Name" => song.name = Some(value),
+                    "Last-Modified" => song.last_mod = Some(value),
+                    "Time" => {
+                        song.duration =
+                            value.parse().ok().map(Duration::from_secs);
+                    }
+                    _ => song.tags.push((key, value)),
+                }
+            }
+        }
+    }
+    songs.extend(current);
+
+    songs
+}
+
 pub struct File {
     pub full_path: String,
-    pub relative_path: String,
+    /// The path within the music directory, `None` for files outside of it
+    pub relative_path: Option<String>,
+}
+
+impl File {
+    /// The format of a playlist file, `None` for songs
+    pub(crate) fn playlist(&self) -> Option<Format> {
+        Format::of(Path::new(&self.full_path))
+    }
+}
+
+/// Resolves `.` and `..` without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+
+    normalized
+}
+
+/// Compares names the way people number them: `2 - b` before `10 - a` and
+/// `1-02` before `1-10`, ignoring case
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut left, mut right) = (a, b);
+
+    while !left.is_empty() && !right.is_empty() {
+        let (chunk_a, rest_a) = split_chunk(left);
+        let (chunk_b, rest_b) = split_chunk(right);
+
+        let ordering = if is_number(chunk_a) && is_number(chunk_b) {
+            let (a, b) = (
+                chunk_a.trim_start_matches('0'),
+                chunk_b.trim_start_matches('0'),
+            );
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        } else {
+            chunk_a.to_lowercase().cmp(&chunk_b.to_lowercase())
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        (left, right) = (rest_a, rest_b);
+    }
+
+    left.len().cmp(&right.len()).then_with(|| a.cmp(b))
+}
+
+fn is_number(chunk: &str) -> bool {
+    chunk.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// The leading run of digits or of other characters, and the rest
+fn split_chunk(text: &str) -> (&str, &str) {
+    let digits = is_number(text);
+    let end = text
+        .find(|c: char| c.is_ascii_digit() != digits)
+        .unwrap_or(text.len());
+
+    text.split_at(end)
+}
+
+/// How `add_local` picks up files when walking directories
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FinderOptions {
+    /// Extensions of the files to queue, MPD's decoder suffixes when unset
+    pub extensions: Option<Vec<String>>,
+    /// Also queue hidden files and look into hidden directories
+    pub hidden: bool,
 }
 
 pub struct Finder {
     music_dir: String,
+    extensions: Vec<String>,
+    hidden: bool,
+    /// Directories already walked, so that symlinks can't loop
+    visited: HashSet<PathBuf>,
     pub found: Vec<File>,
 }
 
@@ -133,54 +325,153 @@ impl Finder {
     pub fn new(music_dir: String) -> Self {
         Finder {
             music_dir,
+            extensions: VALID_EXTENSIONS
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            hidden: false,
+            visited: HashSet::new(),
             found: Vec::<File>::new(),
         }
     }
 
-    fn is_music_file(path: &Path) -> bool {
+    /// Only files with these extensions (case-insensitive) are music files
+    #[must_use]
+    pub fn extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions
+            .into_iter()
+            .map(|extension| extension.to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Whether hidden files and directories are walked too
+    #[must_use]
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    fn is_music_file(&self, path: &Path) -> bool {
         if let Some(ext) = path.extension() {
             if let Some(ext_str) = ext.to_str() {
-                return VALID_EXTENSIONS.contains(&ext_str);
+                return self.extensions.contains(&ext_str.to_lowercase());
             }
         }
         false
     }
 
-    fn file_for(&self, path: &Path) -> Option<File> {
-        let full_path = path.to_str().unwrap_or("").to_string();
-        let mut relative_path = match full_path.strip_prefix(&self.music_dir) {
-            Some(remainder) => remainder.to_string(),
-            None => full_path.clone(),
-        };
+    fn is_hidden(path: &Path) -> bool {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'))
+    }
 
-        if relative_path.starts_with('/') {
-            relative_path.remove(0);
+    /// Where `path` is, whether or not it is a music file
+    pub fn locate(&self, path: &Path) -> File {
+        let path = normalize(path);
+        let relative_path = path
+            .strip_prefix(&self.music_dir)
+            .ok()
+            .and_then(Path::to_str)
+            .map(String::from);
+
+        File {
+            full_path: path.to_str().unwrap_or("").to_string(),
+            relative_path,
         }
+    }
 
-        if Self::is_music_file(full_path.as_ref()) {
-            Some(File {
-                full_path,
-                relative_path,
-            })
+    fn file_for(&self, path: &Path) -> Option<File> {
+        if self.is_music_file(path) || Format::of(path).is_some() {
+            Some(self.locate(path))
         } else {
             None
         }
     }
 
+    /// Puts the songs of each directory in disc and track order, as
+    /// tagged in `songs` (what MPD knows of them), when all of them have a
+    /// track number. Otherwise they stay in the natural order of their names.
+    pub fn order_by_tags(&mut self, songs: &[mpd::Song]) {
+        let numbers: HashMap<&str, (u32, u32)> = songs
+            .iter()
+            .filter(|song| number(song, "Track") > 0)
+            .map(|song| {
+                let numbers = (number(song, "Disc"), number(song, "Track"));
+                (song.file.as_str(), numbers)
+            })
+            .collect();
+        let key = |file: &File| {
+            file.relative_path
+                .as_deref()
+                .and_then(|path| numbers.get(path))
+                .copied()
+        };
+
+        let mut start = 0;
+        while start < self.found.len() {
+            let end = {
+                let parent = Path::new(&self.found[start].full_path).parent();
+                start
+                    + self.found[start..]
+                        .iter()
+                        .take_while(|file| {
+                            Path::new(&file.full_path).parent() == parent
+                        })
+                        .count()
+            };
+
+            let directory = &mut self.found[start..end];
+            if directory.iter().all(|file| key(file).is_some()) {
+                directory.sort_by_key(|file| key(file));
+            }
+            start = end;
+        }
+    }
+
+    /// Collects the music and playlist files at `file_or_dir`, in natural
+    /// order of their names, following symlinks. Playlists inside a
+    /// directory are only picked up when it has no music files of its own,
+    /// so that an album's cue sheet or m3u does not queue its songs twice.
     pub fn find(&mut self, file_or_dir: &Path) -> eyre::Result<()> {
         if file_or_dir.is_dir() {
-            for entry in fs::read_dir(file_or_dir)? {
-                let entry = entry?;
-                let path = entry.path();
+            if !self.visited.insert(fs::canonicalize(file_or_dir)?) {
+                return Ok(());
+            }
 
+            let mut paths = fs::read_dir(file_or_dir)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.retain(|path| self.hidden || !Self::is_hidden(path));
+            paths.sort_by(|a, b| {
+                natural_cmp(
+                    &a.file_name().unwrap_or_default().to_string_lossy(),
+                    &b.file_name().unwrap_or_default().to_string_lossy(),
+                )
+            });
+
+            let mut playlists = Vec::new();
+            let mut has_songs = false;
+
+            for path in paths {
                 if path.is_dir() {
                     self.find(&path)?;
-                } else if let Some(song) = self.file_for(&path) {
-                    self.found.push(song);
+                } else if let Some(file) = self.file_for(&path) {
+                    if file.playlist().is_some() {
+                        playlists.push(file);
+                    } else {
+                        has_songs = true;
+                        self.found.push(file);
+                    }
                 }
             }
-        } else if let Some(song) = self.file_for(file_or_dir) {
-            self.found.push(song);
+
+            if !has_songs {
+                self.found.extend(playlists);
+            }
+        } else if let Some(file) = self.file_for(file_or_dir) {
+            self.found.push(file);
         }
 
         Ok(())
@@ -209,8 +500,135 @@ mod tests {
         let valid_file = Path::new("test.mp3");
         let invalid_file = Path::new("test.txt");
 
-        assert!(Finder::is_music_file(valid_file));
-        assert!(!Finder::is_music_file(invalid_file));
+        let finder = Finder::new(String::new());
+
+        assert!(finder.is_music_file(valid_file));
+        assert!(finder.is_music_file(Path::new("test.DFF")));
+        assert!(!finder.is_music_file(invalid_file));
+
+        let finder = finder.extensions(vec!["WV".to_string()]);
+        assert!(finder.is_music_file(Path::new("test.wv")));
+        assert!(!finder.is_music_file(valid_file));
+    }
+
+    #[test]
+    fn test_songs_from_pairs() {
+        let pairs = [
+            ("directory", "Artist"),
+            ("Last-Modified", "2024-01-01T00:00:00Z"),
+            ("file", "Artist/01.flac"),
+            ("Artist", "Björk"),
+            ("Album", "Debut"),
+            ("Time", "215"),
+            ("playlist", "Artist/set.m3u"),
+            ("file", "Artist/02.flac"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+
+        let songs = from_pairs(pairs.to_vec());
+
+        assert_eq!(songs.len(), 2);
+        assert_eq!(songs[0].artist.as_deref(), Some("Björk"));
+        assert_eq!(songs[0].duration, Some(Duration::from_secs(215)));
+        assert_eq!(songs[0].tags, [("Album".to_string(), "Debut".to_string())]);
+        assert_eq!(songs[1].file, "Artist/02.flac");
+    }
+
+    #[test]
+    fn test_natural_order() {
+        let mut names = vec![
+            "10 - Ten.flac",
+            "2 - Two.flac",
+            "1-10 x.flac",
+            "1-02 x.flac",
+            "b.flac",
+            "A.flac",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+
+        assert_eq!(
+            names,
+            [
+                "1-02 x.flac",
+                "1-10 x.flac",
+                "2 - Two.flac",
+                "10 - Ten.flac",
+                "A.flac",
+                "b.flac"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_skips_hidden_and_symlink_loops() {
+        let temp_dir = TempDir::new("music").unwrap();
+        let album = temp_dir.path().join("album");
+        fs::create_dir(&album).unwrap();
+        for name in ["10.mp3", "2.mp3", "1.mp3", ".hidden.mp3"] {
+            StdFile::create(album.join(name)).unwrap();
+        }
+        std::os::unix::fs::symlink(temp_dir.path(), album.join("loop"))
+            .unwrap();
+
+        let mut finder = Finder::new(temp_dir.path().display().to_string());
+        finder.find(&album).unwrap();
+
+        let found: Vec<_> = finder
+            .found
+            .iter()
+            .filter_map(|file| file.relative_path.clone())
+            .collect();
+        assert_eq!(found, ["album/1.mp3", "album/2.mp3", "album/10.mp3"]);
+    }
+
+    #[test]
+    fn test_order_by_tags() {
+        let temp_dir = TempDir::new("music").unwrap();
+        for album in ["tagged", "untagged"] {
+            let album = temp_dir.path().join(album);
+            fs::create_dir(&album).unwrap();
+            for name in ["a.flac", "b.flac", "c.flac"] {
+                StdFile::create(album.join(name)).unwrap();
+            }
+        }
+        let song = |file: &str, tags: &[(&str, &str)]| mpd::Song {
+            file: file.to_string(),
+            tags: tags
+                .iter()
+                .map(|(tag, value)| ((*tag).to_string(), (*value).to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        let songs = [
+            song("tagged/a.flac", &[("Disc", "2"), ("Track", "1/2")]),
+            song("tagged/b.flac", &[("Disc", "1"), ("Track", "2")]),
+            song("tagged/c.flac", &[("Track", "1")]),
+            song("untagged/a.flac", &[("Track", "3")]),
+            song("untagged/b.flac", &[("Track", "1")]),
+        ];
+
+        let mut finder = Finder::new(temp_dir.path().display().to_string());
+        finder.find(temp_dir.path()).unwrap();
+        finder.order_by_tags(&songs);
+
+        let found: Vec<_> = finder
+            .found
+            .iter()
+            .filter_map(|file| file.relative_path.clone())
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "tagged/c.flac",
+                "tagged/b.flac",
+                "tagged/a.flac",
+                // c.flac has no track number, so the names decide
+                "untagged/a.flac",
+                "untagged/b.flac",
+                "untagged/c.flac",
+            ]
+        );
     }
 
     #[test]
@@ -242,6 +660,39 @@ mod tests {
         let file = finder.file_for(&file_path).unwrap();
 
         assert_eq!(file.full_path, file_path.to_str().unwrap());
-        assert_eq!(file.relative_path, music_file);
+        assert_eq!(file.relative_path.unwrap(), music_file);
+    }
+
+    #[test]
+    fn test_find_playlists_only_without_songs() {
+        let temp_dir = TempDir::new("music").unwrap();
+        create_temp_music_files(temp_dir.path());
+        StdFile::create(temp_dir.path().join("album.cue")).unwrap();
+        let sets = temp_dir.path().join("sets");
+        fs::create_dir(&sets).unwrap();
+        StdFile::create(sets.join("friday.m3u")).unwrap();
+
+        let mut finder = Finder::new(temp_dir.path().display().to_string());
+        finder.find(temp_dir.path()).unwrap();
+
+        let playlists: Vec<_> = finder
+            .found
+            .iter()
+            .filter_map(|file| file.relative_path.clone())
+            .filter(|path| !path.starts_with("song"))
+            .collect();
+        assert_eq!(playlists, ["sets/friday.m3u"]);
+    }
+
+    #[test]
+    fn test_locate_outside_music_dir() {
+        let finder = Finder::new("/music".to_string());
+
+        let inside = finder.locate(Path::new("/music/sets/../a/b.mp3"));
+        assert_eq!(inside.relative_path.unwrap(), "a/b.mp3");
+
+        let outside = finder.locate(Path::new("/musicians/b.mp3"));
+        assert_eq!(outside.full_path, "/musicians/b.mp3");
+        assert_eq!(outside.relative_path, None);
     }
 }
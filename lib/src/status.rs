@@ -42,10 +42,14 @@ pub struct Status {
     pub title: String,
     pub position: u32,
     pub queue_count: u32,
+    /// Serialized as `HH:MM:SS`, in whole seconds
     #[serde(serialize_with = "serialize_time")]
     pub elapsed: Time,
     #[serde(serialize_with = "serialize_time")]
     pub track_length: Time,
+    /// `elapsed` in seconds, with the precision MPD reports
+    pub elapsed_secs: f64,
+    pub track_length_secs: f64,
     pub repeat: OnOff,
     pub random: OnOff,
     pub single: OnOff,
@@ -65,8 +69,8 @@ impl fmt::Display for Status {
             self.title,
             self.position,
             self.queue_count,
-            self.elapsed,
-            self.track_length,
+            self.elapsed.truncated(),
+            self.track_length.truncated(),
             self.repeat,
             self.random,
             self.single,
@@ -81,9 +85,8 @@ mod tests {
     use crate::time::Time;
     use crate::OnOff;
 
-    #[test]
-    fn test_status_display_format() {
-        let status = Status {
+    fn status() -> Status {
+        Status {
             volume: "100".to_string(),
             state: State::Play,
             artist: "Phish".to_string(),
@@ -91,18 +94,33 @@ mod tests {
             title: "Chalk Dust Torture".to_string(),
             position: 3,
             queue_count: 10,
-            elapsed: Time::from(60),
+            elapsed: Time::from_millis(60_250),
             track_length: Time::from(300),
+            elapsed_secs: 60.25,
+            track_length_secs: 300.0,
             repeat: OnOff::Off,
             random: OnOff::On,
             single: OnOff::Off,
             consume: OnOff::Off,
             file_path: Some("path/to/file".to_string()),
-        };
+        }
+    }
 
-        let display_output = format!("{status}");
+    #[test]
+    fn test_status_display_format() {
+        let display_output = format!("{}", status());
         let expected_output = "volume=100\nstate=play\nartist=Phish\nalbum=A Picture Of Nectar\ntitle=Chalk Dust Torture\nposition=3\nqueue_count=10\nelapsed=00:01:00\ntrack_length=00:05:00\nrepeat=off\nrandom=on\nsingle=off\nconsume=off";
 
         assert_eq!(display_output, expected_output);
     }
+
+    #[test]
+    fn test_status_json_times() {
+        let json = serde_json::to_value(status()).unwrap();
+
+        assert_eq!(json["elapsed"], "00:01:00");
+        assert_eq!(json["track_length"], "00:05:00");
+        assert_eq!(json["elapsed_secs"], 60.25);
+        assert_eq!(json["track_length_secs"], 300.0);
+    }
 }
@@ -0,0 +1,109 @@
+//! The connection to MPD: TCP, or its local Unix socket when the address
+//! is a path. Only clients on the local socket may add files from outside
+//! the music directory.
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use eyre::WrapErr;
+
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Whether `address` (eg. `/run/mpd/socket`) names a Unix socket rather
+/// than a host and port
+pub(crate) fn is_socket(address: &str) -> bool {
+    address.starts_with('/')
+}
+
+impl Stream {
+    pub(crate) fn connect(address: &str) -> eyre::Result<Self> {
+        let stream = if is_socket(address) {
+            Self::unix(address)
+        } else {
+            TcpStream::connect(address).map(Stream::Tcp)
+        };
+
+        stream.wrap_err("Error connecting to mpd server".to_string())
+    }
+
+    #[cfg(unix)]
+    fn unix(path: &str) -> io::Result<Self> {
+        UnixStream::connect(path).map(Stream::Unix)
+    }
+
+    #[cfg(not(unix))]
+    fn unix(_path: &str) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix sockets are not supported on this platform",
+        ))
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    #[test]
+    fn test_connect_to_unix_socket() {
+        let dir = tempdir::TempDir::new("stream").unwrap();
+        let path = dir.path().join("socket");
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"OK MPD 0.23.5\n").unwrap();
+        });
+
+        let stream = Stream::connect(path.to_str().unwrap()).unwrap();
+        assert!(matches!(stream, Stream::Unix(_)));
+
+        let mut greeting = String::new();
+        BufReader::new(stream).read_line(&mut greeting).unwrap();
+        assert_eq!(greeting, "OK MPD 0.23.5\n");
+    }
+}
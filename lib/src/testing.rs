@@ -0,0 +1,236 @@
+//! A minimal in-process MPD server used to exercise `Client` in tests.
+//!
+//! Every command line received is recorded and handed to a handler which
+//! returns either the response body (without the trailing `OK`) or an error
+//! message that is sent back as an `ACK`.
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::{Client, OutputFormat};
+
+type Handler = dyn Fn(&str) -> Result<String, String> + Send + Sync;
+
+pub struct FakeServer {
+    pub address: String,
+    pub port: String,
+    commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeServer {
+    pub fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&str) -> Result<String, String> + Send + Sync + 'static,
+    {
+        let listener =
+            TcpListener::bind("127.0.0.1:0").expect("bind fake mpd server");
+        let port = listener.local_addr().unwrap().port().to_string();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = Arc::clone(&commands);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = Arc::clone(&handler);
+                let recorded = Arc::clone(&recorded);
+
+                thread::spawn(move || serve(stream, &*handler, &recorded));
+            }
+        });
+
+        Self {
+            address: "127.0.0.1".to_string(),
+            port,
+            commands,
+        }
+    }
+
+    /// A client for the server, without a library cache or undo journal
+    #[must_use]
+    pub fn client(&self) -> Client {
+        let mut client =
+            Client::new(&self.address, &self.port, Some(OutputFormat::Json))
+                .expect("connect to fake mpd server");
+        client.set_cache(None);
+        client.set_journal(None);
+
+        client
+    }
+
+    /// All command lines received so far, across every connection.
+    #[must_use]
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+fn serve(stream: TcpStream, handler: &Handler, recorded: &Mutex<Vec<String>>) {
+    let mut writer = stream.try_clone().expect("clone fake mpd stream");
+    let reader = BufReader::new(stream);
+    let mut batch: Option<(Vec<String>, bool)> = None;
+
+    if writer.write_all(b"OK MPD 0.23.5\n").is_err() {
+        return;
+    }
+
+    for line in reader.lines() {
+        let Ok(line) = line else { return };
+
+        if line == "command_list_begin" || line == "command_list_ok_begin" {
+            batch = Some((Vec::new(), line == "command_list_ok_begin"));
+            continue;
+        }
+
+        let response = if line == "command_list_end" {
+            let (lines, list_ok) = batch.take().unwrap_or_default();
+            respond_to_list(&lines, list_ok, handler, recorded)
+        } else if let Some((lines, _)) = batch.as_mut() {
+            lines.push(line);
+            continue;
+        } else {
+            respond(&line, handler, recorded)
+        };
+
+        if writer.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+fn respond(
+    line: &str,
+    handler: &Handler,
+    recorded: &Mutex<Vec<String>>,
+) -> String {
+    recorded.lock().unwrap().push(line.to_string());
+
+    match handle(line, handler) {
+        Ok(body) => format!("{body}OK\n"),
+        Err(message) => ack(0, line, &message),
+    }
+}
+
+fn respond_to_list(
+    lines: &[String],
+    list_ok: bool,
+    handler: &Handler,
+    recorded: &Mutex<Vec<String>>,
+) -> String {
+    let mut response = String::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        recorded.lock().unwrap().push(line.clone());
+
+        match handle(line, handler) {
+            Ok(body) => {
+                response.push_str(&body);
+                if list_ok {
+                    response.push_str("list_OK\n");
+                }
+            }
+            Err(message) => return ack(index, line, &message),
+        }
+    }
+
+    response.push_str("OK\n");
+    response
+}
+
+/// Answers the commands `Client` sends implicitly before deferring to the
+/// test's handler.
+fn handle(line: &str, handler: &Handler) -> Result<String, String> {
+    match line {
+        "replay_gain_status" => Ok("replay_gain_mode: off\n".to_string()),
+        // unless the handler knows better, `getvol` agrees with `status`
+        "getvol" => match handler(line) {
+            Ok(body) if body.is_empty() => Ok(handler("status")?
+                .lines()
+                .find(|line| line.starts_with("volume: "))
+                .map(|line| format!("{line}\n"))
+                .unwrap_or_default()),
+            response => response,
+        },
+        _ => handler(line),
+    }
+}
+
+fn ack(index: usize, line: &str, message: &str) -> String {
+    let command = line.split_whitespace().next().unwrap_or_default();
+
+    format!("ACK [50@{index}] {{{command}}} {message}\n")
+}
+
+/// Splits a command line into its command and unquoted arguments.
+#[must_use]
+pub fn parse_line(line: &str) -> (String, Vec<String>) {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut in_part = false;
+
+    for c in line.chars() {
+        match c {
+            _ if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            '\\' if quoted => escaped = true,
+            '"' => {
+                quoted = !quoted;
+                in_part = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_part {
+                    parts.push(std::mem::take(&mut current));
+                    in_part = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_part = true;
+            }
+        }
+    }
+
+    if in_part {
+        parts.push(current);
+    }
+
+    let command = if parts.is_empty() {
+        String::new()
+    } else {
+        parts.remove(0)
+    };
+
+    (command, parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let (command, args) = parse_line(r#"find "Artist" "Sigur \"R\" Rós""#);
+
+        assert_eq!(command, "find");
+        assert_eq!(args, vec!["Artist", "Sigur \"R\" Rós"]);
+    }
+
+    #[test]
+    fn test_fake_server_records_commands() {
+        let server = FakeServer::start(|line| match line {
+            "status" => Ok("volume: 40\nstate: play\n".to_string()),
+            "currentsong" => Ok(String::new()),
+            _ => Err("unknown command".to_string()),
+        });
+        let mut client = server.client();
+
+        let status = client.status().unwrap();
+
+        assert_eq!(status.volume, "40");
+        assert!(server.commands().contains(&"status".to_string()));
+    }
+}
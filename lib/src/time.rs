@@ -1,125 +1,256 @@
 use std::fmt;
-use std::ops::{Add, Sub};
+use std::ops::{Add, Neg, Sub};
+use std::str::FromStr;
 use std::time::Duration;
 
-use chrono::{NaiveTime, Timelike};
-use serde::Serialize;
+use eyre::eyre;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
 
-#[allow(clippy::struct_field_names)]
-#[derive(Clone, Debug, Serialize)]
+const MILLIS_PER_SECOND: i64 = 1000;
+const MILLIS_PER_MINUTE: i64 = 60 * MILLIS_PER_SECOND;
+const MILLIS_PER_HOUR: i64 = 60 * MILLIS_PER_MINUTE;
+
+/// A signed span of time with millisecond precision: a position within a
+/// song or an offset to seek by. There is no upper bound, so positions in
+/// audiobooks or mixes longer than a day are fine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Time {
-    pub as_string: String,
-    #[serde(skip_serializing)]
-    pub as_native: NaiveTime,
-    #[serde(skip_serializing)]
-    pub as_secs: i64,
+    millis: i64,
 }
 
 impl Time {
-    pub fn compute_offset(&self, other: &str) -> i64 {
-        let seconds = Self::from(other.to_string());
+    pub const ZERO: Time = Time { millis: 0 };
 
-        let seconds = if other.contains('-') {
-            self - &seconds
-        } else {
-            self + &seconds
-        };
+    #[must_use]
+    pub fn from_millis(millis: i64) -> Self {
+        Self { millis }
+    }
 
-        seconds.as_secs
+    #[must_use]
+    pub fn from_secs(secs: i64) -> Self {
+        Self::from_millis(secs.saturating_mul(MILLIS_PER_SECOND))
     }
 
-    fn add_or_subtract(
-        &self,
-        other: &Time,
-        operation: fn(chrono::Duration, chrono::Duration) -> chrono::Duration,
-    ) -> Time {
-        let time1 = self.as_secs;
-        let time2 = other.as_secs;
-
-        let duration1 = chrono::Duration::seconds(time1);
-        let duration2 = chrono::Duration::seconds(time2);
-        let result_duration = operation(duration1, duration2);
-
-        let result_time = NaiveTime::from_num_seconds_from_midnight_opt(
-            u32::try_from(result_duration.num_seconds())
-                .expect("Overflow error"),
-            0,
-        )
-        .expect("Invalid time");
+    /// Whole seconds, rounded towards zero
+    #[must_use]
+    pub fn as_secs(self) -> i64 {
+        self.millis / MILLIS_PER_SECOND
+    }
+
+    /// The time without its fraction of a second
+    #[must_use]
+    pub fn truncated(self) -> Self {
+        Self::from_secs(self.as_secs())
+    }
 
-        Time::from(result_time.format("%H:%M:%S").to_string())
+    #[must_use]
+    pub fn as_millis(self) -> i64 {
+        self.millis
+    }
+
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn as_secs_f64(self) -> f64 {
+        self.millis as f64 / 1000.0
+    }
+
+    #[must_use]
+    pub fn is_negative(self) -> bool {
+        self.millis < 0
+    }
+
+    /// The time as a `Duration`, negative times becoming zero
+    #[must_use]
+    pub fn to_duration(self) -> Duration {
+        Duration::from_millis(u64::try_from(self.millis).unwrap_or(0))
     }
 }
 
-impl Add for &Time {
+impl Add for Time {
     type Output = Time;
 
-    fn add(self, other: &Time) -> Time {
-        self.add_or_subtract(other, |t1, t2| t1 + t2)
+    fn add(self, other: Time) -> Time {
+        Time::from_millis(self.millis.saturating_add(other.millis))
     }
 }
 
-impl Sub for &Time {
+impl Sub for Time {
     type Output = Time;
 
-    fn sub(self, other: &Time) -> Time {
-        self.add_or_subtract(other, |t1, t2| t1 - t2)
+    fn sub(self, other: Time) -> Time {
+        Time::from_millis(self.millis.saturating_sub(other.millis))
+    }
+}
+
+impl Neg for Time {
+    type Output = Time;
+
+    fn neg(self) -> Time {
+        Time::from_millis(self.millis.saturating_neg())
     }
 }
 
 impl From<Duration> for Time {
     fn from(duration: Duration) -> Self {
-        Self::from(duration.as_secs())
+        Self::from_millis(
+            i64::try_from(duration.as_millis()).unwrap_or(i64::MAX),
+        )
     }
 }
 
 impl From<u64> for Time {
-    fn from(duration: u64) -> Self {
-        Time::from(format!(
-            "{:02}:{:02}:{:02}",
-            duration / 3600,
-            (duration % 3600) / 60,
-            duration % 60
-        ))
-    }
-}
-
-impl From<String> for Time {
-    fn from(input: String) -> Self {
-        let cleansed: String = input
-            .chars()
-            .filter(|c| c.is_numeric() || *c == ':')
-            .collect();
-
-        let parts: Vec<u8> = cleansed
-            .split(':')
-            .map(|n| n.parse::<u8>().unwrap_or(0))
-            .collect();
-
-        let as_string = match parts.len() {
-            1 => format!("00:00:{:02}", parts[0]),
-            2 => format!("00:{:02}:{:02}", parts[0], parts[1]),
-            3 => format!("{:02}:{:02}:{:02}", parts[0], parts[1], parts[2]),
-            _ => String::from("Invalid"),
+    fn from(seconds: u64) -> Self {
+        Self::from_secs(i64::try_from(seconds).unwrap_or(i64::MAX))
+    }
+}
+
+/// Milliseconds in `number` (eg. `30` or `30.25`) of a unit lasting `unit`
+/// milliseconds
+fn millis_of(number: &str, unit: i64) -> Option<i64> {
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+
+    if whole.is_empty() && fraction.is_empty()
+        || !digits(whole)
+        || !digits(fraction)
+    {
+        return None;
+    }
+
+    let whole = if whole.is_empty() {
+        0
+    } else {
+        whole.parse::<i64>().ok()?
+    };
+    let fraction = fraction
+        .chars()
+        .zip([100, 10, 1])
+        .map(|(digit, weight)| {
+            i64::from(digit.to_digit(10).unwrap_or(0)) * weight
+        })
+        .sum::<i64>();
+
+    whole
+        .checked_mul(unit)?
+        .checked_add(fraction * unit / MILLIS_PER_SECOND)
+}
+
+/// `[[H:]M:]S[.mmm]`, only the leading component may exceed 59
+fn parse_clock(clock: &str) -> Option<i64> {
+    let parts: Vec<&str> = clock.split(':').collect();
+    let units = match parts.len() {
+        2 => &[MILLIS_PER_MINUTE, MILLIS_PER_SECOND][..],
+        3 => &[MILLIS_PER_HOUR, MILLIS_PER_MINUTE, MILLIS_PER_SECOND][..],
+        _ => return None,
+    };
+
+    let mut total: i64 = 0;
+    for (index, (part, unit)) in parts.iter().zip(units).enumerate() {
+        let last = index == parts.len() - 1;
+        if !last && part.contains('.') {
+            return None;
+        }
+
+        let millis = millis_of(part, *unit)?;
+        if index > 0 && millis >= 60 * unit {
+            return None;
+        }
+        total = total.checked_add(millis)?;
+    }
+
+    Some(total)
+}
+
+/// A sequence of amounts with units such as `1h2m`, `90s` or `250ms`
+fn parse_units(input: &str) -> Option<i64> {
+    let mut total: i64 = 0;
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let (number, after) = rest.split_at(split);
+        let letters = after
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(letters);
+
+        let unit = match unit {
+            "h" => MILLIS_PER_HOUR,
+            "m" => MILLIS_PER_MINUTE,
+            "s" => MILLIS_PER_SECOND,
+            "ms" => 1,
+            _ => return None,
         };
+        total = total.checked_add(millis_of(number, unit)?)?;
+        rest = after;
+    }
+
+    Some(total)
+}
 
-        let as_native_time = NaiveTime::parse_from_str(&as_string, "%H:%M:%S")
-            .or_else(|_| NaiveTime::parse_from_str(&as_string, "%M:%S"))
-            .or_else(|_| NaiveTime::parse_from_str("00:01", "%M:%S"))
-            .unwrap();
-        let as_secs = i64::from(as_native_time.num_seconds_from_midnight());
+impl FromStr for Time {
+    type Err = eyre::Report;
+
+    /// Accepts seconds (`90`, `1.5`), clock times (`1:30`, `01:02:03`,
+    /// `1:30.250`) and units (`90s`, `1h2m`), optionally signed (`-0:30`)
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            eyre!(
+                "invalid time: {input} (expected eg. 1:30, 01:02:03, 90s or 1h2m)"
+            )
+        };
 
-        Time {
-            as_string,
-            as_native: as_native_time,
-            as_secs,
+        let trimmed = input.trim();
+        let (negative, unsigned) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+
+        let millis = if unsigned.contains(':') {
+            parse_clock(unsigned)
+        } else if unsigned.ends_with(|c: char| c.is_ascii_alphabetic()) {
+            parse_units(unsigned)
+        } else {
+            millis_of(unsigned, MILLIS_PER_SECOND)
         }
+        .ok_or_else(invalid)?;
+
+        Ok(Time::from_millis(if negative { -millis } else { millis }))
     }
 }
 
+/// `HH:MM:SS`, with milliseconds only when there are any
 impl fmt::Display for Time {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.as_string)
+        let sign = if self.is_negative() { "-" } else { "" };
+        let millis = self.millis.unsigned_abs();
+        let hours = millis / 3_600_000;
+        let minutes = millis / 60_000 % 60;
+        let seconds = millis / 1000 % 60;
+        let fraction = millis % 1000;
+
+        write!(f, "{sign}{hours:02}:{minutes:02}:{seconds:02}")?;
+        if fraction > 0 {
+            write!(f, ".{fraction:03}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Both the human readable form and the number of seconds
+impl Serialize for Time {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Time", 2)?;
+        state.serialize_field("as_string", &self.to_string())?;
+        state.serialize_field("as_secs", &self.as_secs_f64())?;
+        state.end()
     }
 }
 
@@ -131,15 +262,15 @@ impl From<Duration> for HumanReadableDuration {
     }
 }
 
-impl ToString for HumanReadableDuration {
-    fn to_string(&self) -> String {
+impl fmt::Display for HumanReadableDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let total_seconds = self.0.as_secs();
         let days = total_seconds / 86400;
         let hours = (total_seconds % 86400) / 3600;
         let minutes = (total_seconds % 3600) / 60;
         let seconds = total_seconds % 60;
 
-        format!("{days} days, {hours}:{minutes:02}:{seconds:02}")
+        write!(f, "{days} days, {hours}:{minutes:02}:{seconds:02}")
     }
 }
 
@@ -149,17 +280,15 @@ pub struct Track {
     pub total: Time,
 }
 
-impl From<Option<(Duration, Duration)>> for Track {
-    fn from(time: Option<(Duration, Duration)>) -> Self {
-        match time {
-            Some((elapsed, total)) => Track {
-                elapsed: Time::from(elapsed),
-                total: Time::from(total),
-            },
-            None => Track {
-                elapsed: Time::from(0),
-                total: Time::from(0),
-            },
+impl From<&mpd::Status> for Track {
+    /// Prefers the precise `elapsed` and `duration` fields over the whole
+    /// seconds of `time`
+    fn from(status: &mpd::Status) -> Self {
+        let (elapsed, total) = status.time.unwrap_or_default();
+
+        Track {
+            elapsed: Time::from(status.elapsed.unwrap_or(elapsed)),
+            total: Time::from(status.duration.unwrap_or(total)),
         }
     }
 }
@@ -169,38 +298,80 @@ mod tests {
     use super::*;
     use std::time::Duration;
 
+    fn parse(input: &str) -> i64 {
+        input.parse::<Time>().unwrap().as_millis()
+    }
+
     #[test]
-    fn compute_offset_positive() {
-        let time = Time::from("0:05".to_string());
+    fn test_parse_clock_times() {
+        assert_eq!(parse("1:30"), 90_000);
+        assert_eq!(parse("01:02:03"), 3_723_000);
+        assert_eq!(parse("90:00"), 5_400_000);
+        assert_eq!(parse("1:30.250"), 90_250);
+        assert_eq!(parse("30:00:00"), 108_000_000);
+    }
 
-        assert_eq!(time.compute_offset("0:15"), 20);
+    #[test]
+    fn test_parse_units_and_seconds() {
+        assert_eq!(parse("90s"), 90_000);
+        assert_eq!(parse("1h2m"), 3_720_000);
+        assert_eq!(parse("1m30.5s"), 90_500);
+        assert_eq!(parse("250ms"), 250);
+        assert_eq!(parse("90"), 90_000);
+        assert_eq!(parse("1.5"), 1_500);
     }
 
     #[test]
-    fn compute_offset_negative() {
-        let time = Time::from("1:00".to_string());
+    fn test_parse_signed_offsets() {
+        assert_eq!(parse("-0:30"), -30_000);
+        assert_eq!(parse("+10s"), 10_000);
+    }
 
-        assert_eq!(time.compute_offset("-0:30"), 30);
+    #[test]
+    fn test_parse_errors() {
+        for input in ["", "abc", "1:75", "1:2:3:4", "1.5:00", "10x", "1h2", "-"]
+        {
+            assert!(input.parse::<Time>().is_err(), "{input} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_does_not_overflow_a_day() {
+        let time = Time::from_secs(23 * 3600) + "2h".parse().unwrap();
+
+        assert_eq!(time.as_secs(), 25 * 3600);
+        assert_eq!((Time::from_secs(10) - Time::from_secs(30)).as_secs(), -20);
+        assert_eq!(Time::from_secs(-5).to_duration(), Duration::ZERO);
     }
 
     #[test]
     fn test_time_from_duration() {
-        let duration = Duration::from_secs(3661); // 1 hour, 1 minute, 1 second
-        let time = Time::from(duration);
-        assert_eq!(time.as_string, "01:01:01");
+        let time = Time::from(Duration::from_millis(3_661_500));
+        assert_eq!(time.to_string(), "01:01:01.500");
     }
 
     #[test]
     fn test_time_from_u64() {
         let duration: u64 = 3661; // 1 hour, 1 minute, 1 second
         let time = Time::from(duration);
-        assert_eq!(time.as_string, "01:01:01");
+        assert_eq!(time.to_string(), "01:01:01");
     }
 
     #[test]
     fn test_time_display() {
-        let time = Time::from("5:30".to_string());
+        let time: Time = "5:30".parse().unwrap();
         assert_eq!(format!("{time}"), "00:05:30");
+        assert_eq!((-time).to_string(), "-00:05:30");
+    }
+
+    #[test]
+    fn test_time_serializes_string_and_seconds() {
+        let time = Time::from_millis(90_250);
+
+        assert_eq!(
+            serde_json::to_string(&time).unwrap(),
+            r#"{"as_string":"00:01:30.250","as_secs":90.25}"#
+        );
     }
 
     #[test]
@@ -211,11 +382,15 @@ mod tests {
     }
 
     #[test]
-    fn test_track_from_option() {
-        let elapsed = Duration::from_secs(60);
-        let total = Duration::from_secs(300);
-        let track = Track::from(Some((elapsed, total)));
-        assert_eq!(track.elapsed.as_string, "00:01:00");
-        assert_eq!(track.total.as_string, "00:05:00");
+    #[allow(clippy::duration_suboptimal_units)]
+    fn test_track_from_status() {
+        let status = mpd::Status {
+            time: Some((Duration::from_secs(60), Duration::from_secs(300))),
+            elapsed: Some(Duration::from_millis(60_500)),
+            ..Default::default()
+        };
+        let track = Track::from(&status);
+        assert_eq!(track.elapsed.to_string(), "00:01:00.500");
+        assert_eq!(track.total.to_string(), "00:05:00");
     }
 }
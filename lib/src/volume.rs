@@ -0,0 +1,209 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use eyre::eyre;
+use serde::Serialize;
+
+use crate::{dirs, OnOff};
+
+const MAX: u8 = 100;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Volume {
+    /// `None` when no output has a mixer (MPD reports -1)
+    pub volume: Option<u8>,
+    pub muted: OnOff,
+}
+
+impl fmt::Display for Volume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.volume {
+            Some(volume) => write!(f, "volume={volume}")?,
+            None => write!(f, "volume=n/a (no mixer)")?,
+        }
+
+        write!(f, "\nmuted={}", self.muted)
+    }
+}
+
+/// A requested volume: an absolute level or a step up or down
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Change {
+    To(u8),
+    Up(u8),
+    Down(u8),
+}
+
+impl FromStr for Change {
+    type Err = eyre::Report;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            eyre!("invalid volume: {input} (expected 0-100, +<num> or -<num>)")
+        };
+        let level = |digits: &str| -> eyre::Result<u8> {
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit())
+            {
+                return Err(invalid());
+            }
+
+            match digits.parse::<u8>() {
+                Ok(level) if level <= MAX => Ok(level),
+                _ => Err(eyre!("volume must be between 0 and {MAX}: {input}")),
+            }
+        };
+
+        match input.trim() {
+            step if step.starts_with('+') => Ok(Change::Up(level(&step[1..])?)),
+            step if step.starts_with('-') => {
+                Ok(Change::Down(level(&step[1..])?))
+            }
+            absolute => Ok(Change::To(level(absolute)?)),
+        }
+    }
+}
+
+impl Change {
+    /// The resulting level, steps being clamped to 0-100
+    pub(crate) fn apply(self, current: u8) -> u8 {
+        match self {
+            Change::To(level) => level,
+            Change::Up(step) => current.saturating_add(step).min(MAX),
+            Change::Down(step) => current.saturating_sub(step),
+        }
+    }
+}
+
+/// The level `elapsed` into a linear ramp from `from` to `to` lasting `over`
+#[must_use]
+pub fn ramp(from: u8, to: u8, elapsed: Duration, over: Duration) -> u8 {
+    if elapsed >= over {
+        return to;
+    }
+
+    let millis = |duration: Duration| {
+        i128::try_from(duration.as_millis()).unwrap_or(i128::MAX)
+    };
+    let (start, end) = (i128::from(from), i128::from(to));
+    let level = start + (end - start) * millis(elapsed) / millis(over);
+
+    u8::try_from(level).unwrap_or(to)
+}
+
+/// Where the level to restore on unmute is kept for the server at `address`
+pub(crate) fn mute_file(address: &str) -> PathBuf {
+    let name = address.replace([':', '/'], "_");
+
+    dirs::state_dir().join(format!("muted-{name}"))
+}
+
+pub(crate) fn muted_level(file: &Path) -> Option<u8> {
+    fs::read_to_string(file).ok()?.trim().parse().ok()
+}
+
+pub(crate) fn remember(file: &Path, level: u8) -> eyre::Result<()> {
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(fs::write(file, level.to_string())?)
+}
+
+pub(crate) fn forget(file: &Path) -> eyre::Result<()> {
+    if file.exists() {
+        fs::remove_file(file)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{parse_line, FakeServer};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_parse_change() {
+        assert_eq!("30".parse::<Change>().unwrap(), Change::To(30));
+        assert_eq!("+5".parse::<Change>().unwrap(), Change::Up(5));
+        assert_eq!("-10".parse::<Change>().unwrap(), Change::Down(10));
+        assert!("3O".parse::<Change>().is_err());
+        assert!("+".parse::<Change>().is_err());
+        assert!("--5".parse::<Change>().is_err());
+        assert_eq!(
+            "101".parse::<Change>().unwrap_err().to_string(),
+            "volume must be between 0 and 100: 101"
+        );
+    }
+
+    #[test]
+    fn test_steps_are_clamped() {
+        assert_eq!(Change::Up(10).apply(95), 100);
+        assert_eq!(Change::Down(10).apply(5), 0);
+        assert_eq!(Change::To(30).apply(80), 30);
+    }
+
+    #[test]
+    fn test_ramp() {
+        let secs = Duration::from_secs;
+        assert_eq!(ramp(10, 40, secs(0), secs(300)), 10);
+        assert_eq!(ramp(10, 40, secs(150), secs(300)), 25);
+        assert_eq!(ramp(10, 40, secs(400), secs(300)), 40);
+        assert_eq!(ramp(80, 0, secs(30), secs(60)), 40);
+        assert_eq!(ramp(80, 0, Duration::ZERO, Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn test_remember_muted_level() {
+        let dir = tempdir::TempDir::new("volume").unwrap();
+        let file = dir.path().join("muted");
+
+        assert_eq!(muted_level(&file), None);
+        remember(&file, 42).unwrap();
+        assert_eq!(muted_level(&file), Some(42));
+        forget(&file).unwrap();
+        assert_eq!(muted_level(&file), None);
+    }
+
+    #[test]
+    fn test_set_volume_reports_result_and_mixerless() {
+        let level = Mutex::new("40".to_string());
+        let server = FakeServer::start(move |line| {
+            let mut level = level.lock().unwrap();
+            match parse_line(line) {
+                (command, args) if command == "setvol" => {
+                    level.clone_from(&args[0]);
+                    Ok(String::new())
+                }
+                (command, _) if command == "getvol" => {
+                    Ok(format!("volume: {level}\n"))
+                }
+                _ => Ok(String::new()),
+            }
+        });
+        let mut client = server.client();
+
+        assert_eq!(
+            client.set_volume("+5").unwrap().unwrap(),
+            r#"{"volume":45,"muted":"Off"}"#
+        );
+        assert!(server.commands().contains(&r#"setvol "45""#.to_string()));
+        assert!(client.set_volume("loud").is_err());
+
+        let mixerless = FakeServer::start(|line| match line {
+            "status" => Ok("volume: -1\nstate: stop\n".to_string()),
+            _ => Ok(String::new()),
+        });
+        let mut client = mixerless.client();
+
+        assert_eq!(
+            client.volume().unwrap().unwrap(),
+            r#"{"volume":null,"muted":"Off"}"#
+        );
+        assert!(client.set_volume("50").is_err());
+    }
+}